@@ -0,0 +1,198 @@
+use ::{Vertex, BuildRenderable, RenderableIndices, Mesh};
+use ::glium::{VertexBuffer, Display};
+use ::nalgebra::{Vec3, Norm, cross};
+
+/// A bicubic Bezier patch defined by a 4x4 grid of control points stored
+/// row by row. Rows are indexed by u and columns by v.
+#[derive(Copy, Clone)]
+pub struct BezierPatch {
+    pub control : [Vec3<f32>; 16]
+}
+
+fn bernstein(t: f32) -> [f32; 4] {
+    let s = 1.0 - t;
+    [s*s*s, 3.0*t*s*s, 3.0*t*t*s, t*t*t]
+}
+
+fn bernstein_derivative(t: f32) -> [f32; 4] {
+    let s = 1.0 - t;
+    [-3.0*s*s, 3.0*s*s - 6.0*t*s, 6.0*t*s - 3.0*t*t, 3.0*t*t]
+}
+
+impl BezierPatch {
+    pub fn new(control: [Vec3<f32>; 16]) -> BezierPatch {
+        BezierPatch { control : control }
+    }
+
+    fn blend(&self, bu: &[f32; 4], bv: &[f32; 4]) -> Vec3<f32> {
+        let mut p = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..4 {
+            for j in 0..4 {
+                p = p + self.control[i*4 + j] * (bu[i] * bv[j]);
+            }
+        }
+        p
+    }
+
+    /// Returns the point on the patch at (u, v).
+    pub fn position(&self, u: f32, v: f32) -> Vec3<f32> {
+        self.blend(&bernstein(u), &bernstein(v))
+    }
+
+    /// Returns the unit normal at (u, v), computed from the partial
+    /// derivatives of the patch. Where an edge of the patch collapses to a
+    /// point the normal is taken from just inside the patch.
+    pub fn normal(&self, u: f32, v: f32) -> Vec3<f32> {
+        let du = self.blend(&bernstein_derivative(u), &bernstein(v));
+        let dv = self.blend(&bernstein(u), &bernstein_derivative(v));
+        let n  = cross(&dv, &du);
+
+        if n.sqnorm() > 1.0e-12 {
+            n.normalize()
+        }
+        else {
+            let eps = 1.0e-3;
+            let (nu, nv) = (
+                if u < 0.5 { u + eps } else { u - eps },
+                if v < 0.5 { v + eps } else { v - eps }
+            );
+            let du = self.blend(&bernstein_derivative(nu), &bernstein(nv));
+            let dv = self.blend(&bernstein(nu), &bernstein_derivative(nv));
+            cross(&dv, &du).normalize()
+        }
+    }
+
+    /// Appends a (resolution+1) x (resolution+1) grid of vertices sampled
+    /// from the patch, and its triangles, to mesh.
+    pub fn tessellate(&self, resolution: u32, mesh: &mut Mesh) {
+        let base   = mesh.vertices.len() as u32;
+        let stride = resolution + 1;
+        let step   = 1.0 / (resolution as f32);
+
+        for i in 0..stride {
+            let u = (i as f32) * step;
+            for j in 0..stride {
+                let v = (j as f32) * step;
+                let p = self.position(u, v);
+                let n = self.normal(u, v);
+
+                mesh.push_vertex(
+                    Vertex::new([p.x, p.y, p.z], [n.x, n.y, n.z], [v, u])
+                );
+            }
+        }
+
+        for i in 0..resolution {
+            for j in 0..resolution {
+                let a = base + i * stride + j;
+                let b = a + 1;
+                let d = a + stride;
+                let c = d + 1;
+
+                // collapsed edges produce zero area triangles, skip them
+                if (mesh.position(a) - mesh.position(b)).sqnorm() > 1.0e-12 {
+                    mesh.push_triangle(a, b, d);
+                }
+                if (mesh.position(c) - mesh.position(d)).sqnorm() > 1.0e-12 {
+                    mesh.push_triangle(b, c, d);
+                }
+            }
+        }
+    }
+}
+
+/// A surface made of several Bezier patches tessellated at a uniform
+/// resolution.
+pub struct BezierSurface {
+    pub patches : Vec<BezierPatch>,
+    mesh        : Mesh
+}
+
+impl BezierSurface {
+    pub fn new(patches: Vec<BezierPatch>, resolution: u32) -> BezierSurface {
+        let mut mesh = Mesh::new();
+        for patch in &patches {
+            patch.tessellate(resolution, &mut mesh);
+        }
+
+        BezierSurface { patches : patches, mesh : mesh }
+    }
+
+    pub fn mesh(&self) -> &Mesh {
+        &self.mesh
+    }
+}
+
+impl BuildRenderable for BezierSurface {
+    fn get_vertex_array(&self, display: &Display) -> VertexBuffer<Vertex> {
+        self.mesh.get_vertex_array(display)
+    }
+
+    fn get_indices(&self, display: &Display) -> RenderableIndices {
+        self.mesh.get_indices(display)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::nalgebra::dot;
+
+    fn patch_from<F: Fn(f32, f32) -> Vec3<f32>>(point: F) -> BezierPatch {
+        let mut control = [Vec3::new(0.0, 0.0, 0.0); 16];
+        for i in 0..4 {
+            for j in 0..4 {
+                control[i*4 + j] = point(i as f32 / 3.0, j as f32 / 3.0);
+            }
+        }
+        BezierPatch::new(control)
+    }
+
+    fn wavy_patch() -> BezierPatch {
+        patch_from(|u, v| Vec3::new(v, (3.0 * u).sin() * (2.0 * v).cos(), u))
+    }
+
+    /// Normal from central differences of the position.
+    fn numeric_normal(patch: &BezierPatch, u: f32, v: f32) -> Vec3<f32> {
+        let h  = 1.0e-3;
+        let du = patch.position(u + h, v) - patch.position(u - h, v);
+        let dv = patch.position(u, v + h) - patch.position(u, v - h);
+        cross(&dv, &du).normalize()
+    }
+
+    #[test]
+    fn corners_interpolate_the_control_points() {
+        let patch = wavy_patch();
+        for &(u, v, index) in &[(0.0, 0.0, 0), (0.0, 1.0, 3), (1.0, 0.0, 12), (1.0, 1.0, 15)] {
+            let p = patch.position(u, v);
+            assert!((p - patch.control[index]).sqnorm() < 1.0e-10,
+                    "corner {} {} is {:?}", u, v, p);
+        }
+    }
+
+    #[test]
+    fn normals_match_finite_differences() {
+        let patch = wavy_patch();
+        for &(u, v) in &[(0.25, 0.25), (0.5, 0.7), (0.8, 0.1), (0.1, 0.9)] {
+            let n = patch.normal(u, v);
+            assert!((n.norm() - 1.0).abs() < 1.0e-4);
+            assert!(dot(&n, &numeric_normal(&patch, u, v)) > 0.999,
+                    "normal at {} {} is {:?}", u, v, n);
+        }
+    }
+
+    #[test]
+    fn collapsed_edges_take_the_normal_from_inside() {
+        // the u = 0 row collapses to a point like the top of the lid
+        let patch = patch_from(|u, v| Vec3::new(u * (2.0 * v - 1.0), 0.5 * u * u, u));
+
+        for &v in &[0.0, 0.3, 0.5, 1.0] {
+            let n = patch.normal(0.0, v);
+            assert!(n.x.is_finite() && n.y.is_finite() && n.z.is_finite());
+            assert!((n.norm() - 1.0).abs() < 1.0e-4);
+
+            let inside = numeric_normal(&patch, 0.01, v.max(0.01).min(0.99));
+            assert!(dot(&n, &inside) > 0.99, "normal at 0 {} is {:?}", v, n);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+/**
+ * This recipe synthesizes an ocean from a wind driven wave spectrum with
+ * FFTs on the CPU each frame, and displaces a planar mesh by the uploaded
+ * maps. The mesh covers several tiles of the ocean. Tab toggles wireframe,
+ * Return switches between the Phillips and JONSWAP spectra.
+ **/
+
+extern crate glium;
+extern crate glutin;
+extern crate glCookbook;
+extern crate nalgebra;
+
+use glutin::{Event, ElementState, VirtualKeyCode};
+use glium::{DisplayBuild, Surface};
+use nalgebra::Iso3;
+use glCookbook::{Controller, FreeCamera, Grid, RenderableObj, Ocean, OceanSpectrum, OceanRenderer};
+
+// Program entry point
+fn main() {
+    let display = glutin::WindowBuilder::new()
+        .with_dimensions(1366, 768)
+        .with_multisampling(4)
+        .with_depth_buffer(24)
+        .with_vsync()
+        .with_title("fft ocean".to_string())
+        .build_glium()
+        .unwrap();
+
+    let mut ocean = Ocean::new(11, 128, 128.0);
+    ocean.wind_direction = (0.8, 0.6);
+    ocean.regenerate();
+
+    let water        = RenderableObj::new(&Grid::new(384.0, 384.0, 384, 384), &display);
+    let mut renderer = OceanRenderer::new(&display, &ocean);
+
+    let mut time   = 0.0f32;
+    let mut camera = FreeCamera::new(1.0, 75.0, 1.0, 1000.0);
+    camera.pos.y   = 20.0;
+    camera.pos.z   = 100.0;
+
+    let mut controller = Controller::new();
+    controller.rot_speed  = 1.0/40.0;
+    controller.move_speed = 0.5;
+
+    'mainLoop : loop {
+        time += 1.0/60.0;
+
+        ocean.update(time);
+        renderer.upload(&ocean);
+
+        let mut target = display.draw();
+        target.clear_color_and_depth((0.55, 0.7, 0.85, 1.0), 1.0);
+        renderer.draw(
+            &mut target, &water, &camera.projection.to_mat(),
+            &camera.get_view_transform(), &Iso3::new(nalgebra::zero(), nalgebra::zero())
+        );
+        target.finish();
+
+        for event in display.poll_events() {
+            match event {
+                Event::Closed => break 'mainLoop,
+                Event::Resized(w, h) => {
+                    camera.projection.set_aspect((w as f32)/(h as f32));
+                },
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Tab)) => {
+                    renderer.wire = !renderer.wire;
+                },
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Return)) => {
+                    ocean.spectrum = match ocean.spectrum {
+                        OceanSpectrum::Phillips { .. } =>
+                            OceanSpectrum::Jonswap { fetch : 100000.0, gamma : 3.3 },
+                        OceanSpectrum::Jonswap { .. } =>
+                            OceanSpectrum::Phillips { amplitude : 0.0081 }
+                    };
+                    ocean.regenerate();
+                },
+                _ => ()
+            }
+            controller.process_event(&event);
+        }
+        controller.update(&mut camera, &display);
+    }
+}
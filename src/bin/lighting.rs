@@ -1,6 +1,8 @@
 /**
- * This recipe uses the vertex shader to create a ripple effect on a planar
- * mesh.
+ * This recipe lights a ball and a plane with a light circling them, under a
+ * sky the ball reflects. Give six PNG images (+x, -x, +y, -y, +z, -z) for a
+ * cubemap sky, one for an equirectangular panorama, or none for a
+ * procedural gradient.
  **/
 
 #[macro_use]
@@ -17,7 +19,7 @@ use glCookbook::{
     Grid, FreeCamera,
     RenderableObj,
     Controller, IsoSphere, LightingRenderer,
-    NormalRenderer
+    NormalRenderer, SkyboxRenderer, Environment
 };
 use nalgebra::{Vec3, Mat4, Iso3, Transformation};
 use num::Float;
@@ -38,6 +40,14 @@ fn main() {
     let ball_model =
         nalgebra::Iso3::new(Vec3::new(0.0, 2.0, 0.0), nalgebra::zero());
 
+    let args: Vec<String> = ::std::env::args().skip(1).collect();
+    let environment = match args.len() {
+        6 => Environment::from_faces(&display, &args[..]).unwrap(),
+        1 => Environment::from_equirectangular(&display, &args[0]).unwrap(),
+        _ => Environment::gradient(&display)
+    };
+    let skybox_renderer = SkyboxRenderer::new(&display);
+
     let mut lighting_renderer = LightingRenderer::new(&display);
     let normal_renderer       = NormalRenderer::new(&display);
     let mut camera            = FreeCamera::new(1.0, 75.0, 1.0, 500.0);
@@ -61,16 +71,20 @@ fn main() {
 
         let mut target = display.draw();
         target.clear_color_and_depth((0.02, 0.02, 0.05, 1.0), 1.0);
+        skybox_renderer.draw(
+            &mut target, &environment, &camera.projection.to_mat(), &camera.get_view_transform()
+        );
 
-
+        lighting_renderer.reflectivity = 0.0;
         lighting_renderer.draw(
             &mut target, &grid, &camera.projection.to_mat(),
             &camera.get_view_transform(), &Iso3::new(nalgebra::zero(), nalgebra::zero())
         );
 
-        lighting_renderer.draw(
+        lighting_renderer.reflectivity = 0.6;
+        lighting_renderer.draw_in_environment(
             &mut target, &ball, &camera.projection.to_mat(),
-            &camera.get_view_transform(), &ball_model
+            &camera.get_view_transform(), &ball_model, &environment
         );
 
         normal_renderer.draw(
@@ -0,0 +1,99 @@
+/**
+ * This recipe displaces a planar mesh with a sum of Gerstner waves on the
+ * GPU and shades it as water. A few buoys are floated on the same waves
+ * evaluated on the CPU. Tab toggles wireframe.
+ **/
+
+extern crate glium;
+extern crate glutin;
+extern crate glCookbook;
+extern crate nalgebra;
+
+use glutin::{Event, ElementState, VirtualKeyCode};
+use glium::{DisplayBuild, Surface};
+use nalgebra::{Iso3, Vec3, Norm, Cross};
+use glCookbook::{
+    Controller, FreeCamera, Grid, IsoSphere, RenderableObj, LightingRenderer,
+    GerstnerRenderer, GerstnerSurface
+};
+
+// Program entry point
+fn main() {
+    let display = glutin::WindowBuilder::new()
+        .with_dimensions(1366, 768)
+        .with_multisampling(4)
+        .with_depth_buffer(24)
+        .with_vsync()
+        .with_title("ocean".to_string())
+        .build_glium()
+        .unwrap();
+
+    let water        = RenderableObj::new(&Grid::new(200.0, 200.0, 200, 200), &display);
+    let mut renderer = GerstnerRenderer::new(&display, GerstnerSurface::default_sea(0.3));
+
+    let buoy              = RenderableObj::new(&IsoSphere::new(2), &display);
+    let mut buoy_renderer = LightingRenderer::new(&display);
+    buoy_renderer.light_position = Vec3::new(0.0, 1000.0, 0.0);
+    buoy_renderer.diffuse_color  = Vec3::new(0.9, 0.4, 0.1);
+    let buoys = [(0.0f32, 0.0f32), (15.0, -10.0), (-20.0, 5.0), (8.0, 25.0)];
+
+    let mut time   = 0.0f32;
+    let mut camera = FreeCamera::new(1.0, 75.0, 1.0, 500.0);
+    camera.pos.y   = 10.0;
+    camera.pos.z   = 40.0;
+
+    let mut controller = Controller::new();
+    controller.rot_speed  = 1.0/40.0;
+    controller.move_speed = 0.2;
+
+    'mainLoop : loop {
+        time += 1.0/60.0;
+
+        let mut target = display.draw();
+        target.clear_color_and_depth((0.55, 0.7, 0.85, 1.0), 1.0);
+
+        let proj = camera.projection.to_mat();
+        let view = camera.get_view_transform();
+
+        renderer.draw(
+            &mut target, &water, &proj, &view,
+            &Iso3::new(nalgebra::zero(), nalgebra::zero()), time
+        );
+
+        // each buoy rides the surface and tilts with it
+        for &(x, z) in buoys.iter() {
+            let surface  = renderer.surface();
+            let (rx, rz) = surface.resting_point(x, z, time);
+            let height   = surface.displacement(rx, rz, time).y;
+            let normal   = surface.normal(rx, rz, time);
+
+            let axis  = Vec3::y().cross(&normal);
+            let tilt  = if axis.norm() > 1.0e-6 {
+                axis.normalize() * normal.y.min(1.0).acos()
+            } else {
+                nalgebra::zero()
+            };
+
+            buoy_renderer.draw(
+                &mut target, &buoy, &proj, &view, &Iso3::new(Vec3::new(x, height, z), tilt)
+            );
+        }
+
+        target.finish();
+
+        for event in display.poll_events() {
+            match event {
+                Event::Closed => break 'mainLoop,
+                Event::Resized(w, h) => {
+                    camera.projection.set_aspect((w as f32)/(h as f32));
+                },
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Tab)) => {
+                    renderer.wire = !renderer.wire;
+                },
+                _ => ()
+            }
+            controller.process_event(&event);
+        }
+        controller.update(&mut camera, &display);
+    }
+}
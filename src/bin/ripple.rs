@@ -1,45 +1,39 @@
 /**
  * This recipe uses the vertex shader to create a ripple effect on a planar
+ * mesh. Clicking starts a new ripple where the center of the view meets the
  * mesh.
  **/
 
-#[macro_use]
 extern crate glium;
 extern crate glutin;
 extern crate glCookbook;
 extern crate nalgebra;
 
-use glutin::{Event};
-use glium::{DisplayBuild, Surface, Display};
-use nalgebra::{Iso3, Vec3, ToHomogeneous, Rotation};
-use glCookbook::{Grid, FreeCamera};
+use glutin::{Event, ElementState, MouseButton};
+use glium::{DisplayBuild, Surface};
+use nalgebra::{Iso3, Vec3, Rotation, Transform, Rotate};
+use glCookbook::{Grid, FreeCamera, RenderableObj, RippleRenderer};
 
 // Program entry point
 fn main() {
     let display = glutin::WindowBuilder::new()
         .with_dimensions(1366, 768)
         .with_multisampling(4)
+        .with_depth_buffer(24)
         .with_vsync()
         .with_title("tetra".to_string())
         .build_glium()
         .unwrap();
 
-    let grid       = Grid::new(20.0, 20.0, 60, 60);
-    let vertex_buf = glium::VertexBuffer::new(&display, grid.get_vertices());
-    let indices    = glium::index::IndexBuffer::new(
-        &display, glium::index::TrianglesList(grid.indices)
-    );
-
-    let program = create_shader_program(&display);
+    let grid         = RenderableObj::new(&Grid::new(20.0, 20.0, 60, 60), &display);
+    let mut renderer = RippleRenderer::new(&display);
+    renderer.spawn(&display, 0.0, 0.0, 0.0);
 
     let mut time   = 0.0f32;
     let mut model  = Iso3::new(nalgebra::zero(), nalgebra::zero());
     let mut camera = FreeCamera::new(1.0, 75.0, 1.0, 500.0);
     camera.pos.z   = 30.0;
 
-    let mut draw_params: glium::DrawParameters = std::default::Default::default();
-    draw_params.polygon_mode = glium::PolygonMode::Line;
-
     let (mut center_x, mut center_y): (i32, i32) = (0, 0);
     let (mut rx, mut ry): (f32, f32) = (0.0, 0.0);
 
@@ -48,19 +42,13 @@ fn main() {
 
         model.rotation = model.rotation.append_rotation(&(Vec3::y() * rx));
         model.rotation = model.rotation.append_rotation(&(Vec3::x() * ry));
-        let mvp = camera.projection.to_mat() * camera.get_view_matrix() * model.to_homogeneous();
-
-        let uniforms = uniform!(
-            MVP  : mvp,
-            time : time
-        );
 
         let mut target = display.draw();
-        target.clear_color(0.02, 0.02, 0.05, 1.0);
-        target.draw(
-            &vertex_buf, &indices, &program, &uniforms,
-            &draw_params
-        ).unwrap();
+        target.clear_color_and_depth((0.02, 0.02, 0.05, 1.0), 1.0);
+        renderer.draw(
+            &mut target, &grid, &camera.projection.to_mat(),
+            &camera.get_view_transform(), &model, time
+        );
         target.finish();
 
         for event in display.poll_events() {
@@ -82,6 +70,15 @@ fn main() {
                         rx = 0.0;
                         ry = 0.0;
                     }
+                },
+                Event::MouseInput(ElementState::Pressed, MouseButton::Left) => {
+                    // intersect the view ray with the mesh's plane in model space
+                    let origin = model.inv_transform(&camera.pos);
+                    let dir    = model.inv_rotate(&camera.look_direction());
+                    if dir.y != 0.0 && -origin.y / dir.y > 0.0 {
+                        let hit = origin + dir * (-origin.y / dir.y);
+                        renderer.spawn(&display, hit.x, hit.z, time);
+                    }
                 }
                 _ => ()
             }
@@ -90,36 +87,3 @@ fn main() {
             .set_cursor_position(center_x, center_y);
     }
 }
-
-fn create_shader_program(display: &Display) -> glium::Program {
-    let vertex_shader_src = r#"
-        #version 330
-        in vec3 position;
-
-        uniform mat4 MVP;
-        uniform float time;
-
-        const float amplitude = 2.0;
-        const float frequency = 0.5;
-        const float PI = 3.14159;
-
-        void main() {
-            float distance = length(position * frequency);
-            float y = amplitude*sin(-PI*distance+time);
-            gl_Position = MVP * vec4(position.x, y, position.z, 1.0);
-        }
-    "#;
-
-    let fragment_shader_src = r#"
-        #version 330
-        out vec4 vFragColor;
-        void main() {
-            vFragColor = vec4(1.0);
-        }
-    "#;
-
-    glium::Program::from_source(
-        display, vertex_shader_src, fragment_shader_src, None
-    ).unwrap()
-}
-
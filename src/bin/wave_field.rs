@@ -0,0 +1,128 @@
+/**
+ * This recipe simulates water with the wave equation, on the CPU or the
+ * GPU. A point circling the pool keeps disturbing it and clicking drops a
+ * stone where the center of the view meets the water. Return switches
+ * between the CPU and GPU solvers, B between reflecting and absorbing
+ * edges, Tab toggles wireframe.
+ **/
+
+extern crate glium;
+extern crate glutin;
+extern crate glCookbook;
+extern crate nalgebra;
+
+use glutin::{Event, ElementState, VirtualKeyCode, MouseButton};
+use glium::{DisplayBuild, Surface};
+use nalgebra::{Iso3, Vec3};
+use glCookbook::{
+    Controller, FreeCamera, Grid, RenderableObj, LightingRenderer,
+    WaveField, GpuWaveField, WaveFieldRenderer, Boundary
+};
+
+const SAMPLES : usize = 128;
+const SIZE    : f32   = 20.0;
+
+// Program entry point
+fn main() {
+    let display = glutin::WindowBuilder::new()
+        .with_dimensions(1366, 768)
+        .with_multisampling(4)
+        .with_depth_buffer(24)
+        .with_vsync()
+        .with_title("wave field".to_string())
+        .build_glium()
+        .unwrap();
+
+    let mut field     = WaveField::new(SAMPLES, SAMPLES);
+    let mut gpu_field = GpuWaveField::new(&display, SAMPLES as u32, SAMPLES as u32);
+    let mut use_gpu   = false;
+
+    let flat_grid = RenderableObj::new(
+        &Grid::new(SIZE, SIZE, SAMPLES as u32, SAMPLES as u32), &display
+    );
+    let mut gpu_renderer = WaveFieldRenderer::new(&display);
+    let mut cpu_renderer = LightingRenderer::new(&display);
+    cpu_renderer.light_position = Vec3::new(0.0, 100.0, 0.0);
+    cpu_renderer.diffuse_color  = Vec3::new(0.1, 0.35, 0.5);
+
+    let dt         = 1.0/60.0;
+    let mut time   = 0.0f32;
+    let mut camera = FreeCamera::new(1.0, 75.0, 1.0, 500.0);
+    camera.pos.y   = 15.0;
+    camera.pos.z   = 30.0;
+
+    let mut controller = Controller::new();
+    controller.rot_speed  = 1.0/40.0;
+    controller.move_speed = 0.2;
+
+    // world position on the water to sample position
+    let to_sample = |v: f32| (v / SIZE + 1.0) * 0.5 * (SAMPLES - 1) as f32;
+
+    'mainLoop : loop {
+        time += dt;
+
+        let (bx, bz) = (to_sample(12.0 * time.cos()), to_sample(12.0 * time.sin()));
+        if use_gpu {
+            gpu_field.disturb(bx, bz, 3.0, 0.02);
+            gpu_field.step(dt);
+        } else {
+            field.disturb(bx, bz, 3.0, 0.02);
+            field.step(dt);
+        }
+
+        let mut target = display.draw();
+        target.clear_color_and_depth((0.02, 0.02, 0.05, 1.0), 1.0);
+
+        let proj  = camera.projection.to_mat();
+        let view  = camera.get_view_transform();
+        let model = Iso3::new(nalgebra::zero(), nalgebra::zero());
+        if use_gpu {
+            gpu_renderer.draw(
+                &mut target, &flat_grid, SIZE, SIZE, gpu_field.heights(), &proj, &view, &model
+            );
+        } else {
+            let water = RenderableObj::new(&field.to_grid(SIZE, SIZE), &display);
+            cpu_renderer.draw(&mut target, &water, &proj, &view, &model);
+        }
+        target.finish();
+
+        for event in display.poll_events() {
+            match event {
+                Event::Closed => break 'mainLoop,
+                Event::Resized(w, h) => {
+                    camera.projection.set_aspect((w as f32)/(h as f32));
+                },
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Return)) => {
+                    use_gpu = !use_gpu;
+                    field.clear();
+                    gpu_field.clear();
+                },
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::B)) => {
+                    let boundary = match field.boundary {
+                        Boundary::Reflect => Boundary::Absorb,
+                        Boundary::Absorb  => Boundary::Reflect
+                    };
+                    field.boundary     = boundary;
+                    gpu_field.boundary = boundary;
+                },
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VirtualKeyCode::Tab)) => {
+                    gpu_renderer.wire = !gpu_renderer.wire;
+                    cpu_renderer.wire = !cpu_renderer.wire;
+                },
+                Event::MouseInput(ElementState::Pressed, MouseButton::Left) => {
+                    // intersect the view ray with the resting water plane
+                    let dir = camera.look_direction();
+                    if dir.y < 0.0 {
+                        let hit = camera.pos + dir * (-camera.pos.y / dir.y);
+                        let (sx, sz) = (to_sample(hit.x), to_sample(hit.z));
+                        field.disturb(sx, sz, 4.0, -1.0);
+                        gpu_field.disturb(sx, sz, 4.0, -1.0);
+                    }
+                },
+                _ => ()
+            }
+            controller.process_event(&event);
+        }
+        controller.update(&mut camera, &display);
+    }
+}
@@ -48,6 +48,11 @@ impl FreeCamera {
         ::nalgebra::inv(&view).unwrap()
     }
 
+    /// Returns the unit vector the camera looks along.
+    pub fn look_direction(&self) -> Vec3<f32> {
+        self.look
+    }
+
     /// Moves the camera in the direction of the look vector.
     pub fn advance(&mut self, dist: f32) {
         self.pos = self.pos + self.look * dist;
@@ -58,6 +63,16 @@ impl FreeCamera {
         self.pos = self.pos + self.right * dist;
     }
 
+    /// Moves the camera along the ground, forward in the direction it looks
+    /// and sideways along the right vector, ignoring their vertical parts.
+    pub fn walk(&mut self, forward: f32, side: f32) {
+        let flat = |v: Vec3<f32>| {
+            let length = (v.x*v.x + v.z*v.z).sqrt();
+            if length > 0.0 { Vec3::new(v.x / length, 0.0, v.z / length) } else { ::nalgebra::zero() }
+        };
+        self.pos = self.pos + flat(self.look) * forward + flat(self.right) * side;
+    }
+
     /// Rotates the look vector around the right vector, to look up/down
     pub fn rotate_up(&mut self, angle_in_degrees: f32) {
         let angle = angle_in_degrees * 3.1415 / 180.0;
@@ -0,0 +1,314 @@
+//! Erosion simulations run on a heightmap on the CPU. Results only depend
+//! on the parameters and seed, so the same terrain is produced every run.
+
+use ::Heightmap;
+use ::random::Rng;
+
+/// Particle based hydraulic erosion. Droplets of water run downhill picking
+/// up sediment where they speed up and depositing it where they slow down.
+#[derive(Clone)]
+pub struct HydraulicErosion {
+    pub seed         : u32,
+    /// Number of droplets simulated.
+    pub droplets     : u32,
+    /// Steps a droplet runs for before it is dropped.
+    pub lifetime     : u32,
+    /// How much a droplet keeps its direction instead of following the
+    /// slope, from 0 to 1.
+    pub inertia      : f32,
+    /// Sediment carried per unit of speed, water and drop in height.
+    pub capacity     : f32,
+    /// Lowest capacity, so droplets on flat ground still erode.
+    pub min_capacity : f32,
+    /// Fraction of the excess sediment deposited each step.
+    pub deposition   : f32,
+    /// Fraction of the free capacity eroded each step.
+    pub erosion      : f32,
+    /// Fraction of water lost each step.
+    pub evaporation  : f32,
+    pub gravity      : f32,
+    /// Radius, in samples, of the area eroded around a droplet.
+    pub radius       : f32
+}
+
+impl HydraulicErosion {
+    pub fn new(seed: u32, droplets: u32) -> HydraulicErosion {
+        HydraulicErosion {
+            seed         : seed,
+            droplets     : droplets,
+            lifetime     : 30,
+            inertia      : 0.05,
+            capacity     : 4.0,
+            min_capacity : 0.01,
+            deposition   : 0.3,
+            erosion      : 0.3,
+            evaporation  : 0.01,
+            gravity      : 4.0,
+            radius       : 3.0
+        }
+    }
+
+    pub fn erode(&self, heightmap: &mut Heightmap) {
+        let (width, depth) = (heightmap.width(), heightmap.depth());
+        if width < 2 || depth < 2 {
+            return;
+        }
+
+        let mut rng = Rng::new(self.seed);
+        let max_x   = (width - 1) as f32;
+        let max_z   = (depth - 1) as f32;
+
+        for _ in 0..self.droplets {
+            let (mut x, mut z)   = (rng.range(0.0, max_x), rng.range(0.0, max_z));
+            let (mut dx, mut dz) = (0.0f32, 0.0f32);
+            let mut speed    = 1.0f32;
+            let mut water    = 1.0f32;
+            let mut sediment = 0.0f32;
+
+            for _ in 0..self.lifetime {
+                // droplets stay inside the map so no samples are addressed
+                // outside of it
+                let height   = heightmap.sample(x, z);
+                let (gx, gz) = heightmap.gradient(x, z);
+
+                dx = dx * self.inertia - gx * (1.0 - self.inertia);
+                dz = dz * self.inertia - gz * (1.0 - self.inertia);
+                let length = (dx*dx + dz*dz).sqrt();
+                if length < 1.0e-6 {
+                    break;
+                }
+                dx /= length;
+                dz /= length;
+
+                let (nx, nz) = (x + dx, z + dz);
+                if nx < 0.0 || nz < 0.0 || nx >= max_x || nz >= max_z {
+                    break;
+                }
+
+                let delta    = heightmap.sample(nx, nz) - height;
+                let capacity = (-delta * speed * water * self.capacity).max(self.min_capacity);
+
+                if delta > 0.0 || sediment > capacity {
+                    // fill the pit climbed out of, or drop the excess
+                    let amount = if delta > 0.0 {
+                        delta.min(sediment)
+                    } else {
+                        (sediment - capacity) * self.deposition
+                    };
+                    sediment -= amount;
+                    deposit(heightmap, x, z, amount);
+                }
+                else {
+                    // never dig below the point the droplet moves to
+                    let amount = ((capacity - sediment) * self.erosion).min(-delta);
+                    self.erode_around(heightmap, x, z, amount);
+                    sediment += amount;
+                }
+
+                speed = (speed * speed + delta * self.gravity).max(0.0).sqrt();
+                water *= 1.0 - self.evaporation;
+                x = nx;
+                z = nz;
+            }
+        }
+    }
+
+    /// Lowers the samples within radius of (x, z) by amount in total,
+    /// weighted towards the center.
+    fn erode_around(&self, heightmap: &mut Heightmap, x: f32, z: f32, amount: f32) {
+        let reach = self.radius.ceil() as i64;
+        let (cx, cz) = (x.round() as i64, z.round() as i64);
+
+        let mut weights = vec![];
+        let mut total   = 0.0;
+        for sz in cz - reach..cz + reach + 1 {
+            for sx in cx - reach..cx + reach + 1 {
+                if sx < 0 || sz < 0 ||
+                   sx >= heightmap.width() as i64 || sz >= heightmap.depth() as i64 {
+                    continue;
+                }
+                let distance = ((sx as f32 - x).powi(2) + (sz as f32 - z).powi(2)).sqrt();
+                let weight   = self.radius - distance;
+                if weight > 0.0 {
+                    weights.push((sx as usize, sz as usize, weight));
+                    total += weight;
+                }
+            }
+        }
+
+        for &(sx, sz, weight) in &weights {
+            let height = heightmap.get(sx, sz);
+            heightmap.set(sx, sz, height - amount * weight / total);
+        }
+    }
+}
+
+/// Adds amount to the four samples around (x, z), bilinearly weighted.
+fn deposit(heightmap: &mut Heightmap, x: f32, z: f32, amount: f32) {
+    let (x0, z0) = (x.floor() as usize, z.floor() as usize);
+    let (tx, tz) = (x - x0 as f32, z - z0 as f32);
+
+    let corners = [
+        (x0,     z0,     (1.0 - tx) * (1.0 - tz)),
+        (x0 + 1, z0,     tx * (1.0 - tz)),
+        (x0,     z0 + 1, (1.0 - tx) * tz),
+        (x0 + 1, z0 + 1, tx * tz)
+    ];
+    for &(cx, cz, weight) in corners.iter() {
+        let height = heightmap.get(cx, cz);
+        heightmap.set(cx, cz, height + amount * weight);
+    }
+}
+
+/// Thermal erosion, material slides from samples to lower neighbours
+/// wherever the drop between them is steeper than the talus angle.
+#[derive(Clone)]
+pub struct ThermalErosion {
+    pub iterations : u32,
+    /// Largest stable height difference between neighbouring samples.
+    pub talus      : f32,
+    /// Fraction of the excess moved each iteration, from 0 to 0.5.
+    pub rate       : f32
+}
+
+impl ThermalErosion {
+    pub fn new(iterations: u32, talus: f32) -> ThermalErosion {
+        ThermalErosion { iterations : iterations, talus : talus, rate : 0.5 }
+    }
+
+    pub fn erode(&self, heightmap: &mut Heightmap) {
+        let (width, depth) = (heightmap.width(), heightmap.depth());
+        let neighbours = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+        for _ in 0..self.iterations {
+            // gather every move first so the result does not depend on the
+            // order samples are visited in
+            let mut change = vec![0.0f32; width * depth];
+
+            for z in 0..depth {
+                for x in 0..width {
+                    let height = heightmap.get(x, z);
+                    let mut steepest = 0.0f32;
+                    let mut excess   = 0.0f32;
+                    let mut lower    = vec![];
+
+                    for &(ox, oz) in neighbours.iter() {
+                        let (nx, nz) = (x as i64 + ox, z as i64 + oz);
+                        if nx < 0 || nz < 0 || nx >= width as i64 || nz >= depth as i64 {
+                            continue;
+                        }
+                        let (nx, nz) = (nx as usize, nz as usize);
+                        let drop     = height - heightmap.get(nx, nz);
+                        if drop > self.talus {
+                            steepest = steepest.max(drop);
+                            excess  += drop - self.talus;
+                            lower.push((nx, nz, drop - self.talus));
+                        }
+                    }
+
+                    if lower.is_empty() {
+                        continue;
+                    }
+
+                    let moved = self.rate * (steepest - self.talus);
+                    change[z * width + x] -= moved;
+                    for &(nx, nz, part) in &lower {
+                        change[nz * width + nx] += moved * part / excess;
+                    }
+                }
+            }
+
+            for z in 0..depth {
+                for x in 0..width {
+                    let height = heightmap.get(x, z);
+                    heightmap.set(x, z, height + change[z * width + x]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::Heightmap;
+
+    /// Rolling hills with a steep ridge along x = 16.
+    fn hills() -> Heightmap {
+        Heightmap::from_fn(32, 32, |x, z| {
+            let ridge = 12.0 - 1.5 * (x as f32 - 16.0).abs();
+            3.0 * ((x as f32 * 0.4).sin() + (z as f32 * 0.3).cos()) + ridge.max(0.0)
+        })
+    }
+
+    fn total(map: &Heightmap) -> f64 {
+        map.data().iter().map(|&h| h as f64).sum()
+    }
+
+    /// The largest drop between neighbouring samples.
+    fn steepest(map: &Heightmap) -> f32 {
+        let mut steepest = 0.0f32;
+        for z in 0..map.depth() {
+            for x in 0..map.width() {
+                if x + 1 < map.width() {
+                    steepest = steepest.max((map.get(x, z) - map.get(x + 1, z)).abs());
+                }
+                if z + 1 < map.depth() {
+                    steepest = steepest.max((map.get(x, z) - map.get(x, z + 1)).abs());
+                }
+            }
+        }
+        steepest
+    }
+
+    #[test]
+    fn hydraulic_erosion_depends_only_on_the_seed() {
+        let eroded = |seed: u32| {
+            let mut map = hills();
+            HydraulicErosion::new(seed, 2000).erode(&mut map);
+            map
+        };
+
+        let (first, second) = (eroded(5), eroded(5));
+        assert_eq!(first.data(), second.data());
+        assert!(first.data() != eroded(6).data());
+        assert!(first.data() != hills().data());
+        assert!(first.data().iter().all(|h| h.is_finite()));
+    }
+
+    #[test]
+    fn thermal_erosion_is_repeatable() {
+        let eroded = || {
+            let mut map = hills();
+            ThermalErosion::new(20, 0.5).erode(&mut map);
+            map
+        };
+        assert_eq!(eroded().data(), eroded().data());
+    }
+
+    #[test]
+    fn thermal_erosion_conserves_mass() {
+        let mut map = hills();
+        let before  = total(&map);
+        ThermalErosion::new(50, 0.5).erode(&mut map);
+
+        assert!((total(&map) - before).abs() < 1.0e-3 * before.abs(),
+                "mass changed from {} to {}", before, total(&map));
+    }
+
+    #[test]
+    fn thermal_erosion_relaxes_slopes_towards_the_talus() {
+        let mut map = hills();
+        let talus   = 0.5;
+        let (low, high) = map.range();
+
+        let before = steepest(&map);
+        ThermalErosion::new(200, talus).erode(&mut map);
+        let after  = steepest(&map);
+        assert!(after < talus + 0.05, "steepest drop went from {} to {}", before, after);
+
+        // material only moves downhill, so no new extremes appear
+        let (new_low, new_high) = map.range();
+        assert!(new_low >= low - 1.0e-4 && new_high <= high + 1.0e-4);
+    }
+}
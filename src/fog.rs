@@ -0,0 +1,108 @@
+use ::sky::Sky;
+use ::nalgebra::Vec3;
+
+/// GLSL blending a color towards the fog color by the fog between the
+/// camera and a point, both in world space. Shaders including it must set
+/// the uniforms it declares from a Fog.
+pub const FOG : &'static str = r#"
+    uniform int fog_mode;
+    uniform vec4 fog_params;
+    uniform vec3 fog_color;
+
+    // fraction of the light from the point view_offset away from the camera
+    // which is replaced by fog
+    float fog_amount(vec3 camera_position, vec3 view_offset) {
+        float dist = length(view_offset);
+        if (fog_mode == 1) {
+            return clamp((dist - fog_params.x) / (fog_params.y - fog_params.x), 0.0, 1.0);
+        }
+        if (fog_mode == 2) {
+            return 1.0 - exp(-fog_params.x * dist);
+        }
+        if (fog_mode == 3) {
+            float d = fog_params.x * dist;
+            return 1.0 - exp(-d * d);
+        }
+        if (fog_mode == 4) {
+            // density * exp(-falloff * (y - base_height)) integrated along
+            // the ray
+            float start = fog_params.x * exp(-fog_params.y * (camera_position.y - fog_params.z));
+            float climb = fog_params.y * view_offset.y;
+            float depth = abs(climb) > 1.0e-4 ?
+                start * dist * (1.0 - exp(-climb)) / climb :
+                start * dist;
+            return 1.0 - exp(-depth);
+        }
+        return 0.0;
+    }
+
+    vec3 apply_fog(vec3 color, vec3 camera_position, vec3 view_offset) {
+        return mix(color, fog_color, fog_amount(camera_position, view_offset));
+    }
+"#;
+
+/// How fog thickens with distance from the camera.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FogMode {
+    None,
+    /// From none at start to complete at end.
+    Linear { start: f32, end: f32 },
+    /// Fog with the same density everywhere.
+    Exponential { density: f32 },
+    /// Like Exponential, but clearer near the camera and thicker beyond.
+    ExponentialSquared { density: f32 },
+    /// Exponential fog whose density falls off with height above
+    /// base_height by falloff per unit, settling in valleys.
+    Height { density: f32, falloff: f32, base_height: f32 }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Fog {
+    pub mode  : FogMode,
+    pub color : Vec3<f32>
+}
+
+impl Fog {
+    pub fn new(mode: FogMode) -> Fog {
+        Fog { mode : mode, color : Vec3::new(0.7, 0.75, 0.8) }
+    }
+
+    pub fn none() -> Fog {
+        Fog::new(FogMode::None)
+    }
+
+    /// Takes the color of the fog from the horizon of sky, averaged all the
+    /// way around.
+    pub fn set_sky_color(&mut self, sky: &Sky) {
+        let count = 8;
+        let mut color = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..count {
+            let angle = i as f32 / count as f32 * 2.0 * ::std::f32::consts::PI;
+            color = color + sky.radiance(Vec3::new(angle.cos(), 0.05, angle.sin()));
+        }
+        self.color = color * (1.0 / count as f32);
+    }
+
+    /// Number passed to the fog_mode uniform.
+    pub fn mode_index(&self) -> i32 {
+        match self.mode {
+            FogMode::None                      => 0,
+            FogMode::Linear { .. }             => 1,
+            FogMode::Exponential { .. }        => 2,
+            FogMode::ExponentialSquared { .. } => 3,
+            FogMode::Height { .. }             => 4
+        }
+    }
+
+    /// Values passed to the fog_params uniform.
+    pub fn params(&self) -> [f32; 4] {
+        match self.mode {
+            FogMode::None                           => [0.0, 0.0, 0.0, 0.0],
+            FogMode::Linear { start, end }          => [start, end, 0.0, 0.0],
+            FogMode::Exponential { density }        => [density, 0.0, 0.0, 0.0],
+            FogMode::ExponentialSquared { density } => [density, 0.0, 0.0, 0.0],
+            FogMode::Height { density, falloff, base_height } =>
+                [density, falloff, base_height, 0.0]
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use ::RenderableObj;
+use ::lighting::{BLINN_PHONG, WATER, model_space_lighting};
+use ::nalgebra::{Vec3, Pnt3, Mat4, Iso3, Norm, cross, to_homogeneous, Transformation};
+use ::glium::{Program, Display, DrawParameters, DepthTest, Frame, PolygonMode};
+use ::glium::texture::{Texture2d, UncompressedFloatFormat};
+
+/// Most waves a GerstnerRenderer draws, any more are ignored.
+pub const MAX_WAVES : usize = 8;
+
+const GRAVITY : f32 = 9.81;
+
+/// One directional trochoidal wave.
+#[derive(Copy, Clone, Debug)]
+pub struct GerstnerWave {
+    /// Unit direction the wave travels in, on the xz plane.
+    pub direction  : (f32, f32),
+    /// Distance between crests.
+    pub wavelength : f32,
+    pub amplitude  : f32,
+    /// From 0, a sine wave, to 1, where the crests of all the waves
+    /// together become sharp.
+    pub steepness  : f32,
+    /// Distance a crest travels per unit of time.
+    pub speed      : f32
+}
+
+impl GerstnerWave {
+    /// Creates a wave travelling at angle radians around the y axis from +x,
+    /// moving at the speed of a deep water wave of its length.
+    pub fn new(angle: f32, wavelength: f32, amplitude: f32, steepness: f32) -> GerstnerWave {
+        GerstnerWave {
+            direction  : (angle.cos(), angle.sin()),
+            wavelength : wavelength,
+            amplitude  : amplitude,
+            steepness  : steepness,
+            speed      : (GRAVITY * wavelength / (2.0 * ::std::f32::consts::PI)).sqrt()
+        }
+    }
+
+    fn wavenumber(&self) -> f32 {
+        2.0 * ::std::f32::consts::PI / self.wavelength
+    }
+}
+
+/// A sum of Gerstner waves, evaluated on the CPU exactly as the
+/// GerstnerRenderer does on the GPU.
+#[derive(Clone, Debug)]
+pub struct GerstnerSurface {
+    pub waves : Vec<GerstnerWave>
+}
+
+impl GerstnerSurface {
+    pub fn new(waves: Vec<GerstnerWave>) -> GerstnerSurface {
+        GerstnerSurface { waves : waves }
+    }
+
+    /// A few waves roughly following direction angle, for a quick sea.
+    pub fn default_sea(angle: f32) -> GerstnerSurface {
+        GerstnerSurface::new(vec![
+            GerstnerWave::new(angle,        16.0, 0.50, 0.6),
+            GerstnerWave::new(angle + 0.4,  9.0,  0.30, 0.6),
+            GerstnerWave::new(angle - 0.5,  5.0,  0.15, 0.5),
+            GerstnerWave::new(angle + 1.1,  2.5,  0.06, 0.4)
+        ])
+    }
+
+    /// Sharpness of each wave, scaled so that the crests only just reach
+    /// a point when every wave is at full steepness. Flat waves have none.
+    fn sharpness(&self, wave: &GerstnerWave) -> f32 {
+        let count = self.waves.len().min(MAX_WAVES) as f32;
+        let ka    = wave.wavenumber() * wave.amplitude;
+        if ka == 0.0 { 0.0 } else { wave.steepness / (ka * count) }
+    }
+
+    fn phase(wave: &GerstnerWave, x: f32, z: f32, time: f32) -> f32 {
+        let k = wave.wavenumber();
+        k * (wave.direction.0 * x + wave.direction.1 * z) - k * wave.speed * time
+    }
+
+    /// Offset of the point of the surface which rests at (x, 0, z).
+    pub fn displacement(&self, x: f32, z: f32, time: f32) -> Vec3<f32> {
+        let mut offset = Vec3::new(0.0, 0.0, 0.0);
+        for wave in self.waves.iter().take(MAX_WAVES) {
+            let theta = GerstnerSurface::phase(wave, x, z, time);
+            let q     = self.sharpness(wave);
+            let a     = wave.amplitude;
+
+            offset.x += q * a * wave.direction.0 * theta.cos();
+            offset.y += a * theta.sin();
+            offset.z += q * a * wave.direction.1 * theta.cos();
+        }
+        offset
+    }
+
+    /// Where the point of the surface resting at (x, 0, z) is at time.
+    pub fn position(&self, x: f32, z: f32, time: f32) -> Pnt3<f32> {
+        Pnt3::new(x, 0.0, z) + self.displacement(x, z, time)
+    }
+
+    /// Unit normal of the surface at the point resting at (x, 0, z), from
+    /// the tangents of the displaced surface along x and z.
+    pub fn normal(&self, x: f32, z: f32, time: f32) -> Vec3<f32> {
+        let mut along_x = Vec3::new(1.0, 0.0, 0.0);
+        let mut along_z = Vec3::new(0.0, 0.0, 1.0);
+        for wave in self.waves.iter().take(MAX_WAVES) {
+            let theta      = GerstnerSurface::phase(wave, x, z, time);
+            let (sin, cos) = theta.sin_cos();
+            let ka         = wave.wavenumber() * wave.amplitude;
+            let q          = self.sharpness(wave);
+            let (dx, dz)   = wave.direction;
+
+            along_x = along_x + Vec3::new(-q * dx * dx * sin, dx * cos, -q * dx * dz * sin) * ka;
+            along_z = along_z + Vec3::new(-q * dx * dz * sin, dz * cos, -q * dz * dz * sin) * ka;
+        }
+        cross(&along_z, &along_x).normalize()
+    }
+
+    /// The resting point of the surface which the waves carry over the
+    /// world position (x, z) at time. The waves move points sideways, so it
+    /// is found by iterating.
+    pub fn resting_point(&self, x: f32, z: f32, time: f32) -> (f32, f32) {
+        let (mut rx, mut rz) = (x, z);
+        for _ in 0..4 {
+            let d = self.displacement(rx, rz, time);
+            rx = x - d.x;
+            rz = z - d.z;
+        }
+        (rx, rz)
+    }
+
+    /// Height of the surface directly above or below the world position
+    /// (x, z), for floating objects.
+    pub fn height_at(&self, x: f32, z: f32, time: f32) -> f32 {
+        let (rx, rz) = self.resting_point(x, z, time);
+        self.displacement(rx, rz, time).y
+    }
+}
+
+/// Draws a flat mesh on the xz plane as water displaced by Gerstner waves,
+/// shaded with Fresnel reflection of the sky and Blinn-Phong specular.
+pub struct GerstnerRenderer {
+    pub program         : Program,
+    /// Direction towards the light, in world space.
+    pub light_direction : Vec3<f32>,
+    pub water_color     : Vec3<f32>,
+    pub sky_color       : Vec3<f32>,
+    pub specular_color  : Vec3<f32>,
+    pub shininess       : f32,
+    pub wire            : bool,
+    surface             : GerstnerSurface,
+    wave_data           : Texture2d
+}
+
+impl GerstnerRenderer {
+    pub fn new(display: &Display, surface: GerstnerSurface) -> GerstnerRenderer {
+        GerstnerRenderer {
+            program         : GerstnerRenderer::create_shader_program(&display),
+            light_direction : Vec3::new(0.3, 0.5, -0.8).normalize(),
+            water_color     : Vec3::new(0.0, 0.12, 0.18),
+            sky_color       : Vec3::new(0.55, 0.7, 0.85),
+            specular_color  : Vec3::new(1.0, 0.95, 0.85),
+            shininess       : 256.0,
+            wire            : false,
+            wave_data       : GerstnerRenderer::create_wave_data(&display, &surface),
+            surface         : surface
+        }
+    }
+
+    pub fn surface(&self) -> &GerstnerSurface {
+        &self.surface
+    }
+
+    pub fn set_surface(&mut self, display: &Display, surface: GerstnerSurface) {
+        self.wave_data = GerstnerRenderer::create_wave_data(display, &surface);
+        self.surface   = surface;
+    }
+
+    pub fn draw(
+        &self, frame: &mut Frame,
+        obj: &RenderableObj, proj: &Mat4<f32>, view: &Iso3<f32>, model: &Iso3<f32>, time: f32
+    ) {
+        let mv  = view.prepend_transformation(model);
+        let mvp = *proj * to_homogeneous(&mv);
+
+        let (eye, light) = model_space_lighting(&mv, model, &self.light_direction);
+
+        let params = DrawParameters {
+            depth_test   : DepthTest::IfLess,
+            depth_write  : true,
+            polygon_mode : if self.wire == true { PolygonMode::Line } else { PolygonMode::Fill },
+            .. ::std::default::Default::default()
+        };
+
+        let uniforms = uniform!(
+            MVP             : mvp,
+            time            : time,
+            wave_count      : self.surface.waves.len().min(MAX_WAVES) as i32,
+            waves           : &self.wave_data,
+            eye_position    : eye,
+            light_direction : light,
+            water_color     : self.water_color,
+            sky_color       : self.sky_color,
+            specular_color  : self.specular_color,
+            shininess       : self.shininess
+        );
+
+        obj.draw(frame, &self.program, &uniforms, &params);
+    }
+
+    /// Packs each wave into a column of two texels, the wave vector,
+    /// amplitude and sharpness below the angular frequency.
+    fn create_wave_data(display: &Display, surface: &GerstnerSurface) -> Texture2d {
+        let mut shape = vec![(0.0, 0.0, 0.0, 0.0); MAX_WAVES];
+        let mut speed = vec![(0.0, 0.0, 0.0, 0.0); MAX_WAVES];
+
+        for (i, wave) in surface.waves.iter().take(MAX_WAVES).enumerate() {
+            let k = wave.wavenumber();
+            shape[i] = (
+                wave.direction.0 * k, wave.direction.1 * k,
+                wave.amplitude, surface.sharpness(wave)
+            );
+            speed[i] = (k * wave.speed, 0.0, 0.0, 0.0);
+        }
+
+        Texture2d::with_format(
+            display, vec![shape, speed], UncompressedFloatFormat::F32F32F32F32, false
+        ).unwrap()
+    }
+
+    fn create_shader_program(display: &Display) -> Program {
+        let vertex_shader_src = r#"
+            #version 330
+            in vec3 position;
+            smooth out vec3 surface_position;
+            smooth out vec3 surface_normal;
+
+            uniform mat4 MVP;
+            uniform float time;
+            uniform int wave_count;
+            uniform sampler2D waves;
+
+            void main() {
+                vec3 p       = vec3(position.x, 0.0, position.z);
+                vec3 along_x = vec3(1.0, 0.0, 0.0);
+                vec3 along_z = vec3(0.0, 0.0, 1.0);
+
+                for (int i = 0; i < wave_count; i++) {
+                    vec4 shape   = texelFetch(waves, ivec2(i, 0), 0);
+                    float omega  = texelFetch(waves, ivec2(i, 1), 0).x;
+                    vec2 k       = shape.xy;
+                    float a      = shape.z;
+                    float q      = shape.w;
+                    float theta  = dot(k, position.xz) - omega * time;
+                    vec2 dir     = normalize(k);
+                    float ka     = length(k) * a;
+
+                    p.xz += q * a * dir * cos(theta);
+                    p.y  += a * sin(theta);
+
+                    float s = sin(theta);
+                    along_x += ka * vec3(-q * dir.x * dir.x * s, dir.x * cos(theta), -q * dir.x * dir.y * s);
+                    along_z += ka * vec3(-q * dir.x * dir.y * s, dir.y * cos(theta), -q * dir.y * dir.y * s);
+                }
+
+                surface_position = p;
+                surface_normal = cross(along_z, along_x);
+                gl_Position = MVP * vec4(p, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = [r#"
+            #version 330
+        "#, BLINN_PHONG, WATER, r#"
+            smooth in vec3 surface_position;
+            smooth in vec3 surface_normal;
+            out vec4 vFragColor;
+
+            uniform vec3 eye_position;
+            uniform vec3 light_direction;
+            uniform vec3 water_color;
+            uniform vec3 sky_color;
+            uniform vec3 specular_color;
+            uniform float shininess;
+
+            void main() {
+                vec3 N = normalize(surface_normal);
+                vec3 V = normalize(eye_position - surface_position);
+
+                vec3 color = water(
+                    N, light_direction, V, water_color, sky_color, specular_color, shininess
+                );
+                vFragColor = vec4(color, 1.0);
+            }
+        "#].concat();
+
+        Program::from_source(
+            display, vertex_shader_src, &fragment_shader_src, None
+        ).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::nalgebra::{Vec3, Pnt3, Norm, cross};
+
+    /// Resting points spread over a few wavelengths, at a few times.
+    fn samples() -> Vec<(f32, f32, f32)> {
+        let mut points = vec![];
+        for i in 0..6 {
+            for j in 0..6 {
+                points.push((i as f32 * 3.7 - 9.0, j as f32 * 2.9 - 7.0, i as f32 * 0.45 + j as f32 * 0.3));
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn normals_match_finite_differences() {
+        let surface = GerstnerSurface::default_sea(0.3);
+        let h = 1.0e-2;
+
+        for (x, z, time) in samples() {
+            let along_x = surface.position(x + h, z, time) - surface.position(x - h, z, time);
+            let along_z = surface.position(x, z + h, time) - surface.position(x, z - h, time);
+            let expected: Vec3<f32> = cross(&along_z, &along_x).normalize();
+
+            let n = surface.normal(x, z, time);
+            assert!((n - expected).norm() < 1.0e-2,
+                    "normal at {} {} was {:?} not {:?}", x, z, n, expected);
+        }
+    }
+
+    #[test]
+    fn height_at_finds_the_displaced_surface() {
+        let surface = GerstnerSurface::default_sea(1.2);
+
+        for (x, z, time) in samples() {
+            let p: Pnt3<f32> = surface.position(x, z, time);
+            let height = surface.height_at(p.x, p.z, time);
+            assert!((height - p.y).abs() < 1.0e-2,
+                    "height at {} {} was {} not {}", p.x, p.z, height, p.y);
+        }
+    }
+
+    #[test]
+    fn resting_points_are_carried_over_the_position() {
+        let surface = GerstnerSurface::default_sea(0.7);
+
+        for (x, z, time) in samples() {
+            let (rx, rz) = surface.resting_point(x, z, time);
+            let p: Pnt3<f32> = surface.position(rx, rz, time);
+            assert!((p.x - x).abs() < 1.0e-2 && (p.z - z).abs() < 1.0e-2,
+                    "resting point of {} {} moves to {} {}", x, z, p.x, p.z);
+        }
+    }
+
+    #[test]
+    fn flat_waves_have_no_sharpness() {
+        let surface = GerstnerSurface::new(vec![
+            GerstnerWave::new(0.0, 10.0, 0.0, 0.5),
+            GerstnerWave::new(1.0, 6.0, 0.3, 0.5)
+        ]);
+        assert_eq!(surface.sharpness(&surface.waves[0]), 0.0);
+
+        for (x, z, time) in samples() {
+            let d = surface.displacement(x, z, time);
+            let n = surface.normal(x, z, time);
+            assert!(d.x.is_finite() && d.y.is_finite() && d.z.is_finite());
+            assert!(n.x.is_finite() && n.y.is_finite() && n.z.is_finite());
+        }
+    }
+
+    #[test]
+    fn height_at_on_a_sine_wave() {
+        // without sharpness points only move up and down
+        let wave    = GerstnerWave::new(0.0, 10.0, 0.5, 0.0);
+        let surface = GerstnerSurface::new(vec![wave]);
+        for (x, z, time) in samples() {
+            let expected = 0.5 * (wave.wavenumber() * (x - wave.speed * time)).sin();
+            assert!((surface.height_at(x, z, time) - expected).abs() < 1.0e-4);
+        }
+    }
+}
@@ -1,44 +1,153 @@
-use ::{RenderableIndices, Vertex};
-use ::glium::{IndexBuffer, VertexBuffer, Display};
-use ::glium::index::{TrianglesList};
+use ::{RenderableIndices, Vertex, Mesh};
+use ::glium::{VertexBuffer, Display};
+use ::nalgebra::{Vec3, Norm};
+use ::std::collections::{HashMap, HashSet};
 
-pub struct Grid{
-    pub indices : Vec<u16>,
-    vertices    : Vec<[f32; 3]>
+/// How each quad of the grid is split into triangles.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Triangulation {
+    /// Diagonals alternate direction from one quad to the next.
+    Alternating,
+    /// Every quad is split along the same diagonal.
+    Uniform,
+    /// Every quad gets a center vertex and is split into four triangles.
+    Diamond
+}
+
+pub struct Grid {
+    pub indices   : Vec<u32>,
+    vertices      : Vec<Vertex>,
+    depth         : f32,
+    width         : f32,
+    x_count       : u32,
+    z_count       : u32,
+    triangulation : Triangulation,
+    skirt_depth   : Option<f32>,
+    height_fn     : Option<Box<Fn(f32, f32) -> f32>>
 }
 
 impl Grid {
-    pub fn new(depth: f32, width: f32, x_count: u16, z_count: u16) -> Grid {
-        let mut grid = Grid { vertices : vec![], indices  : vec![] };
+    pub fn new(depth: f32, width: f32, x_count: u32, z_count: u32) -> Grid {
+        let mut grid = Grid {
+            vertices      : vec![],
+            indices       : vec![],
+            depth         : depth,
+            width         : width,
+            x_count       : x_count,
+            z_count       : z_count,
+            triangulation : Triangulation::Alternating,
+            skirt_depth   : None,
+            height_fn     : None
+        };
 
-        grid.build_vertices(depth, width, x_count, z_count);
-        grid.build_indices(x_count, z_count);
+        grid.build();
         grid
     }
 
+    /// Selects how each quad is split into triangles.
+    pub fn with_triangulation(mut self, triangulation: Triangulation) -> Grid {
+        self.triangulation = triangulation;
+        self.build();
+        self
+    }
+
+    /// Adds a vertical strip of the given depth below every edge of the grid
+    /// to hide cracks between neighbouring grids.
+    pub fn with_skirt(mut self, skirt_depth: f32) -> Grid {
+        self.skirt_depth = Some(skirt_depth);
+        self.build();
+        self
+    }
+
+    /// Displaces every vertex to y = height(x, z) and computes the normals
+    /// from the slope of the height function.
+    pub fn with_height<F>(mut self, height: F) -> Grid
+        where F: Fn(f32, f32) -> f32 + 'static
+    {
+        self.height_fn = Some(Box::new(height));
+        self.build();
+        self
+    }
+
     pub fn get_vertices(&self) -> Vec<Vertex> {
-        let mut verts = vec![];
+        self.vertices.clone()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
 
-        for pos in &self.vertices {
-            verts.push(Vertex::from_position(pos[0], pos[1], pos[2]));
+    pub fn to_mesh(&self) -> Mesh {
+        Mesh::from_parts(self.get_vertices(), self.indices.clone())
+    }
+
+    fn build(&mut self) {
+        self.vertices = vec![];
+        self.indices  = vec![];
+
+        self.build_vertices();
+        self.build_indices();
+        if let Some(skirt_depth) = self.skirt_depth {
+            self.build_skirt(skirt_depth);
         }
-        verts
     }
 
-    fn build_vertices(
-        &mut self, depth: f32, width: f32, x_count: u16, z_count: u16
-    ) {
+    fn spacing(&self) -> (f32, f32) {
+        (
+            2.0 * self.width / (self.x_count as f32 - 1.0),
+            2.0 * self.depth / (self.z_count as f32 - 1.0)
+        )
+    }
+
+    fn grid_vertex(&self, x: f32, z: f32, u: f32, v: f32) -> Vertex {
+        let (dx, dz) = self.spacing();
+
+        match self.height_fn {
+            Some(ref height) => {
+                let nx = (height(x - dx, z) - height(x + dx, z)) / (2.0 * dx);
+                let nz = (height(x, z - dz) - height(x, z + dz)) / (2.0 * dz);
+                let n  = Vec3::new(nx, 1.0, nz).normalize();
+
+                Vertex::new([x, height(x, z), z], [n.x, n.y, n.z], [u, v])
+            },
+            None => Vertex::new([x, 0.0, z], [0.0, 1.0, 0.0], [u, v])
+        }
+    }
+
+    fn build_vertices(&mut self) {
+        let (x_count, z_count) = (self.x_count, self.z_count);
+
         for j in 0..z_count {
-            let scaled_j = ((j as f32)/(z_count as f32 -1.0)) * 2.0 - 1.0;
+            let v = (j as f32)/(z_count as f32 - 1.0);
             for i in 0..x_count {
-                let scaled_i = ((i as f32)/(x_count as f32 - 1.0)) * 2.0 - 1.0;
+                let u = (i as f32)/(x_count as f32 - 1.0);
 
-                self.vertices.push([scaled_i * width, 0.0, scaled_j * depth]);
+                let vertex = self.grid_vertex(
+                    (u * 2.0 - 1.0) * self.width, (v * 2.0 - 1.0) * self.depth, u, v
+                );
+                self.vertices.push(vertex);
+            }
+        }
+
+        if self.triangulation == Triangulation::Diamond {
+            for j in 0..z_count-1 {
+                let v = (j as f32 + 0.5)/(z_count as f32 - 1.0);
+                for i in 0..x_count-1 {
+                    let u = (i as f32 + 0.5)/(x_count as f32 - 1.0);
+
+                    let vertex = self.grid_vertex(
+                        (u * 2.0 - 1.0) * self.width, (v * 2.0 - 1.0) * self.depth, u, v
+                    );
+                    self.vertices.push(vertex);
+                }
             }
         }
     }
 
-    fn build_indices(&mut self, x_count: u16, z_count: u16) {
+    fn build_indices(&mut self) {
+        let (x_count, z_count) = (self.x_count, self.z_count);
+        let centers = x_count * z_count;
+
         let mut count = 0;
         for row in 0..z_count-1 {
             for col in 0..x_count-1 {
@@ -47,58 +156,162 @@ impl Grid {
                 let tr = tl + x_count;
                 let br = tr + 1;
 
-                if count % 2 == 0 {
-                    self.indices.push(tl);
-                    self.indices.push(bl);
-                    self.indices.push(br);
-
-                    self.indices.push(tl);
-                    self.indices.push(br);
-                    self.indices.push(tr);
-                }
-                else {
-                    self.indices.push(tl);
-                    self.indices.push(bl);
-                    self.indices.push(tr);
-
-                    self.indices.push(tr);
-                    self.indices.push(bl);
-                    self.indices.push(br);
+                match self.triangulation {
+                    Triangulation::Diamond => {
+                        let c = centers + row * (x_count - 1) + col;
+                        self.indices.extend([
+                            tl, bl, c,
+                            bl, br, c,
+                            br, tr, c,
+                            tr, tl, c
+                        ].iter().cloned());
+                    },
+                    Triangulation::Alternating if count % 2 != 0 => {
+                        self.indices.extend([
+                            tl, bl, tr,
+                            tr, bl, br
+                        ].iter().cloned());
+                    },
+                    _ => {
+                        self.indices.extend([
+                            tl, bl, br,
+                            tl, br, tr
+                        ].iter().cloned());
+                    }
                 }
                 count += 1;
             }
         }
     }
-}
 
-impl ::BuildRenderable for Grid {
-    fn get_vertex_array(&self, display: &Display) -> VertexBuffer<Vertex> {
-        let mut vertices = self.get_vertices();
+    fn build_skirt(&mut self, skirt_depth: f32) {
+        // boundary edges are the directed edges whose reverse is not used
+        // by any triangle, walking them backwards keeps the winding
+        let mut edges = HashSet::new();
+        for tri in self.indices.chunks(3) {
+            edges.insert((tri[0], tri[1]));
+            edges.insert((tri[1], tri[2]));
+            edges.insert((tri[2], tri[0]));
+        }
+
+        let mut boundary: Vec<(u32, u32)> = edges.iter()
+            .filter(|&&(a, b)| !edges.contains(&(b, a)))
+            .cloned()
+            .collect();
+        boundary.sort();
 
-        for vertex in &mut vertices {
-            vertex.normal = [0.0, 1.0, 0.0];
+        let mut lowered = HashMap::new();
+        for &(a, b) in &boundary {
+            let a_low = self.lowered_vertex(&mut lowered, a, skirt_depth);
+            let b_low = self.lowered_vertex(&mut lowered, b, skirt_depth);
+
+            self.indices.extend([
+                b, a, a_low,
+                b, a_low, b_low
+            ].iter().cloned());
+        }
+    }
+
+    fn lowered_vertex(
+        &mut self, lowered: &mut HashMap<u32, u32>, index: u32, skirt_depth: f32
+    ) -> u32 {
+        if let Some(&low) = lowered.get(&index) {
+            return low;
         }
 
-        VertexBuffer::new(display, vertices)
+        let mut vertex = self.vertices[index as usize];
+        vertex.position[1] -= skirt_depth;
+
+        let low = self.vertices.len() as u32;
+        self.vertices.push(vertex);
+        lowered.insert(index, low);
+        low
+    }
+}
+
+impl ::BuildRenderable for Grid {
+    fn get_vertex_array(&self, display: &Display) -> VertexBuffer<Vertex> {
+        VertexBuffer::new(display, self.get_vertices())
     }
 
     fn get_indices(&self, display: &Display) -> RenderableIndices {
-        RenderableIndices::Buffer(
-            IndexBuffer::new(
-                display, TrianglesList(self.indices.clone())
-            )
+        RenderableIndices::from_triangles(
+            display, self.vertex_count(), self.indices.clone()
         )
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::validation::validate;
 
+    #[test]
+    fn corners_span_the_texture() {
+        let grid     = Grid::new(2.0, 3.0, 4, 5);
+        let vertices = grid.get_vertices();
 
+        for &(index, position, uv) in &[
+            (0,  [-3.0, 0.0, -2.0], [0.0, 0.0]),
+            (3,  [ 3.0, 0.0, -2.0], [1.0, 0.0]),
+            (16, [-3.0, 0.0,  2.0], [0.0, 1.0]),
+            (19, [ 3.0, 0.0,  2.0], [1.0, 1.0])
+        ] {
+            assert_eq!(vertices[index].position, position);
+            assert_eq!(vertices[index].tex_coord, uv);
+        }
+    }
 
+    #[test]
+    fn diamond_adds_a_vertex_per_quad() {
+        let grid = Grid::new(1.0, 1.0, 4, 5).with_triangulation(Triangulation::Diamond);
 
+        assert_eq!(grid.vertex_count(), 4 * 5 + 3 * 4);
+        assert_eq!(grid.indices.len(), 3 * 4 * 3 * 4);
 
+        let center = grid.get_vertices()[4 * 5];
+        assert_eq!(center.tex_coord, [0.5 / 3.0, 0.5 / 4.0]);
+    }
 
+    #[test]
+    fn skirt_closes_every_boundary_edge() {
+        let depth   = 0.5;
+        let plain   = Grid::new(1.0, 1.0, 4, 5);
+        let skirted = Grid::new(1.0, 1.0, 4, 5).with_skirt(depth);
 
+        // two triangles below each of the 2 * (3 + 4) boundary edges
+        assert_eq!(skirted.indices.len() - plain.indices.len(), 3 * 2 * 14);
 
+        let mut mesh = skirted.to_mesh();
+        mesh.compute_normals();
+        assert_eq!(validate(&mesh), vec![]);
 
+        // only the lower edge of the skirt is left open
+        let mut edges = HashSet::new();
+        for tri in mesh.indices.chunks(3) {
+            for k in 0..3 {
+                edges.insert((tri[k], tri[(k + 1) % 3]));
+            }
+        }
+        for &(a, b) in &edges {
+            if !edges.contains(&(b, a)) {
+                assert_eq!(mesh.vertices[a as usize].position[1], -depth);
+                assert_eq!(mesh.vertices[b as usize].position[1], -depth);
+            }
+        }
+    }
 
+    #[test]
+    fn height_normals_follow_the_slope() {
+        let grid     = Grid::new(1.0, 1.0, 5, 5).with_height(|x, z| 0.5 * x - 0.25 * z);
+        let expected = Vec3::new(-0.5, 1.0, 0.25).normalize();
 
+        for vertex in grid.get_vertices() {
+            let p = vertex.position;
+            let n = vertex.normal;
+            assert!((p[1] - (0.5 * p[0] - 0.25 * p[2])).abs() < 1.0e-6);
+            assert!((Vec3::new(n[0], n[1], n[2]) - expected).norm() < 1.0e-5,
+                    "normal {:?} at {:?}", n, p);
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! Half-edge representation of triangle meshes for adjacency queries.
+//!
+//! Connectivity comes from the indices alone, so meshes which duplicate
+//! vertices at texture seams should be passed through Mesh::weld first.
+
+use ::{Vertex, BuildRenderable, RenderableIndices, Mesh};
+use ::glium::{VertexBuffer, Display};
+use ::std::collections::HashMap;
+
+/// One directed edge of a triangle. The half-edges of face f are stored at
+/// 3f, 3f+1 and 3f+2 in winding order.
+#[derive(Copy, Clone)]
+pub struct HalfEdge {
+    /// Vertex the half-edge starts from.
+    pub origin : u32,
+    /// The opposite half-edge of the neighbouring face, None on boundaries.
+    pub twin   : Option<usize>,
+    pub face   : usize
+}
+
+/// Problems found by HalfEdgeMesh::validate.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopologyError {
+    /// A triangle refers to a vertex which does not exist.
+    IndexOutOfRange(usize),
+    /// A triangle uses the same vertex more than once.
+    DegenerateFace(usize),
+    /// More than two triangles share the edge.
+    NonManifoldEdge(u32, u32),
+    /// Two triangles traverse the shared edge in the same direction.
+    InconsistentWinding(u32, u32),
+    /// The triangles around the vertex form more than one fan.
+    NonManifoldVertex(u32)
+}
+
+pub struct HalfEdgeMesh {
+    pub vertices   : Vec<Vertex>,
+    pub half_edges : Vec<HalfEdge>,
+    vertex_edge    : Vec<Option<usize>>,
+    edge_uses      : HashMap<(u32, u32), Vec<usize>>
+}
+
+impl HalfEdgeMesh {
+    pub fn from_mesh(mesh: &Mesh) -> HalfEdgeMesh {
+        let mut half_edges  = vec![];
+        let mut vertex_edge = vec![None; mesh.vertices.len()];
+        let mut edge_uses: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+
+        for (face, tri) in mesh.indices.chunks(3).enumerate() {
+            for k in 0..3 {
+                let (a, b) = (tri[k], tri[(k + 1) % 3]);
+                let index  = half_edges.len();
+
+                half_edges.push(HalfEdge { origin : a, twin : None, face : face });
+                if (a as usize) < vertex_edge.len() && vertex_edge[a as usize].is_none() {
+                    vertex_edge[a as usize] = Some(index);
+                }
+
+                let key = if a < b { (a, b) } else { (b, a) };
+                edge_uses.entry(key).or_insert(vec![]).push(index);
+            }
+        }
+
+        // only link edges shared by exactly two oppositely wound faces
+        for uses in edge_uses.values() {
+            if uses.len() == 2 &&
+               half_edges[uses[0]].origin != half_edges[uses[1]].origin {
+                half_edges[uses[0]].twin = Some(uses[1]);
+                half_edges[uses[1]].twin = Some(uses[0]);
+            }
+        }
+
+        HalfEdgeMesh {
+            vertices    : mesh.vertices.clone(),
+            half_edges  : half_edges,
+            vertex_edge : vertex_edge,
+            edge_uses   : edge_uses
+        }
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.half_edges.len() / 3
+    }
+
+    pub fn next(&self, edge: usize) -> usize {
+        edge - edge % 3 + (edge + 1) % 3
+    }
+
+    pub fn prev(&self, edge: usize) -> usize {
+        edge - edge % 3 + (edge + 2) % 3
+    }
+
+    pub fn twin(&self, edge: usize) -> Option<usize> {
+        self.half_edges[edge].twin
+    }
+
+    pub fn origin(&self, edge: usize) -> u32 {
+        self.half_edges[edge].origin
+    }
+
+    /// Vertex the half-edge points to.
+    pub fn dest(&self, edge: usize) -> u32 {
+        self.half_edges[self.next(edge)].origin
+    }
+
+    pub fn face_vertices(&self, face: usize) -> [u32; 3] {
+        [
+            self.half_edges[face*3].origin,
+            self.half_edges[face*3 + 1].origin,
+            self.half_edges[face*3 + 2].origin
+        ]
+    }
+
+    /// Faces sharing an edge with face, boundary edges are skipped.
+    pub fn face_neighbours(&self, face: usize) -> Vec<usize> {
+        (face*3..face*3 + 3)
+            .filter_map(|e| self.twin(e))
+            .map(|t| self.half_edges[t].face)
+            .collect()
+    }
+
+    pub fn is_boundary_edge(&self, edge: usize) -> bool {
+        self.twin(edge).is_none()
+    }
+
+    /// Half-edges with no twin.
+    pub fn boundary_edges(&self) -> Vec<usize> {
+        (0..self.half_edges.len()).filter(|&e| self.is_boundary_edge(e)).collect()
+    }
+
+    /// Half-edges leaving vertex in counter-clockwise order. For vertices on
+    /// a boundary the first edge is the one following the boundary.
+    pub fn outgoing_edges(&self, vertex: u32) -> Vec<usize> {
+        let start = match self.vertex_edge.get(vertex as usize) {
+            Some(&Some(e)) => e,
+            _ => return vec![]
+        };
+
+        // rewind clockwise until a boundary is found or the fan closes
+        let mut first = start;
+        for _ in 0..self.half_edges.len() {
+            match self.twin(first) {
+                Some(t) if self.next(t) != start => first = self.next(t),
+                _ => break
+            }
+        }
+
+        let mut edges = vec![first];
+        let mut edge  = first;
+        for _ in 0..self.half_edges.len() {
+            match self.twin(self.prev(edge)) {
+                Some(t) if t != first => {
+                    edges.push(t);
+                    edge = t;
+                },
+                _ => break
+            }
+        }
+        edges
+    }
+
+    pub fn is_boundary_vertex(&self, vertex: u32) -> bool {
+        self.outgoing_edges(vertex).iter().any(|&e| {
+            self.is_boundary_edge(e) || self.is_boundary_edge(self.prev(e))
+        })
+    }
+
+    /// Vertices connected to vertex by an edge, in counter-clockwise order.
+    pub fn vertex_ring(&self, vertex: u32) -> Vec<u32> {
+        let edges    = self.outgoing_edges(vertex);
+        let mut ring: Vec<u32> = edges.iter().map(|&e| self.dest(e)).collect();
+
+        if let Some(&last) = edges.last() {
+            let incoming = self.prev(last);
+            if self.is_boundary_edge(incoming) {
+                ring.push(self.origin(incoming));
+            }
+        }
+        ring
+    }
+
+    /// Faces using vertex, in counter-clockwise order.
+    pub fn vertex_faces(&self, vertex: u32) -> Vec<usize> {
+        self.outgoing_edges(vertex).iter().map(|&e| self.half_edges[e].face).collect()
+    }
+
+    /// Checks the mesh is a consistently wound 2-manifold, possibly with
+    /// boundaries.
+    pub fn validate(&self) -> Result<(), Vec<TopologyError>> {
+        let mut errors = vec![];
+
+        for face in 0..self.face_count() {
+            let tri   = self.face_vertices(face);
+            let (a, b, c) = (tri[0], tri[1], tri[2]);
+            let count = self.vertices.len() as u32;
+            if a >= count || b >= count || c >= count {
+                errors.push(TopologyError::IndexOutOfRange(face));
+            }
+            if a == b || b == c || c == a {
+                errors.push(TopologyError::DegenerateFace(face));
+            }
+        }
+
+        let mut edges: Vec<_> = self.edge_uses.iter().collect();
+        edges.sort_by(|a, b| a.0.cmp(b.0));
+        for (&(a, b), uses) in edges {
+            if uses.len() > 2 {
+                errors.push(TopologyError::NonManifoldEdge(a, b));
+            }
+            else if uses.len() == 2 &&
+                    self.origin(uses[0]) == self.origin(uses[1]) {
+                errors.push(TopologyError::InconsistentWinding(a, b));
+            }
+        }
+
+        // a manifold vertex reaches every face using it by walking its fan
+        let mut face_count = vec![0; self.vertices.len()];
+        for edge in &self.half_edges {
+            if (edge.origin as usize) < face_count.len() {
+                face_count[edge.origin as usize] += 1;
+            }
+        }
+        for v in 0..self.vertices.len() {
+            let reached = self.outgoing_edges(v as u32).len();
+            if reached < face_count[v] {
+                errors.push(TopologyError::NonManifoldVertex(v as u32));
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    pub fn is_manifold(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    pub fn to_mesh(&self) -> Mesh {
+        let indices = self.half_edges.iter().map(|e| e.origin).collect();
+        Mesh::from_parts(self.vertices.clone(), indices)
+    }
+}
+
+impl BuildRenderable for HalfEdgeMesh {
+    fn get_vertex_array(&self, display: &Display) -> VertexBuffer<Vertex> {
+        VertexBuffer::new(display, self.vertices.clone())
+    }
+
+    fn get_indices(&self, display: &Display) -> RenderableIndices {
+        self.to_mesh().get_indices(display)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh_from(vertex_count: usize, triangles: &[[u32; 3]]) -> HalfEdgeMesh {
+        let mut mesh = Mesh::new();
+        for i in 0..vertex_count {
+            let angle = i as f32;
+            mesh.push_vertex(Vertex::from_position(angle.cos(), 0.0, angle.sin()));
+        }
+        for t in triangles {
+            mesh.push_triangle(t[0], t[1], t[2]);
+        }
+        HalfEdgeMesh::from_mesh(&mesh)
+    }
+
+    /// Three triangles around vertex 0 with the rim 1, 2, 3, 4 left open.
+    fn open_fan() -> HalfEdgeMesh {
+        mesh_from(5, &[[0, 1, 2], [0, 2, 3], [0, 3, 4]])
+    }
+
+    #[test]
+    fn outgoing_edges_walk_the_fan() {
+        let fan = open_fan();
+
+        let dests: Vec<u32> = fan.outgoing_edges(0).iter().map(|&e| fan.dest(e)).collect();
+        assert_eq!(dests, vec![1, 2, 3]);
+        for &e in &fan.outgoing_edges(2) {
+            assert_eq!(fan.origin(e), 2);
+        }
+        assert_eq!(fan.outgoing_edges(2).len(), 2);
+        assert_eq!(fan.vertex_faces(0), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn vertex_ring_includes_the_boundary_neighbour() {
+        let fan = open_fan();
+        assert_eq!(fan.vertex_ring(0), vec![1, 2, 3, 4]);
+        assert_eq!(fan.vertex_ring(2), vec![3, 0, 1]);
+        assert_eq!(fan.vertex_ring(4), vec![0, 3]);
+
+        let closed = mesh_from(5, &[[0, 1, 2], [0, 2, 3], [0, 3, 4], [0, 4, 1]]);
+        assert_eq!(closed.vertex_ring(0).len(), 4);
+        assert!(!closed.is_boundary_vertex(0));
+        assert!(closed.is_boundary_vertex(1));
+    }
+
+    #[test]
+    fn face_neighbours_skip_the_boundary() {
+        let fan = open_fan();
+        assert_eq!(fan.face_neighbours(0), vec![1]);
+        assert_eq!(fan.face_neighbours(1), vec![0, 2]);
+        assert_eq!(fan.face_neighbours(2), vec![1]);
+    }
+
+    #[test]
+    fn boundary_edges_follow_the_rim() {
+        let fan = open_fan();
+        let edges: Vec<(u32, u32)> = fan.boundary_edges().iter()
+            .map(|&e| (fan.origin(e), fan.dest(e)))
+            .collect();
+        assert_eq!(edges, vec![(0, 1), (1, 2), (2, 3), (3, 4), (4, 0)]);
+        assert_eq!(fan.validate(), Ok(()));
+    }
+
+    #[test]
+    fn edges_shared_by_three_faces_are_non_manifold() {
+        let mesh   = mesh_from(5, &[[0, 1, 2], [1, 0, 3], [0, 1, 4]]);
+        let errors = mesh.validate().unwrap_err();
+        assert!(errors.contains(&TopologyError::NonManifoldEdge(0, 1)), "{:?}", errors);
+    }
+
+    #[test]
+    fn fans_touching_at_a_vertex_are_non_manifold() {
+        let bowtie = mesh_from(5, &[[0, 1, 2], [0, 3, 4]]);
+        assert_eq!(bowtie.validate(), Err(vec![TopologyError::NonManifoldVertex(0)]));
+    }
+
+    #[test]
+    fn faces_wound_the_same_way_along_an_edge_are_inconsistent() {
+        let mesh   = mesh_from(4, &[[0, 1, 2], [0, 1, 3]]);
+        let errors = mesh.validate().unwrap_err();
+        assert!(errors.contains(&TopologyError::InconsistentWinding(0, 1)), "{:?}", errors);
+    }
+}
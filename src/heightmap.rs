@@ -0,0 +1,274 @@
+use ::glium::Display;
+use ::glium::texture::{Texture2d, UncompressedFloatFormat};
+use ::nalgebra::{Vec3, Norm};
+
+/// How samples outside of the heightmap are found.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Addressing {
+    /// Use the nearest edge sample.
+    Clamp,
+    /// Repeat the heightmap, so that it tiles.
+    Wrap
+}
+
+/// A grid of heights stored row by row, indexed by (x, z). Samples are one
+/// unit apart, sample (x, z) lying at position (x, z).
+#[derive(Clone)]
+pub struct Heightmap {
+    width      : usize,
+    depth      : usize,
+    data       : Vec<f32>,
+    addressing : Addressing
+}
+
+impl Heightmap {
+    /// Creates a flat heightmap of width x depth samples, both of which
+    /// must be non-zero.
+    pub fn new(width: usize, depth: usize) -> Heightmap {
+        assert!(width > 0 && depth > 0, "heightmap size {}x{} is empty", width, depth);
+        Heightmap {
+            width      : width,
+            depth      : depth,
+            data       : vec![0.0; width * depth],
+            addressing : Addressing::Clamp
+        }
+    }
+
+    /// Creates a heightmap with every sample set to height(x, z).
+    pub fn from_fn<F>(width: usize, depth: usize, height: F) -> Heightmap
+        where F: Fn(usize, usize) -> f32
+    {
+        let mut map = Heightmap::new(width, depth);
+        for z in 0..depth {
+            for x in 0..width {
+                map.data[z * width + x] = height(x, z);
+            }
+        }
+        map
+    }
+
+    /// Creates a heightmap from rows of samples, as read back from a
+    /// texture.
+    pub fn from_rows(rows: &Vec<Vec<f32>>) -> Heightmap {
+        let depth = rows.len();
+        let width = if depth > 0 { rows[0].len() } else { 0 };
+        Heightmap::from_fn(width, depth, |x, z| rows[z][x])
+    }
+
+    /// Sets how the sampling queries treat positions outside of the map.
+    pub fn with_addressing(mut self, addressing: Addressing) -> Heightmap {
+        self.addressing = addressing;
+        self
+    }
+
+    pub fn addressing(&self) -> Addressing {
+        self.addressing
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn get(&self, x: usize, z: usize) -> f32 {
+        self.data[z * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, z: usize, height: f32) {
+        self.data[z * self.width + x] = height;
+    }
+
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+
+    /// Returns the sample at any integer position, addressed by the
+    /// heightmap's addressing mode.
+    pub fn texel(&self, x: i64, z: i64) -> f32 {
+        let address = |v: i64, n: usize| {
+            let n = n as i64;
+            match self.addressing {
+                Addressing::Clamp => v.max(0).min(n - 1),
+                Addressing::Wrap  => ((v % n) + n) % n
+            }
+        };
+        self.get(address(x, self.width) as usize, address(z, self.depth) as usize)
+    }
+
+    /// Splits a position into the sample before it and the fraction of the
+    /// way to the next one.
+    fn cell(x: f32, z: f32) -> (i64, i64, f32, f32) {
+        let (fx, fz) = (x.floor(), z.floor());
+        (fx as i64, fz as i64, x - fx, z - fz)
+    }
+
+    /// Bilinearly interpolated height at (x, z).
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let (x0, z0, tx, tz) = Heightmap::cell(x, z);
+
+        let near = lerp(self.texel(x0, z0),     self.texel(x0 + 1, z0),     tx);
+        let far  = lerp(self.texel(x0, z0 + 1), self.texel(x0 + 1, z0 + 1), tx);
+        lerp(near, far, tz)
+    }
+
+    /// Height at (x, z) interpolated with Catmull-Rom splines through the
+    /// surrounding 4x4 samples, smooth across sample boundaries.
+    pub fn sample_bicubic(&self, x: f32, z: f32) -> f32 {
+        let (x0, z0, tx, tz) = Heightmap::cell(x, z);
+
+        let row = |z: i64| catmull_rom(
+            self.texel(x0 - 1, z), self.texel(x0, z),
+            self.texel(x0 + 1, z), self.texel(x0 + 2, z), tx
+        );
+        catmull_rom(row(z0 - 1), row(z0), row(z0 + 1), row(z0 + 2), tz)
+    }
+
+    /// The partial derivatives (dh/dx, dh/dz) of the bilinear surface at
+    /// (x, z).
+    pub fn gradient(&self, x: f32, z: f32) -> (f32, f32) {
+        let (x0, z0, tx, tz) = Heightmap::cell(x, z);
+        let h00 = self.texel(x0,     z0);
+        let h10 = self.texel(x0 + 1, z0);
+        let h01 = self.texel(x0,     z0 + 1);
+        let h11 = self.texel(x0 + 1, z0 + 1);
+
+        (lerp(h10 - h00, h11 - h01, tz), lerp(h01 - h00, h11 - h10, tx))
+    }
+
+    /// Unit normal of the bilinear surface at (x, z), pointing up.
+    pub fn normal(&self, x: f32, z: f32) -> Vec3<f32> {
+        let (dx, dz) = self.gradient(x, z);
+        Vec3::new(-dx, 1.0, -dz).normalize()
+    }
+
+    /// Angle of the surface at (x, z) from horizontal, in radians.
+    pub fn slope(&self, x: f32, z: f32) -> f32 {
+        let (dx, dz) = self.gradient(x, z);
+        (dx*dx + dz*dz).sqrt().atan()
+    }
+
+    /// Returns the lowest and highest samples.
+    pub fn range(&self) -> (f32, f32) {
+        self.data.iter().fold((::std::f32::INFINITY, ::std::f32::NEG_INFINITY), |(lo, hi), &h| {
+            (lo.min(h), hi.max(h))
+        })
+    }
+
+    /// Returns a width x depth heightmap covering the same area, bilinearly
+    /// interpolating between samples.
+    pub fn resample(&self, width: usize, depth: usize) -> Heightmap {
+        let scale = |n: usize, m: usize| {
+            if m > 1 { (n - 1) as f32 / (m - 1) as f32 } else { 0.0 }
+        };
+        let (sx, sz) = (scale(self.width, width), scale(self.depth, depth));
+
+        Heightmap::from_fn(width, depth, |x, z| {
+            self.sample(x as f32 * sx, z as f32 * sz)
+        }).with_addressing(self.addressing)
+    }
+
+    /// Returns the samples as one Vec per row.
+    pub fn to_rows(&self) -> Vec<Vec<f32>> {
+        self.data.chunks(self.width).map(|row| row.to_vec()).collect()
+    }
+
+    /// Uploads the heights unclamped to a single channel float texture,
+    /// without mipmaps.
+    pub fn to_texture(&self, display: &Display) -> Texture2d {
+        Texture2d::with_format(
+            display, self.to_rows(), UncompressedFloatFormat::F32, false
+        ).unwrap()
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * ((2.0 * p1) +
+           (p2 - p0) * t +
+           (2.0*p0 - 5.0*p1 + 4.0*p2 - p3) * t2 +
+           (3.0*p1 - p0 - 3.0*p2 + p3) * t3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A plane rising by 2 along x and 3 along z.
+    fn plane() -> Heightmap {
+        Heightmap::from_fn(8, 8, |x, z| 2.0 * x as f32 + 3.0 * z as f32 + 1.0)
+    }
+
+    fn assert_near(a: f32, b: f32, tolerance: f32) {
+        assert!((a - b).abs() <= tolerance, "{} is not within {} of {}", a, tolerance, b);
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_heightmaps_are_rejected() {
+        Heightmap::new(0, 4);
+    }
+
+    #[test]
+    fn sampling_hits_samples_and_interpolates_planes() {
+        let map = plane();
+        assert_eq!(map.sample(3.0, 5.0), map.get(3, 5));
+        assert_near(map.sample(2.25, 4.5), 2.0 * 2.25 + 3.0 * 4.5 + 1.0, 1.0e-5);
+        assert_near(map.sample_bicubic(2.25, 4.5), 2.0 * 2.25 + 3.0 * 4.5 + 1.0, 1.0e-4);
+        assert_near(map.sample_bicubic(4.0, 1.0), map.get(4, 1), 1.0e-5);
+    }
+
+    #[test]
+    fn bicubic_is_smooth_across_samples() {
+        let map = Heightmap::from_fn(8, 8, |x, z| ((x * 7 + z * 3) % 5) as f32);
+        let slope = |x: f32| (map.sample_bicubic(x + 1.0e-3, 2.5) - map.sample_bicubic(x - 1.0e-3, 2.5)) / 2.0e-3;
+        let linear = |x: f32| (map.sample(x + 1.0e-3, 2.5) - map.sample(x - 1.0e-3, 2.5)) / 2.0e-3;
+
+        // the slope of the bicubic surface is continuous at x = 3, where
+        // the bilinear one kinks
+        assert_near(slope(3.0 - 0.01), slope(3.0 + 0.01), 0.2);
+        assert!((linear(3.0 - 0.01) - linear(3.0 + 0.01)).abs() > 0.5);
+    }
+
+    #[test]
+    fn gradient_matches_finite_differences() {
+        let map = Heightmap::from_fn(8, 8, |x, z| (x as f32 * 0.8).sin() * (z as f32 * 0.5).cos());
+        let h = 1.0e-3;
+        for &(x, z) in &[(1.3, 2.6), (4.7, 0.2), (6.1, 5.9)] {
+            let (dx, dz) = map.gradient(x, z);
+            assert_near(dx, (map.sample(x + h, z) - map.sample(x - h, z)) / (2.0 * h), 1.0e-2);
+            assert_near(dz, (map.sample(x, z + h) - map.sample(x, z - h)) / (2.0 * h), 1.0e-2);
+        }
+
+        let (dx, dz) = plane().gradient(3.4, 2.2);
+        assert_near(dx, 2.0, 1.0e-5);
+        assert_near(dz, 3.0, 1.0e-5);
+
+        let n = plane().normal(3.4, 2.2);
+        assert_near(n.x, -2.0 / 14.0f32.sqrt(), 1.0e-5);
+        assert_near(n.y,  1.0 / 14.0f32.sqrt(), 1.0e-5);
+        assert_near(plane().slope(1.0, 1.0), 13.0f32.sqrt().atan(), 1.0e-5);
+    }
+
+    #[test]
+    fn addressing_modes() {
+        let clamped = plane();
+        assert_eq!(clamped.texel(-3, 2), clamped.get(0, 2));
+        assert_eq!(clamped.texel(11, 9), clamped.get(7, 7));
+        assert_eq!(clamped.sample(-5.0, 3.0), clamped.get(0, 3));
+
+        let wrapped = plane().with_addressing(Addressing::Wrap);
+        assert_eq!(wrapped.texel(-1, 2), wrapped.get(7, 2));
+        assert_eq!(wrapped.texel(8, -8), wrapped.get(0, 0));
+        assert_eq!(wrapped.texel(-17, 19), wrapped.get(7, 3));
+        // between the last and first samples when wrapped
+        assert_near(wrapped.sample(7.5, 0.0), 0.5 * (wrapped.get(7, 0) + wrapped.get(0, 0)), 1.0e-5);
+    }
+}
@@ -0,0 +1,576 @@
+//! Loading and saving heightmaps as grayscale PGM and PNG images, raw
+//! little endian f32 or u16 samples, and ESRI ASCII grids. The PNG decoder
+//! also reads color images for textures.
+//!
+//! Integer samples are mapped to [0, 1] before being scaled, floating point
+//! samples are scaled directly, so height = sample * scale + offset.
+
+use ::Heightmap;
+use ::flate2::Compression;
+use ::flate2::read::ZlibDecoder;
+use ::flate2::write::ZlibEncoder;
+use ::std::io::{self, Read, Write, BufRead, BufReader};
+use ::std::fs::File;
+use ::std::path::Path;
+use ::std::fmt;
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    Io(io::Error),
+    /// The data is not in the expected format, or uses an unsupported
+    /// feature of it.
+    Format(String)
+}
+
+impl From<io::Error> for HeightmapError {
+    fn from(err: io::Error) -> HeightmapError {
+        HeightmapError::Io(err)
+    }
+}
+
+impl fmt::Display for HeightmapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeightmapError::Io(ref err)     => write!(f, "heightmap io error: {}", err),
+            HeightmapError::Format(ref msg) => write!(f, "invalid heightmap: {}", msg)
+        }
+    }
+}
+
+fn format_error<T>(msg: &str) -> Result<T, HeightmapError> {
+    Err(HeightmapError::Format(msg.to_string()))
+}
+
+fn check_size(width: usize, depth: usize) -> Result<(), HeightmapError> {
+    if width == 0 || depth == 0 { format_error("heightmap has no samples") } else { Ok(()) }
+}
+
+/// Maps stored samples to world heights.
+#[derive(Copy, Clone)]
+pub struct HeightScale {
+    pub scale  : f32,
+    pub offset : f32
+}
+
+impl HeightScale {
+    pub fn new(scale: f32, offset: f32) -> HeightScale {
+        HeightScale { scale : scale, offset : offset }
+    }
+
+    pub fn identity() -> HeightScale {
+        HeightScale::new(1.0, 0.0)
+    }
+
+    pub fn apply(&self, sample: f32) -> f32 {
+        sample * self.scale + self.offset
+    }
+
+    /// Inverse of apply.
+    pub fn remove(&self, height: f32) -> f32 {
+        (height - self.offset) / self.scale
+    }
+
+    fn to_unit_u16(&self, height: f32) -> u16 {
+        (self.remove(height).max(0.0).min(1.0) * 65535.0).round() as u16
+    }
+}
+
+/// Loads a heightmap picking the format from the file extension: pgm, png
+/// or asc.
+pub fn load<P: AsRef<Path>>(path: P, scale: HeightScale) -> Result<Heightmap, HeightmapError> {
+    let path = path.as_ref();
+    let file = try!(File::open(path));
+    let ext  = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+
+    match &ext[..] {
+        "pgm" => load_pgm(file, scale),
+        "png" => load_png(file, scale),
+        "asc" => load_esri_ascii(file, scale),
+        _     => format_error("unknown heightmap file extension")
+    }
+}
+
+fn read_all<R: Read>(mut reader: R) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    try!(reader.read_to_end(&mut bytes));
+    Ok(bytes)
+}
+
+/// Loads a binary (P5) or plain (P2) 8 or 16 bit PGM image.
+pub fn load_pgm<R: Read>(reader: R, scale: HeightScale) -> Result<Heightmap, HeightmapError> {
+    let bytes = try!(read_all(reader));
+
+    // header: magic, width, height and maxval separated by whitespace
+    // with # starting comments
+    let mut fields = vec![];
+    let mut pos    = 0;
+    while fields.len() < 4 {
+        while pos < bytes.len() && (bytes[pos] as char).is_whitespace() {
+            pos += 1;
+        }
+        if pos < bytes.len() && bytes[pos] == b'#' {
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while pos < bytes.len() && !(bytes[pos] as char).is_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return format_error("truncated PGM header");
+        }
+        fields.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+    }
+    pos += 1;
+
+    let binary = match &fields[0][..] {
+        "P5" => true,
+        "P2" => false,
+        _    => return format_error("not a grayscale PGM image")
+    };
+    let parse = |s: &String| s.parse::<usize>().ok();
+    let (width, depth, maxval) = match (parse(&fields[1]), parse(&fields[2]), parse(&fields[3])) {
+        (Some(w), Some(h), Some(m)) if m > 0 && m < 65536 => (w, h, m),
+        _ => return format_error("invalid PGM header")
+    };
+
+    let count   = width * depth;
+    let samples: Vec<u32> = if binary {
+        let size = if maxval > 255 { 2 } else { 1 };
+        if bytes.len() < pos + count * size {
+            return format_error("truncated PGM data");
+        }
+        (0..count).map(|i| {
+            let at = pos + i * size;
+            if size == 2 {
+                (bytes[at] as u32) << 8 | bytes[at + 1] as u32
+            } else {
+                bytes[at] as u32
+            }
+        }).collect()
+    }
+    else {
+        let text = String::from_utf8_lossy(&bytes[pos..]).into_owned();
+        let values: Vec<u32> = text.split_whitespace()
+            .filter_map(|s| s.parse().ok())
+            .collect();
+        if values.len() < count {
+            return format_error("truncated PGM data");
+        }
+        values
+    };
+
+    try!(check_size(width, depth));
+    Ok(Heightmap::from_fn(width, depth, |x, z| {
+        scale.apply(samples[z * width + x] as f32 / maxval as f32)
+    }))
+}
+
+/// Saves a binary 16 bit PGM image.
+pub fn save_pgm<W: Write>(
+    heightmap: &Heightmap, mut writer: W, scale: HeightScale
+) -> Result<(), HeightmapError> {
+    try!(write!(writer, "P5\n{} {}\n65535\n", heightmap.width(), heightmap.depth()));
+
+    let mut bytes = Vec::with_capacity(heightmap.data().len() * 2);
+    for &h in heightmap.data() {
+        let v = scale.to_unit_u16(h);
+        bytes.push((v >> 8) as u8);
+        bytes.push(v as u8);
+    }
+    try!(writer.write_all(&bytes));
+    Ok(())
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p  = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// Pixels of a decoded PNG image, row by row from the top, with every
+/// sample mapped to [0, 1].
+pub struct PngImage {
+    pub width    : usize,
+    pub height   : usize,
+    /// Samples per pixel, 1 for gray, 2 for gray and alpha, 3 for RGB and 4
+    /// for RGBA.
+    pub channels : usize,
+    pub samples  : Vec<f32>
+}
+
+/// Decodes a non-interlaced 8 or 16 bit PNG image without a palette.
+pub fn decode_png<R: Read>(reader: R) -> Result<PngImage, HeightmapError> {
+    let bytes = try!(read_all(reader));
+    if bytes.len() < 8 || &bytes[0..8] != b"\x89PNG\r\n\x1a\n" {
+        return format_error("not a PNG image");
+    }
+
+    let mut header = None;
+    let mut data   = vec![];
+    let mut pos    = 8;
+    while pos + 8 <= bytes.len() {
+        let length = be_u32(&bytes[pos..]) as usize;
+        let kind   = &bytes[pos + 4..pos + 8];
+        if pos + 12 + length > bytes.len() {
+            return format_error("truncated PNG chunk");
+        }
+        let body = &bytes[pos + 8..pos + 8 + length];
+
+        if kind == b"IHDR" && length >= 13 {
+            header = Some((
+                be_u32(&body[0..]) as usize, be_u32(&body[4..]) as usize,
+                body[8], body[9], body[12]
+            ));
+        }
+        else if kind == b"IDAT" {
+            data.extend(body.iter().cloned());
+        }
+        else if kind == b"IEND" {
+            break;
+        }
+        pos += 12 + length;
+    }
+
+    let (width, depth, bit_depth, color_type, interlace) = match header {
+        Some(h) => h,
+        None    => return format_error("PNG image has no header")
+    };
+    let channels = match color_type {
+        0 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => return format_error("palette PNG images are not supported")
+    };
+    if interlace != 0 || (bit_depth != 8 && bit_depth != 16) {
+        return format_error("only non-interlaced 8 or 16 bit PNG is supported");
+    }
+
+    let raw    = try!(read_all(ZlibDecoder::new(&data[..])));
+    let size   = (bit_depth / 8) as usize;
+    let bpp    = size * channels;
+    let stride = width * bpp;
+    if raw.len() < (stride + 1) * depth {
+        return format_error("truncated PNG data");
+    }
+
+    // undo the per row filters
+    let mut pixels = vec![0u8; stride * depth];
+    for row in 0..depth {
+        let filter = raw[row * (stride + 1)];
+        let line   = &raw[row * (stride + 1) + 1..(row + 1) * (stride + 1)];
+
+        for i in 0..stride {
+            let a = if i >= bpp { pixels[row * stride + i - bpp] } else { 0 };
+            let b = if row > 0 { pixels[(row - 1) * stride + i] } else { 0 };
+            let c = if i >= bpp && row > 0 { pixels[(row - 1) * stride + i - bpp] } else { 0 };
+
+            pixels[row * stride + i] = match filter {
+                0 => line[i],
+                1 => line[i].wrapping_add(a),
+                2 => line[i].wrapping_add(b),
+                3 => line[i].wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                4 => line[i].wrapping_add(paeth(a, b, c)),
+                _ => return format_error("invalid PNG filter")
+            };
+        }
+    }
+
+    let samples = if size == 2 {
+        pixels.chunks(2).map(|v| ((v[0] as u32) << 8 | v[1] as u32) as f32 / 65535.0).collect()
+    } else {
+        pixels.iter().map(|&v| v as f32 / 255.0).collect()
+    };
+
+    Ok(PngImage { width : width, height : depth, channels : channels, samples : samples })
+}
+
+/// Loads a non-interlaced 8 or 16 bit grayscale PNG image, ignoring any
+/// alpha channel.
+pub fn load_png<R: Read>(reader: R, scale: HeightScale) -> Result<Heightmap, HeightmapError> {
+    let image = try!(decode_png(reader));
+    if image.channels > 2 {
+        return format_error("only grayscale PNG heightmaps are supported");
+    }
+
+    try!(check_size(image.width, image.height));
+    Ok(Heightmap::from_fn(image.width, image.height, |x, z| {
+        scale.apply(image.samples[(z * image.width + x) * image.channels])
+    }))
+}
+
+fn write_png_chunk<W: Write>(writer: &mut W, kind: &[u8], body: &[u8]) -> io::Result<()> {
+    let length = body.len() as u32;
+    try!(writer.write_all(&[
+        (length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8
+    ]));
+
+    let mut checked = kind.to_vec();
+    checked.extend(body.iter().cloned());
+    try!(writer.write_all(&checked));
+
+    let crc = crc32(&checked);
+    writer.write_all(&[(crc >> 24) as u8, (crc >> 16) as u8, (crc >> 8) as u8, crc as u8])
+}
+
+/// Saves a 16 bit grayscale PNG image.
+pub fn save_png<W: Write>(
+    heightmap: &Heightmap, mut writer: W, scale: HeightScale
+) -> Result<(), HeightmapError> {
+    let (width, depth) = (heightmap.width() as u32, heightmap.depth() as u32);
+
+    let mut raw = vec![];
+    for z in 0..heightmap.depth() {
+        raw.push(0);
+        for x in 0..heightmap.width() {
+            let v = scale.to_unit_u16(heightmap.get(x, z));
+            raw.push((v >> 8) as u8);
+            raw.push(v as u8);
+        }
+    }
+    let mut encoder = ZlibEncoder::new(vec![], Compression::Default);
+    try!(encoder.write_all(&raw));
+    let compressed = try!(encoder.finish());
+
+    let header = [
+        (width >> 24) as u8, (width >> 16) as u8, (width >> 8) as u8, width as u8,
+        (depth >> 24) as u8, (depth >> 16) as u8, (depth >> 8) as u8, depth as u8,
+        16, 0, 0, 0, 0
+    ];
+
+    try!(writer.write_all(b"\x89PNG\r\n\x1a\n"));
+    try!(write_png_chunk(&mut writer, b"IHDR", &header));
+    try!(write_png_chunk(&mut writer, b"IDAT", &compressed));
+    try!(write_png_chunk(&mut writer, b"IEND", &[]));
+    Ok(())
+}
+
+/// Loads width x depth little endian f32 samples.
+pub fn load_raw_f32<R: Read>(
+    reader: R, width: usize, depth: usize, scale: HeightScale
+) -> Result<Heightmap, HeightmapError> {
+    let bytes = try!(read_all(reader));
+    if bytes.len() < width * depth * 4 {
+        return format_error("truncated raw f32 data");
+    }
+
+    try!(check_size(width, depth));
+    Ok(Heightmap::from_fn(width, depth, |x, z| {
+        let b    = &bytes[(z * width + x) * 4..];
+        let bits = b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24;
+        scale.apply(f32::from_bits(bits))
+    }))
+}
+
+/// Saves the heights as little endian f32 samples.
+pub fn save_raw_f32<W: Write>(
+    heightmap: &Heightmap, mut writer: W, scale: HeightScale
+) -> Result<(), HeightmapError> {
+    let mut bytes = Vec::with_capacity(heightmap.data().len() * 4);
+    for &h in heightmap.data() {
+        let bits = scale.remove(h).to_bits();
+        bytes.extend([bits as u8, (bits >> 8) as u8, (bits >> 16) as u8, (bits >> 24) as u8].iter().cloned());
+    }
+    try!(writer.write_all(&bytes));
+    Ok(())
+}
+
+/// Loads width x depth little endian u16 samples.
+pub fn load_raw_u16<R: Read>(
+    reader: R, width: usize, depth: usize, scale: HeightScale
+) -> Result<Heightmap, HeightmapError> {
+    let bytes = try!(read_all(reader));
+    if bytes.len() < width * depth * 2 {
+        return format_error("truncated raw u16 data");
+    }
+
+    try!(check_size(width, depth));
+    Ok(Heightmap::from_fn(width, depth, |x, z| {
+        let at = (z * width + x) * 2;
+        let v  = bytes[at] as u32 | (bytes[at + 1] as u32) << 8;
+        scale.apply(v as f32 / 65535.0)
+    }))
+}
+
+/// Saves the heights as little endian u16 samples.
+pub fn save_raw_u16<W: Write>(
+    heightmap: &Heightmap, mut writer: W, scale: HeightScale
+) -> Result<(), HeightmapError> {
+    let mut bytes = Vec::with_capacity(heightmap.data().len() * 2);
+    for &h in heightmap.data() {
+        let v = scale.to_unit_u16(h);
+        bytes.push(v as u8);
+        bytes.push((v >> 8) as u8);
+    }
+    try!(writer.write_all(&bytes));
+    Ok(())
+}
+
+/// Loads an ESRI ASCII grid. The first row of the grid is z = 0 and no data
+/// cells are given the lowest height in the grid.
+pub fn load_esri_ascii<R: Read>(reader: R, scale: HeightScale) -> Result<Heightmap, HeightmapError> {
+    let mut width   = None;
+    let mut depth   = None;
+    let mut no_data = None;
+    let mut values  = vec![];
+
+    for line in BufReader::new(reader).lines() {
+        let line  = try!(line);
+        let mut words = line.split_whitespace();
+        let first = match words.next() {
+            Some(word) => word,
+            None       => continue
+        };
+
+        match &first.to_lowercase()[..] {
+            "ncols"        => width   = words.next().and_then(|w| w.parse::<usize>().ok()),
+            "nrows"        => depth   = words.next().and_then(|w| w.parse::<usize>().ok()),
+            "nodata_value" => no_data = words.next().and_then(|w| w.parse::<f32>().ok()),
+            "xllcorner" | "yllcorner" | "xllcenter" | "yllcenter" | "cellsize" => (),
+            _ => {
+                for word in Some(first).into_iter().chain(words) {
+                    match word.parse::<f32>() {
+                        Ok(v)  => values.push(v),
+                        Err(_) => return format_error("invalid value in ESRI grid")
+                    }
+                }
+            }
+        }
+    }
+
+    let (width, depth) = match (width, depth) {
+        (Some(w), Some(d)) => (w, d),
+        _ => return format_error("ESRI grid is missing ncols or nrows")
+    };
+    if values.len() < width * depth {
+        return format_error("truncated ESRI grid");
+    }
+
+    let is_data = |v: f32| no_data.map_or(true, |n| v != n);
+    let lowest  = values.iter().cloned().filter(|&v| is_data(v)).fold(::std::f32::INFINITY, |a, b| a.min(b));
+    let lowest  = if lowest.is_finite() { lowest } else { 0.0 };
+
+    try!(check_size(width, depth));
+    Ok(Heightmap::from_fn(width, depth, |x, z| {
+        let v = values[z * width + x];
+        scale.apply(if is_data(v) { v } else { lowest })
+    }))
+}
+
+/// Saves an ESRI ASCII grid with unit cells and its corner at the origin.
+pub fn save_esri_ascii<W: Write>(
+    heightmap: &Heightmap, mut writer: W, scale: HeightScale
+) -> Result<(), HeightmapError> {
+    try!(write!(writer, "ncols {}\nnrows {}\nxllcorner 0\nyllcorner 0\ncellsize 1\n",
+                heightmap.width(), heightmap.depth()));
+
+    for z in 0..heightmap.depth() {
+        let row: Vec<String> = (0..heightmap.width())
+            .map(|x| format!("{}", scale.remove(heightmap.get(x, z))))
+            .collect();
+        try!(write!(writer, "{}\n", row.join(" ")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::Heightmap;
+
+    fn terrain() -> Heightmap {
+        Heightmap::from_fn(13, 7, |x, z| {
+            100.0 + 40.0 * ((x as f32 * 0.7).sin() + (z as f32 * 0.3).cos())
+        })
+    }
+
+    fn assert_close(a: &Heightmap, b: &Heightmap, tolerance: f32) {
+        assert_eq!((a.width(), a.depth()), (b.width(), b.depth()));
+        for (i, (&x, &y)) in a.data().iter().zip(b.data().iter()).enumerate() {
+            assert!((x - y).abs() <= tolerance, "sample {} was {} not {}", i, y, x);
+        }
+    }
+
+    /// Maps the 20 to 180 height range of terrain onto [0, 1].
+    fn unit_scale() -> HeightScale {
+        HeightScale::new(160.0, 20.0)
+    }
+
+    #[test]
+    fn pgm_round_trip() {
+        let mut bytes = vec![];
+        save_pgm(&terrain(), &mut bytes, unit_scale()).unwrap();
+        let loaded = load_pgm(&bytes[..], unit_scale()).unwrap();
+        assert_close(&terrain(), &loaded, 160.0 / 65535.0);
+    }
+
+    #[test]
+    fn ascii_pgm_with_comments() {
+        let text = b"P2\n# a comment\n3 2\n# another\n10\n0 5 10\n10 5 0\n";
+        let loaded = load_pgm(&text[..], HeightScale::identity()).unwrap();
+        assert_eq!(loaded.data(), &[0.0, 0.5, 1.0, 1.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let mut bytes = vec![];
+        save_png(&terrain(), &mut bytes, unit_scale()).unwrap();
+        let loaded = load_png(&bytes[..], unit_scale()).unwrap();
+        assert_close(&terrain(), &loaded, 160.0 / 65535.0);
+    }
+
+    #[test]
+    fn raw_round_trips() {
+        let mut bytes = vec![];
+        save_raw_f32(&terrain(), &mut bytes, HeightScale::identity()).unwrap();
+        assert_eq!(bytes.len(), 13 * 7 * 4);
+        let loaded = load_raw_f32(&bytes[..], 13, 7, HeightScale::identity()).unwrap();
+        assert_eq!(terrain().data(), loaded.data());
+
+        let mut bytes = vec![];
+        save_raw_u16(&terrain(), &mut bytes, unit_scale()).unwrap();
+        let loaded = load_raw_u16(&bytes[..], 13, 7, unit_scale()).unwrap();
+        assert_close(&terrain(), &loaded, 160.0 / 65535.0);
+    }
+
+    #[test]
+    fn esri_ascii_round_trip() {
+        let mut bytes = vec![];
+        save_esri_ascii(&terrain(), &mut bytes, HeightScale::identity()).unwrap();
+        let loaded = load_esri_ascii(&bytes[..], HeightScale::identity()).unwrap();
+        assert_close(&terrain(), &loaded, 1.0e-4);
+    }
+
+    #[test]
+    fn truncated_data_is_an_error() {
+        let mut bytes = vec![];
+        save_pgm(&terrain(), &mut bytes, unit_scale()).unwrap();
+        bytes.pop();
+        match load_pgm(&bytes[..], unit_scale()) {
+            Err(HeightmapError::Format(_)) => (),
+            other => panic!("expected a format error, got {:?}", other.map(|_| ()))
+        }
+        assert!(load_raw_f32(&[0u8; 10][..], 2, 2, HeightScale::identity()).is_err());
+        assert!(load_pgm(&b"P2 0 3 255 "[..], HeightScale::identity()).is_err());
+    }
+}
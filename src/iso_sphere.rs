@@ -1,7 +1,7 @@
 use ::{
-    Vertex, BuildRenderable, RenderableIndices
+    Vertex, BuildRenderable, RenderableIndices, Mesh
 };
-use ::nalgebra::{Vec3, Norm};
+use ::nalgebra::{Vec3, Norm, cross, dot};
 use ::glium::{VertexBuffer, Display};
 use ::glium::index::{PrimitiveType};
 use ::num::Float;
@@ -13,11 +13,14 @@ struct Face {
 }
 
 impl Face {
+    /// Creates a face wound counter-clockwise when seen from outside the
+    /// sphere, regardless of the order of the vertices.
     fn from_vec3(v1: Vec3<f32>, v2: Vec3<f32>, v3: Vec3<f32>) -> Face {
+        let outward = dot(&cross(&(v2 - v1), &(v3 - v1)), &(v1 + v2 + v3)) > 0.0;
         Face {
             v1: v1,
-            v2: v2,
-            v3: v3
+            v2: if outward { v2 } else { v3 },
+            v3: if outward { v3 } else { v2 }
         }
     }
 }
@@ -47,6 +50,13 @@ impl IsoSphere {
         vertices
     }
 
+    /// Returns the sphere as an indexed mesh with shared vertices.
+    pub fn to_mesh(&self) -> Mesh {
+        let vertices = self.faces_to_vertex_array();
+        let indices  = (0..vertices.len() as u32).collect();
+        Mesh::from_parts(vertices, indices).weld(1.0e-6)
+    }
+
     fn vertex_from_vec(vec: Vec3<f32>) -> Vertex {
         let mut vert = Vertex::from_position(vec.x, vec.y, vec.z);
         vert.normal = [vec.x, vec.y, vec.z];
@@ -3,37 +3,86 @@ extern crate nalgebra;
 extern crate glium;
 extern crate glutin;
 extern crate num;
+extern crate flate2;
 
 use glium::{
-    IndexBuffer, VertexBuffer, Display
+    IndexBuffer, VertexBuffer, Display, Frame, Surface, Program, DrawParameters
 };
 use glium::index::{
-    PrimitiveType
+    PrimitiveType, NoIndices
 };
+use glium::uniforms::Uniforms;
 use glutin::{Event, VirtualKeyCode};
 
 mod grid;
 mod camera;
 mod iso_sphere;
 mod lighting;
+mod mesh;
+mod bezier;
+mod teapot;
+mod lod;
+mod terrain;
+mod heightmap;
+mod random;
+mod streaming;
+mod ripple;
+mod gerstner;
+mod ocean;
+mod wave_field;
+mod skybox;
+mod sky;
+mod fog;
+pub mod primitives;
+pub mod subdivision;
+pub mod half_edge;
+pub mod simplify;
+pub mod validation;
+pub mod noise;
+pub mod heightmap_io;
+pub mod erosion;
+pub mod raycast;
 
-pub use grid::Grid;
+pub use grid::{Grid, Triangulation};
 pub use camera::FreeCamera;
 pub use iso_sphere::IsoSphere;
 pub use lighting::LightingRenderer;
 pub use lighting::NormalRenderer;
+pub use mesh::Mesh;
+pub use bezier::{BezierPatch, BezierSurface};
+pub use teapot::Teapot;
+pub use lod::LodMesh;
+pub use terrain::{
+    Terrain, TerrainRenderer, HeightmapGenerator, TerrainMeshCenter, TerrainRing,
+    TerrainTrim, TerrainMaterials, wrap_to_size, HEIGHTMAP_SIZE, CLIPMAP_BLOCK
+};
+pub use heightmap::{Heightmap, Addressing};
+pub use random::Rng;
+pub use streaming::{TerrainStream, TileSource, NoiseTiles};
+pub use ripple::{RippleRenderer, RippleEmitter, MAX_EMITTERS};
+pub use gerstner::{GerstnerRenderer, GerstnerSurface, GerstnerWave, MAX_WAVES};
+pub use ocean::{Ocean, OceanSpectrum, OceanRenderer};
+pub use wave_field::{WaveField, GpuWaveField, WaveFieldRenderer, Boundary};
+pub use skybox::{SkyboxRenderer, Environment, EnvironmentKind, ImageError};
+pub use sky::{Sky, PerezCoefficients};
+pub use fog::{Fog, FogMode};
 
 #[derive(Copy, Clone)]
 pub struct Vertex {
-    pub position : [f32; 3],
-    pub normal   : [f32; 3]
+    pub position  : [f32; 3],
+    pub normal    : [f32; 3],
+    pub tex_coord : [f32; 2]
 }
 
-implement_vertex!(Vertex, position, normal);
+implement_vertex!(Vertex, position, normal, tex_coord);
 
 impl Vertex {
+    pub fn new(position: [f32; 3], normal: [f32; 3], tex_coord: [f32; 2]) -> Vertex {
+        Vertex { position : position, normal : normal, tex_coord : tex_coord }
+    }
+
     pub fn from_position(x: f32, y: f32, z: f32) -> Vertex {
-        Vertex { position : [x, y, z], normal : [0.0, 0.0, 0.0] }
+        Vertex::new([x, y, z], [0.0, 0.0, 0.0], [0.0, 0.0])
     }
 }
 
@@ -42,6 +91,53 @@ pub enum RenderableIndices {
     Buffer(IndexBuffer)
 }
 
+impl RenderableIndices {
+    /// Creates a triangle list index buffer for a mesh with vertex_count
+    /// vertices. See build_index_buffer.
+    pub fn from_triangles(
+        display: &Display, vertex_count: usize, indices: Vec<u32>
+    ) -> RenderableIndices {
+        RenderableIndices::Buffer(
+            build_index_buffer(display, vertex_count, indices)
+        )
+    }
+}
+
+/// Largest vertex count which can be addressed with u16 indices.
+pub const MAX_U16_VERTICES : usize = 65536;
+
+/// Indices in the narrowest type able to address every vertex.
+#[derive(Clone, PartialEq, Debug)]
+pub enum IndexData {
+    U16(Vec<u16>),
+    U32(Vec<u32>)
+}
+
+impl IndexData {
+    /// Stores indices as u16 when every one of vertex_count vertices can be
+    /// addressed with 16 bits and as u32 otherwise.
+    pub fn new(vertex_count: usize, indices: Vec<u32>) -> IndexData {
+        if vertex_count <= MAX_U16_VERTICES {
+            IndexData::U16(indices.iter().map(|&i| i as u16).collect())
+        }
+        else {
+            IndexData::U32(indices)
+        }
+    }
+}
+
+/// Uploads a triangle list with the index type chosen by IndexData::new.
+pub fn build_index_buffer(
+    display: &Display, vertex_count: usize, indices: Vec<u32>
+) -> IndexBuffer {
+    match IndexData::new(vertex_count, indices) {
+        IndexData::U16(short) =>
+            IndexBuffer::from_raw(display, short, PrimitiveType::TrianglesList),
+        IndexData::U32(long) =>
+            IndexBuffer::from_raw(display, long, PrimitiveType::TrianglesList)
+    }
+}
+
 pub trait BuildRenderable {
     fn get_vertex_array(&self, display: &Display) -> VertexBuffer<Vertex>;
     fn get_indices(&self, display: &Display) -> RenderableIndices;
@@ -59,6 +155,39 @@ impl RenderableObj {
             indices  : obj.get_indices(&display)
         }
     }
+
+    /// Draws the object with program, with or without its index buffer.
+    pub fn draw<U: Uniforms>(
+        &self, frame: &mut Frame, program: &Program, uniforms: &U, params: &DrawParameters
+    ) {
+        match self.indices {
+            RenderableIndices::None(primitive) => {
+                frame.draw(
+                    &self.vertices,
+                    &NoIndices(primitive),
+                    program, uniforms,
+                    params
+                ).unwrap();
+            },
+            RenderableIndices::Buffer(ref buffer) => {
+                frame.draw(
+                    &self.vertices,
+                    buffer,
+                    program, uniforms,
+                    params
+                ).unwrap();
+            }
+        }
+    }
+}
+
+/// How the Controller moves the camera.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MoveMode {
+    /// Move freely in the direction the camera looks.
+    Fly,
+    /// Walk along the ground, falling under gravity.
+    Walk
 }
 
 pub struct Controller {
@@ -68,8 +197,20 @@ pub struct Controller {
     pub back       : bool,
     pub left       : bool,
     pub right      : bool,
+    pub jump       : bool,
     pub move_speed : f32,
     pub rot_speed  : f32,
+    pub mode       : MoveMode,
+    /// Height of the camera above the ground.
+    pub eye_height : f32,
+    /// Acceleration downwards, in units per frame per frame.
+    pub gravity    : f32,
+    /// Upward speed, in units per frame, at the start of a jump.
+    pub jump_speed : f32,
+    /// Steepest slope, in radians, which can be walked up.
+    pub max_slope  : f32,
+    velocity_y     : f32,
+    grounded       : bool,
     center_x       : i32,
     center_y       : i32
 }
@@ -83,13 +224,20 @@ impl Controller {
             back       : false,
             left       : false,
             right      : false,
+            jump       : false,
             move_speed : 1.0,
             rot_speed  : 1.0,
+            mode       : MoveMode::Fly,
+            eye_height : 5.0,
+            gravity    : 0.02,
+            jump_speed : 0.5,
+            max_slope  : 0.8,
+            velocity_y : 0.0,
+            grounded   : false,
             center_x   : 0,
             center_y   : 0
         }
     }
-
     pub fn process_event(&mut self, event: &Event) {
         match *event {
             Event::Resized(w, h) => {
@@ -113,6 +261,14 @@ impl Controller {
                     VirtualKeyCode::O     => self.back  = pressed,
                     VirtualKeyCode::A     => self.left  = pressed,
                     VirtualKeyCode::E     => self.right = pressed,
+                    VirtualKeyCode::Space => self.jump  = pressed,
+                    VirtualKeyCode::F if pressed => {
+                        self.mode = match self.mode {
+                            MoveMode::Fly  => MoveMode::Walk,
+                            MoveMode::Walk => MoveMode::Fly
+                        };
+                        self.velocity_y = 0.0;
+                    },
                     _ => ()
                 }
             }
@@ -121,6 +277,11 @@ impl Controller {
     }
 
     pub fn update(&self, camera: &mut FreeCamera, display: &Display) {
+        self.fly(camera);
+        self.look_around(camera, display);
+    }
+
+    fn fly(&self, camera: &mut FreeCamera) {
         if self.front {
             camera.advance(self.move_speed);
         }
@@ -133,6 +294,66 @@ impl Controller {
         if self.left {
             camera.strafe(-self.move_speed);
         }
+    }
+
+    /// Moves the camera over the terrain, walking on it or flying without
+    /// going below eye height depending on the mode.
+    pub fn update_on_ground(
+        &mut self, camera: &mut FreeCamera, display: &Display, ground: &Heightmap
+    ) {
+        self.move_on_ground(camera, ground);
+        self.look_around(camera, display);
+    }
+
+    /// The movement and physics of update_on_ground for one frame, without
+    /// turning the camera.
+    pub fn move_on_ground(&mut self, camera: &mut FreeCamera, ground: &Heightmap) {
+        if self.mode == MoveMode::Fly {
+            self.fly(camera);
+
+            let lowest = ground.sample(camera.pos.x, camera.pos.z) + self.eye_height;
+            camera.pos.y = camera.pos.y.max(lowest);
+            return;
+        }
+
+        let axis    = |positive: bool, negative: bool| (positive as i32 - negative as i32) as f32;
+        let forward = axis(self.front, self.back);
+        let side    = axis(self.right, self.left);
+        let scale   = if forward != 0.0 && side != 0.0 { 0.5f32.sqrt() } else { 1.0 };
+
+        let before = camera.pos;
+        camera.walk(forward * scale * self.move_speed, side * scale * self.move_speed);
+
+        // refuse to climb slopes which are too steep, walking down is fine
+        let climb = ground.sample(camera.pos.x, camera.pos.z) - ground.sample(before.x, before.z);
+        let steep = ground.slope(camera.pos.x, camera.pos.z) > self.max_slope;
+        if self.grounded && climb > 0.0 && steep {
+            camera.pos.x = before.x;
+            camera.pos.z = before.z;
+        }
+
+        if self.jump && self.grounded {
+            self.velocity_y = self.jump_speed;
+        }
+        self.velocity_y -= self.gravity;
+        camera.pos.y += self.velocity_y;
+
+        // stay on the ground walking down a slope, as long as it drops no
+        // more in one step than the steepest walkable slope
+        let snap = if self.grounded && self.velocity_y <= 0.0 {
+            self.move_speed * self.max_slope.tan()
+        } else {
+            0.0
+        };
+        let standing = ground.sample(camera.pos.x, camera.pos.z) + self.eye_height;
+        self.grounded = camera.pos.y <= standing + snap;
+        if self.grounded {
+            camera.pos.y    = standing;
+            self.velocity_y = 0.0;
+        }
+    }
+
+    fn look_around(&self, camera: &mut FreeCamera, display: &Display) {
         camera.rotate_up(self.ry * self.rot_speed);
         camera.rotate_left(-self.rx * self.rot_speed);
 
@@ -141,3 +362,123 @@ impl Controller {
             .set_cursor_position(self.center_x, self.center_y);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Pnt3;
+
+    fn walker(ground: &Heightmap, x: f32, z: f32) -> (Controller, FreeCamera) {
+        let mut controller = Controller::new();
+        controller.mode = MoveMode::Walk;
+
+        let mut camera = FreeCamera::new(1.0, 60.0, 0.1, 100.0);
+        camera.pos = Pnt3::new(x, ground.sample(x, z) + controller.eye_height + 2.0, z);
+        (controller, camera)
+    }
+
+    fn land(controller: &mut Controller, camera: &mut FreeCamera, ground: &Heightmap) {
+        for _ in 0..100 {
+            controller.move_on_ground(camera, ground);
+        }
+    }
+
+    #[test]
+    fn walkers_fall_and_land_on_the_ground() {
+        let ground = Heightmap::from_fn(16, 16, |_, _| 1.0);
+        let (mut controller, mut camera) = walker(&ground, 8.0, 8.0);
+
+        let start = camera.pos.y;
+        controller.move_on_ground(&mut camera, &ground);
+        let first = camera.pos.y;
+        controller.move_on_ground(&mut camera, &ground);
+        assert!(first < start);
+        // falling speeds up under gravity
+        assert!(start - first < first - camera.pos.y);
+
+        land(&mut controller, &mut camera, &ground);
+        assert_eq!(camera.pos.y, 1.0 + controller.eye_height);
+        assert!(controller.grounded);
+    }
+
+    #[test]
+    fn jumps_leave_the_ground_and_come_back() {
+        let ground = Heightmap::from_fn(16, 16, |_, _| 0.0);
+        let (mut controller, mut camera) = walker(&ground, 8.0, 8.0);
+        land(&mut controller, &mut camera, &ground);
+
+        controller.jump = true;
+        controller.move_on_ground(&mut camera, &ground);
+        controller.jump = false;
+        assert!(camera.pos.y > controller.eye_height);
+        assert!(!controller.grounded);
+
+        land(&mut controller, &mut camera, &ground);
+        assert_eq!(camera.pos.y, controller.eye_height);
+    }
+
+    #[test]
+    fn steep_slopes_cannot_be_climbed() {
+        // a wall rising two units per sample towards -z, ahead of the camera
+        let ground = Heightmap::from_fn(16, 16, |_, z| if z < 8 { 2.0 * (8 - z) as f32 } else { 0.0 });
+        let (mut controller, mut camera) = walker(&ground, 8.0, 9.5);
+        controller.move_speed = 0.25;
+        land(&mut controller, &mut camera, &ground);
+
+        controller.front = true;
+        land(&mut controller, &mut camera, &ground);
+        assert!(camera.pos.z >= 8.0, "walked up the wall to z = {}", camera.pos.z);
+        assert_eq!(camera.pos.y, controller.eye_height);
+    }
+
+    #[test]
+    fn walkers_stay_on_the_ground_walking_down_slopes() {
+        // falling a quarter unit per sample towards -z, which is walkable
+        let ground = Heightmap::from_fn(16, 64, |_, z| 0.25 * z as f32);
+        let (mut controller, mut camera) = walker(&ground, 8.0, 60.0);
+        land(&mut controller, &mut camera, &ground);
+
+        controller.front = true;
+        for _ in 0..40 {
+            controller.move_on_ground(&mut camera, &ground);
+            assert!(controller.grounded, "left the ground at z = {}", camera.pos.z);
+            let standing = ground.sample(camera.pos.x, camera.pos.z) + controller.eye_height;
+            assert_eq!(camera.pos.y, standing);
+        }
+    }
+
+    #[test]
+    fn small_grids_use_u16_indices() {
+        let grid = Grid::new(1.0, 1.0, 10, 10);
+        match IndexData::new(grid.vertex_count(), grid.indices.clone()) {
+            IndexData::U16(short) => {
+                let widened: Vec<u32> = short.iter().map(|&i| i as u32).collect();
+                assert_eq!(widened, grid.indices);
+            },
+            IndexData::U32(_) => panic!("expected u16 indices")
+        }
+    }
+
+    #[test]
+    fn large_grids_keep_u32_indices() {
+        let grid = Grid::new(1.0, 1.0, 300, 300);
+        assert!(grid.vertex_count() > MAX_U16_VERTICES);
+
+        match IndexData::new(grid.vertex_count(), grid.indices.clone()) {
+            IndexData::U32(long) => {
+                assert_eq!(long, grid.indices);
+                assert_eq!(*long.iter().max().unwrap() as usize, grid.vertex_count() - 1);
+            },
+            IndexData::U16(_) => panic!("expected u32 indices")
+        }
+    }
+
+    #[test]
+    fn the_largest_u16_grid_addresses_every_vertex() {
+        let indices = vec![0, 1, (MAX_U16_VERTICES - 1) as u32];
+        assert_eq!(
+            IndexData::new(MAX_U16_VERTICES, indices),
+            IndexData::U16(vec![0, 1, 65535])
+        );
+    }
+}
@@ -1,8 +1,46 @@
-use ::nalgebra::{Vec3, Mat4, Iso3, to_homogeneous, Transformation, RotationMatrix};
-use ::{RenderableObj, RenderableIndices};
+use ::nalgebra::{Vec3, Pnt3, Mat4, Iso3, Norm, to_homogeneous, Transformation, Transform, Rotate, RotationMatrix};
+use ::RenderableObj;
+use ::skybox::{Environment, ENVIRONMENT};
+use ::fog::{Fog, FOG};
 use ::glium::{Program, Display, DrawParameters, DepthTest, Frame, Surface, PolygonMode};
 use ::glium::index::{NoIndices, PrimitiveType};
 
+/// GLSL for the Blinn-Phong reflection model shared by the renderers. Given
+/// the unit normal and the unit directions to the light and the viewer it
+/// returns the diffuse and specular factors.
+pub const BLINN_PHONG : &'static str = r#"
+    vec2 blinn_phong(vec3 N, vec3 L, vec3 V, float shininess) {
+        vec3 H = normalize(L + V);
+        float diffuse  = max(0.0, dot(N, L));
+        float specular = diffuse > 0.0 ? pow(max(0.0, dot(N, H)), shininess) : 0.0;
+        return vec2(diffuse, specular);
+    }
+"#;
+
+/// GLSL shading water as the water color lit by Blinn-Phong, mixed with
+/// the sky it reflects by Schlick's approximation of the Fresnel term.
+/// Needs BLINN_PHONG.
+pub const WATER : &'static str = r#"
+    vec3 water(vec3 N, vec3 L, vec3 V, vec3 water_color, vec3 sky_color,
+               vec3 specular_color, float shininess) {
+        // the reflectance of water seen head on
+        float f0      = 0.02;
+        float fresnel = f0 + (1.0 - f0) * pow(1.0 - max(0.0, dot(N, V)), 5.0);
+
+        vec2 light = blinn_phong(N, L, V, shininess);
+        vec3 color = mix(water_color * (0.3 + 0.7 * light.x), sky_color, fresnel);
+        return color + light.y * specular_color;
+    }
+"#;
+
+/// The eye position and light_direction in the model space of model, for
+/// shaders which light in model space. mv is the model view transform.
+pub fn model_space_lighting(
+    mv: &Iso3<f32>, model: &Iso3<f32>, light_direction: &Vec3<f32>
+) -> (Vec3<f32>, Vec3<f32>) {
+    let eye = mv.inv_transform(&Pnt3::new(0.0, 0.0, 0.0));
+    (Vec3::new(eye.x, eye.y, eye.z), model.inv_rotate(light_direction).normalize())
+}
 
 pub struct LightingRenderer {
     pub program        : Program,
@@ -10,7 +48,12 @@ pub struct LightingRenderer {
     pub diffuse_color  : Vec3<f32>,
     pub specular_color : Vec3<f32>,
     pub shininess      : f32,
-    pub wire           : bool
+    /// How much of the environment surfaces mirror, from 0 to 1.
+    pub reflectivity   : f32,
+    pub fog            : Fog,
+    pub wire           : bool,
+    /// Reflected by draw, when no environment is given.
+    environment        : Environment
 }
 
 impl LightingRenderer {
@@ -21,7 +64,10 @@ impl LightingRenderer {
             diffuse_color  : Vec3::new(1.0, 1.0, 1.0),
             specular_color : Vec3::new(1.0, 1.0, 1.0),
             shininess      : 128.0,
-            wire           : false
+            reflectivity   : 0.0,
+            fog            : Fog::none(),
+            wire           : false,
+            environment    : Environment::gradient(display)
         }
     }
 
@@ -29,11 +75,24 @@ impl LightingRenderer {
         &self, frame: &mut Frame,
         obj: &RenderableObj, proj: &Mat4<f32>, view: &Iso3<f32>, model: &Iso3<f32>
     )  {
+        self.draw_in_environment(frame, obj, proj, view, model, &self.environment);
+    }
+
+    /// Draws obj reflecting environment by reflectivity.
+    pub fn draw_in_environment(
+        &self, frame: &mut Frame,
+        obj: &RenderableObj, proj: &Mat4<f32>, view: &Iso3<f32>, model: &Iso3<f32>,
+        environment: &Environment
+    )  {
 
         let mv  = view.prepend_transformation(model);
         let mvp = *proj * to_homogeneous(&mv);
         let n   = *mv.to_rot_mat().submat();
 
+        // fog is measured in world space
+        let camera = view.inv_transform(&Pnt3::new(0.0, 0.0, 0.0));
+        let camera = Vec3::new(camera.x, camera.y, camera.z);
+
         let params = DrawParameters {
             depth_test   : DepthTest::IfLess,
             depth_write  : true,
@@ -48,27 +107,25 @@ impl LightingRenderer {
             light_position : self.light_position,
             diffuse_color  : self.diffuse_color,
             specular_color : self.specular_color,
-            shininess      : self.shininess
+            shininess      : self.shininess,
+            reflectivity   : self.reflectivity,
+            view_rotation  : *view.to_rot_mat().submat(),
+
+            camera_position : camera,
+            fog_mode        : self.fog.mode_index(),
+            fog_params      : self.fog.params(),
+            fog_color       : self.fog.color,
+
+            environment_map  : &environment.map,
+            environment_kind : environment.kind_index(),
+            zenith_color     : environment.zenith_color,
+            horizon_color    : environment.horizon_color,
+            ground_color     : environment.ground_color,
+            sun_direction    : environment.sun_direction,
+            sun_color        : environment.sun_color
         );
 
-        match obj.indices {
-            RenderableIndices::None(primitive) => {
-                frame.draw(
-                    &obj.vertices,
-                    &NoIndices(primitive),
-                    &self.program, &uniforms,
-                    &params
-                ).unwrap();
-            },
-            RenderableIndices::Buffer(ref buffer) => {
-                frame.draw(
-                    &obj.vertices,
-                    buffer,
-                    &self.program, &uniforms,
-                    &params
-                ).unwrap();
-            }
-        }
+        obj.draw(frame, &self.program, &uniforms, &params);
     }
 
     fn create_shader_program(display: &Display) -> Program {
@@ -90,9 +147,9 @@ impl LightingRenderer {
             }
         "#;
 
-        let fragment_shader_src = r#"
+        let fragment_shader_src = [r#"
             #version 330
-
+        "#, BLINN_PHONG, ENVIRONMENT, FOG, r#"
             smooth in vec3 eye_space_normal;
             smooth in vec3 eye_space_position;
             out vec4 vFragColor;
@@ -102,6 +159,9 @@ impl LightingRenderer {
             uniform vec3 specular_color;
             uniform mat4 MV;
             uniform float shininess;
+            uniform float reflectivity;
+            uniform mat3 view_rotation;
+            uniform vec3 camera_position;
 
             const vec3 eye_space_camera_pos = vec3(0,0,0);
 
@@ -111,17 +171,20 @@ impl LightingRenderer {
 
                 vec3 L = normalize(eye_space_light_pos - eye_space_position);
                 vec3 V = normalize(eye_space_camera_pos - eye_space_position);
-                vec3 H = normalize(L + V);
-                float diffuse  = max(0, dot(norm, L));
-                float specular = max(0, pow(dot(eye_space_normal, H), shininess));
+                vec2 light = blinn_phong(norm, L, V, shininess);
 
-                vFragColor = specular*vec4(specular_color, 1) + diffuse*vec4(diffuse_color, 1);
+                // the environment is looked up in world space
+                vec3 reflected = transpose(view_rotation) * reflect(-V, norm);
+                vec3 diffuse   = mix(light.x*diffuse_color, environment(reflected), reflectivity);
 
+                vec3 color  = light.y*specular_color + diffuse;
+                vec3 offset = transpose(view_rotation) * eye_space_position;
+                vFragColor  = vec4(apply_fog(color, camera_position, offset), 1);
             }
-        "#;
+        "#].concat();
 
         Program::from_source(
-            display, vertex_shader_src, fragment_shader_src, None
+            display, vertex_shader_src, &fragment_shader_src, None
         ).unwrap()
     }
 }
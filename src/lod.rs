@@ -0,0 +1,101 @@
+use ::{RenderableObj, FreeCamera, Mesh};
+use ::simplify::{simplify, SimplifyTarget};
+use ::nalgebra::{Iso3, Pnt3, Norm};
+use ::glium::Display;
+
+/// Returns mesh followed by level_count - 1 simplifications of it, each with
+/// half the triangles of the one before.
+fn simplified_levels(mesh: &Mesh, level_count: usize) -> Vec<Mesh> {
+    let mut levels = vec![mesh.clone()];
+    for _ in 1..level_count {
+        let target = levels[levels.len() - 1].triangle_count() / 2;
+        let next   = simplify(&levels[levels.len() - 1], SimplifyTarget::TriangleCount(target));
+        levels.push(next);
+    }
+    levels
+}
+
+/// A mesh stored at several levels of detail, from the most detailed to
+/// the least, along with the radius of a sphere around the model origin
+/// which bounds it.
+pub struct LodMesh {
+    pub levels      : Vec<RenderableObj>,
+    pub radius      : f32,
+    /// Projected height in pixels at which the most detailed level is used,
+    /// each following level is used at half the size of the previous one.
+    pub switch_size : f32
+}
+
+impl LodMesh {
+    pub fn new(levels: Vec<RenderableObj>, radius: f32) -> LodMesh {
+        assert!(!levels.is_empty(), "a LodMesh needs at least one level");
+        LodMesh {
+            levels      : levels,
+            radius      : radius,
+            switch_size : 400.0
+        }
+    }
+
+    /// Builds level_count levels from mesh, every level having half the
+    /// triangles of the previous one.
+    pub fn from_mesh(mesh: &Mesh, level_count: usize, display: &Display) -> LodMesh {
+        let radius = mesh.vertices.iter().fold(0.0f32, |r, v| {
+            let p = v.position;
+            r.max((p[0]*p[0] + p[1]*p[1] + p[2]*p[2]).sqrt())
+        });
+
+        let levels = simplified_levels(mesh, level_count).iter()
+            .map(|level| RenderableObj::new(level, display))
+            .collect();
+
+        LodMesh::new(levels, radius)
+    }
+
+    /// Returns the height in pixels the bounding sphere covers on a viewport
+    /// viewport_height pixels high.
+    pub fn projected_size(
+        &self, camera: &FreeCamera, model: &Iso3<f32>, viewport_height: f32
+    ) -> f32 {
+        let t        = model.translation;
+        let distance = (Pnt3::new(t.x, t.y, t.z) - camera.pos).norm();
+        let half_fov = camera.projection.fov() / 2.0;
+
+        if distance <= self.radius {
+            return ::std::f32::INFINITY;
+        }
+        self.radius / (distance * half_fov.tan()) * viewport_height
+    }
+
+    /// Picks the level to draw for the model as seen by camera.
+    pub fn select(
+        &self, camera: &FreeCamera, model: &Iso3<f32>, viewport_height: f32
+    ) -> &RenderableObj {
+        let size  = self.projected_size(camera, model, viewport_height);
+        let level = if size >= self.switch_size {
+            0
+        } else {
+            (self.switch_size / size).log2().floor() as usize
+        };
+
+        &self.levels[level.min(self.levels.len() - 1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::primitives::UvSphere;
+
+    #[test]
+    fn every_level_halves_the_triangles() {
+        let sphere = UvSphere::new(1.0, 32, 16).mesh().weld(1.0e-5);
+        let levels = simplified_levels(&sphere, 4);
+
+        assert_eq!(levels.len(), 4);
+        for pair in levels.windows(2) {
+            let (before, after) = (pair[0].triangle_count(), pair[1].triangle_count());
+            assert!(after <= before / 2 && after + 2 >= before / 2,
+                    "{} triangles simplified to {}", before, after);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use ::{RenderableIndices, Vertex, BuildRenderable};
+use ::glium::{VertexBuffer, Display};
+use ::nalgebra::{Vec3, Norm, cross};
+use ::std::collections::HashMap;
+
+/// An indexed triangle mesh kept on the CPU so it can be generated and
+/// processed before being uploaded.
+#[derive(Clone)]
+pub struct Mesh {
+    pub vertices : Vec<Vertex>,
+    pub indices  : Vec<u32>
+}
+
+impl Mesh {
+    pub fn new() -> Mesh {
+        Mesh { vertices : vec![], indices : vec![] }
+    }
+
+    pub fn from_parts(vertices: Vec<Vertex>, indices: Vec<u32>) -> Mesh {
+        Mesh { vertices : vertices, indices : indices }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// Adds a vertex and returns its index.
+    pub fn push_vertex(&mut self, vertex: Vertex) -> u32 {
+        self.vertices.push(vertex);
+        (self.vertices.len() - 1) as u32
+    }
+
+    pub fn push_triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.indices.push(a);
+        self.indices.push(b);
+        self.indices.push(c);
+    }
+
+    /// Returns the position of the vertex at index as a Vec3.
+    pub fn position(&self, index: u32) -> Vec3<f32> {
+        let p = self.vertices[index as usize].position;
+        Vec3::new(p[0], p[1], p[2])
+    }
+
+    /// Returns the unnormalized face normal of triangle tri, its length is
+    /// twice the area of the triangle.
+    pub fn face_normal(&self, tri: usize) -> Vec3<f32> {
+        let a = self.position(self.indices[tri*3]);
+        let b = self.position(self.indices[tri*3 + 1]);
+        let c = self.position(self.indices[tri*3 + 2]);
+        cross(&(b - a), &(c - a))
+    }
+
+    /// Returns a copy of the mesh where every vertex within tolerance of an
+    /// earlier kept vertex is merged into it, keeping the attributes of the
+    /// kept one. Kept vertices are bucketed into cells of size tolerance,
+    /// so only the 27 cells around a vertex need searching.
+    pub fn weld(&self, tolerance: f32) -> Mesh {
+        let mut cells: HashMap<(i64, i64, i64), Vec<u32>> = HashMap::new();
+        let mut vertices: Vec<Vertex> = vec![];
+        let mut remap    = vec![];
+
+        for vertex in &self.vertices {
+            let p    = vertex.position;
+            let cell = |k: usize| (p[k] / tolerance).floor() as i64;
+            let home = (cell(0), cell(1), cell(2));
+
+            let mut found = None;
+            for dx in -1..2 {
+                for dy in -1..2 {
+                    for dz in -1..2 {
+                        let key = (home.0 + dx, home.1 + dy, home.2 + dz);
+                        for &i in cells.get(&key).map(|c| &c[..]).unwrap_or(&[]) {
+                            let q: [f32; 3] = vertices[i as usize].position;
+                            let d = (0..3).map(|k| (p[k] - q[k]) * (p[k] - q[k]))
+                                .fold(0.0, |sum, x| sum + x);
+                            if d <= tolerance * tolerance && found.map_or(true, |f| i < f) {
+                                found = Some(i);
+                            }
+                        }
+                    }
+                }
+            }
+
+            let index = match found {
+                Some(i) => i,
+                None    => {
+                    vertices.push(*vertex);
+                    let i = (vertices.len() - 1) as u32;
+                    cells.entry(home).or_insert(vec![]).push(i);
+                    i
+                }
+            };
+            remap.push(index);
+        }
+
+        let indices = self.indices.iter().map(|&i| remap[i as usize]).collect();
+        Mesh::from_parts(vertices, indices)
+    }
+
+    /// Replaces every vertex normal with the area weighted average of the
+    /// normals of the faces using it.
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![Vec3::new(0.0f32, 0.0, 0.0); self.vertices.len()];
+
+        for tri in 0..self.triangle_count() {
+            let n = self.face_normal(tri);
+            for k in 0..3 {
+                let i = self.indices[tri*3 + k] as usize;
+                normals[i] = normals[i] + n;
+            }
+        }
+
+        for (vertex, n) in self.vertices.iter_mut().zip(normals.iter()) {
+            let n = if n.sqnorm() > 0.0 { n.normalize() } else { *n };
+            vertex.normal = [n.x, n.y, n.z];
+        }
+    }
+}
+
+impl BuildRenderable for Mesh {
+    fn get_vertex_array(&self, display: &Display) -> VertexBuffer<Vertex> {
+        VertexBuffer::new(display, self.vertices.clone())
+    }
+
+    fn get_indices(&self, display: &Display) -> RenderableIndices {
+        RenderableIndices::from_triangles(
+            display, self.vertices.len(), self.indices.clone()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(positions: &[[f32; 3]]) -> Mesh {
+        let vertices = positions.iter()
+            .map(|p| Vertex::from_position(p[0], p[1], p[2]))
+            .collect();
+        Mesh::from_parts(vertices, vec![])
+    }
+
+    #[test]
+    fn weld_merges_close_vertices_across_cell_boundaries() {
+        // 0.01 tolerance apart, on either side of a cell boundary
+        let mesh = points(&[[0.9995, 0.0, 0.0], [1.0005, 0.0, 0.0]]);
+        assert_eq!(mesh.weld(0.1).vertices.len(), 1);
+    }
+
+    #[test]
+    fn weld_keeps_vertices_further_apart_than_tolerance() {
+        // in neighbouring cells, but 1.5 tolerance apart
+        let mesh = points(&[[0.01, 0.01, 0.01], [0.01, 0.16, 0.01]]);
+        assert_eq!(mesh.weld(0.1).vertices.len(), 2);
+    }
+
+    #[test]
+    fn weld_remaps_indices_to_the_first_vertex() {
+        let mut mesh = points(&[
+            [0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0],
+            [1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 1.0]
+        ]);
+        mesh.indices = vec![0, 2, 1, 3, 4, 5];
+
+        let welded = mesh.weld(1.0e-5);
+        assert_eq!(welded.vertices.len(), 4);
+        assert_eq!(welded.indices, vec![0, 2, 1, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,346 @@
+//! Seedable procedural noise for generating terrain on the CPU. The same
+//! seed always produces the same values.
+
+use ::Heightmap;
+use ::random::Rng;
+
+/// A two dimensional noise function returning values roughly in [-1, 1].
+pub trait Noise2 {
+    fn get(&self, x: f32, y: f32) -> f32;
+}
+
+/// Shuffled lattice hash shared by the noise functions.
+#[derive(Clone)]
+struct Permutation {
+    table : Vec<usize>
+}
+
+impl Permutation {
+    fn new(rng: &mut Rng) -> Permutation {
+        let mut table: Vec<usize> = (0..256).collect();
+        for i in (1..256).rev() {
+            let j = (rng.next_u32() as usize) % (i + 1);
+            table.swap(i, j);
+        }
+
+        let copy = table.clone();
+        table.extend(copy.into_iter());
+        Permutation { table : table }
+    }
+
+    fn hash(&self, x: i32, y: i32) -> usize {
+        self.table[self.table[(x & 255) as usize] + (y & 255) as usize]
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Ken Perlin's improved gradient noise.
+#[derive(Clone)]
+pub struct PerlinNoise {
+    perm : Permutation
+}
+
+impl PerlinNoise {
+    pub fn new(seed: u32) -> PerlinNoise {
+        PerlinNoise { perm : Permutation::new(&mut Rng::new(seed)) }
+    }
+
+    fn grad(hash: usize, x: f32, y: f32) -> f32 {
+        match hash & 7 {
+            0 =>  x + y,
+            1 => -x + y,
+            2 =>  x - y,
+            3 => -x - y,
+            4 =>  x,
+            5 => -x,
+            6 =>  y,
+            _ => -y
+        }
+    }
+}
+
+impl Noise2 for PerlinNoise {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        let (fx, fy) = (x.floor(), y.floor());
+        let (xi, yi) = (fx as i32, fy as i32);
+        let (xf, yf) = (x - fx, y - fy);
+        let (u, v)   = (fade(xf), fade(yf));
+
+        let g00 = PerlinNoise::grad(self.perm.hash(xi,     yi    ), xf,       yf);
+        let g10 = PerlinNoise::grad(self.perm.hash(xi + 1, yi    ), xf - 1.0, yf);
+        let g01 = PerlinNoise::grad(self.perm.hash(xi,     yi + 1), xf,       yf - 1.0);
+        let g11 = PerlinNoise::grad(self.perm.hash(xi + 1, yi + 1), xf - 1.0, yf - 1.0);
+
+        lerp(lerp(g00, g10, u), lerp(g01, g11, u), v)
+    }
+}
+
+/// Two dimensional simplex noise.
+#[derive(Clone)]
+pub struct SimplexNoise {
+    perm : Permutation
+}
+
+impl SimplexNoise {
+    pub fn new(seed: u32) -> SimplexNoise {
+        SimplexNoise { perm : Permutation::new(&mut Rng::new(seed)) }
+    }
+
+    fn corner(&self, hash: usize, x: f32, y: f32) -> f32 {
+        static GRADIENTS : [(f32, f32); 12] = [
+            ( 1.0,  1.0), (-1.0,  1.0), ( 1.0, -1.0), (-1.0, -1.0),
+            ( 1.0,  0.0), (-1.0,  0.0), ( 1.0,  0.0), (-1.0,  0.0),
+            ( 0.0,  1.0), ( 0.0, -1.0), ( 0.0,  1.0), ( 0.0, -1.0)
+        ];
+
+        let t = 0.5 - x*x - y*y;
+        if t < 0.0 {
+            0.0
+        }
+        else {
+            let (gx, gy) = GRADIENTS[hash % 12];
+            t * t * t * t * (gx * x + gy * y)
+        }
+    }
+}
+
+impl Noise2 for SimplexNoise {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        let f2 = 0.5 * (3.0f32.sqrt() - 1.0);
+        let g2 = (3.0 - 3.0f32.sqrt()) / 6.0;
+
+        // skew into the simplex grid to find the containing cell
+        let s = (x + y) * f2;
+        let (i, j) = ((x + s).floor(), (y + s).floor());
+        let t = (i + j) * g2;
+        let (x0, y0) = (x - (i - t), y - (j - t));
+
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+        let (x1, y1) = (x0 - i1 as f32 + g2, y0 - j1 as f32 + g2);
+        let (x2, y2) = (x0 - 1.0 + 2.0 * g2, y0 - 1.0 + 2.0 * g2);
+
+        let (i, j) = (i as i32, j as i32);
+        let n0 = self.corner(self.perm.hash(i, j), x0, y0);
+        let n1 = self.corner(self.perm.hash(i + i1, j + j1), x1, y1);
+        let n2 = self.corner(self.perm.hash(i + 1, j + 1), x2, y2);
+
+        70.0 * (n0 + n1 + n2)
+    }
+}
+
+/// Smoothly interpolated random values at integer lattice points.
+#[derive(Clone)]
+pub struct ValueNoise {
+    perm   : Permutation,
+    values : Vec<f32>
+}
+
+impl ValueNoise {
+    pub fn new(seed: u32) -> ValueNoise {
+        let mut rng = Rng::new(seed);
+        let perm    = Permutation::new(&mut rng);
+        let values  = (0..256).map(|_| rng.range(-1.0, 1.0)).collect();
+
+        ValueNoise { perm : perm, values : values }
+    }
+}
+
+impl Noise2 for ValueNoise {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        let (fx, fy) = (x.floor(), y.floor());
+        let (xi, yi) = (fx as i32, fy as i32);
+        let (u, v)   = (fade(x - fx), fade(y - fy));
+
+        let value = |x: i32, y: i32| self.values[self.perm.hash(x, y) & 255];
+        lerp(
+            lerp(value(xi, yi),     value(xi + 1, yi),     u),
+            lerp(value(xi, yi + 1), value(xi + 1, yi + 1), u),
+            v
+        )
+    }
+}
+
+/// Fractal Brownian motion, octaves of a noise function summed with
+/// increasing frequency and decreasing amplitude.
+#[derive(Clone)]
+pub struct Fbm<N> {
+    pub noise      : N,
+    pub octaves    : u32,
+    /// Frequency multiplier between octaves.
+    pub lacunarity : f32,
+    /// Amplitude multiplier between octaves.
+    pub gain       : f32
+}
+
+impl<N: Noise2> Fbm<N> {
+    pub fn new(noise: N, octaves: u32) -> Fbm<N> {
+        Fbm { noise : noise, octaves : octaves, lacunarity : 2.0, gain : 0.5 }
+    }
+}
+
+impl<N: Noise2> Noise2 for Fbm<N> {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        let (mut sum, mut norm) = (0.0, 0.0);
+        let (mut amplitude, mut frequency) = (1.0, 1.0);
+
+        for _ in 0..self.octaves {
+            sum  += amplitude * self.noise.get(x * frequency, y * frequency);
+            norm += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        if norm > 0.0 { sum / norm } else { 0.0 }
+    }
+}
+
+/// Ridged multifractal, sharp crests formed from inverted absolute noise
+/// where each octave is weighted by the previous one.
+#[derive(Clone)]
+pub struct RidgedMultifractal<N> {
+    pub noise      : N,
+    pub octaves    : u32,
+    pub lacunarity : f32,
+    pub gain       : f32,
+    /// Height of the ridges before squaring.
+    pub offset     : f32
+}
+
+impl<N: Noise2> RidgedMultifractal<N> {
+    pub fn new(noise: N, octaves: u32) -> RidgedMultifractal<N> {
+        RidgedMultifractal {
+            noise      : noise,
+            octaves    : octaves,
+            lacunarity : 2.0,
+            gain       : 2.0,
+            offset     : 1.0
+        }
+    }
+}
+
+impl<N: Noise2> Noise2 for RidgedMultifractal<N> {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        let (mut sum, mut norm) = (0.0, 0.0);
+        let (mut amplitude, mut frequency) = (1.0, 1.0);
+        let mut weight = 1.0;
+
+        for _ in 0..self.octaves {
+            let mut signal = self.offset - self.noise.get(x * frequency, y * frequency).abs();
+            signal = signal * signal * weight;
+            weight = (signal * self.gain).max(0.0).min(1.0);
+
+            sum  += signal * amplitude;
+            norm += amplitude * self.offset * self.offset;
+            amplitude *= 0.5;
+            frequency *= self.lacunarity;
+        }
+
+        // map from [0, norm] to [-1, 1]
+        if norm > 0.0 { sum / norm * 2.0 - 1.0 } else { 0.0 }
+    }
+}
+
+/// Offsets the input of a noise function by a second warping noise.
+#[derive(Clone)]
+pub struct DomainWarp<N, W> {
+    pub noise    : N,
+    pub warp     : W,
+    /// Distance, in noise units, the input is moved by at most.
+    pub strength : f32
+}
+
+impl<N: Noise2, W: Noise2> DomainWarp<N, W> {
+    pub fn new(noise: N, warp: W, strength: f32) -> DomainWarp<N, W> {
+        DomainWarp { noise : noise, warp : warp, strength : strength }
+    }
+}
+
+impl<N: Noise2, W: Noise2> Noise2 for DomainWarp<N, W> {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        // sample the warp at two unrelated offsets for the two axes
+        let wx = self.warp.get(x + 5.2, y + 1.3);
+        let wy = self.warp.get(x + 1.7, y + 9.2);
+        self.noise.get(x + self.strength * wx, y + self.strength * wy)
+    }
+}
+
+/// Fills a width x depth heightmap with noise sampled every 1/frequency
+/// noise units and scaled by amplitude.
+pub fn noise_heightmap<N: Noise2>(
+    noise: &N, width: usize, depth: usize, frequency: f32, amplitude: f32
+) -> Heightmap {
+    Heightmap::from_fn(width, depth, |x, z| {
+        noise.get(x as f32 * frequency, z as f32 * frequency) * amplitude
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Samples noise at non-lattice points over a large area.
+    fn samples<N: Noise2>(noise: &N) -> Vec<f32> {
+        let mut values = vec![];
+        for i in 0..200 {
+            for j in 0..200 {
+                values.push(noise.get(i as f32 * 0.173 - 17.0, j as f32 * 0.131 - 13.0));
+            }
+        }
+        values
+    }
+
+    fn assert_deterministic<N: Noise2, F: Fn(u32) -> N>(name: &str, make: F) {
+        assert_eq!(samples(&make(7)), samples(&make(7)), "{} differs for the same seed", name);
+
+        let (a, b) = (samples(&make(7)), samples(&make(8)));
+        let differing = a.iter().zip(b.iter()).filter(|&(x, y)| x != y).count();
+        assert!(differing > a.len() / 2, "{} barely depends on the seed", name);
+    }
+
+    fn assert_range<N: Noise2>(name: &str, noise: &N, low: f32, high: f32) {
+        let values = samples(noise);
+        let min = values.iter().fold(::std::f32::MAX, |m, &v| m.min(v));
+        let max = values.iter().fold(::std::f32::MIN, |m, &v| m.max(v));
+
+        assert!(min >= low && max <= high, "{} spans {} to {}", name, min, max);
+        // the values should also cover a good part of the range
+        assert!(max - min > 0.5 * (high - low), "{} only spans {} to {}", name, min, max);
+    }
+
+    #[test]
+    fn noise_depends_only_on_the_seed() {
+        assert_deterministic("perlin",  PerlinNoise::new);
+        assert_deterministic("simplex", SimplexNoise::new);
+        assert_deterministic("value",   ValueNoise::new);
+        assert_deterministic("fbm",     |seed| Fbm::new(PerlinNoise::new(seed), 5));
+        assert_deterministic("ridged",  |seed| RidgedMultifractal::new(SimplexNoise::new(seed), 5));
+        assert_deterministic("warp",    |seed| {
+            DomainWarp::new(PerlinNoise::new(seed), ValueNoise::new(seed + 1), 0.5)
+        });
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        assert_range("perlin",  &PerlinNoise::new(3),  -1.0, 1.0);
+        assert_range("simplex", &SimplexNoise::new(3), -1.0, 1.0);
+        assert_range("value",   &ValueNoise::new(3),   -1.0, 1.0);
+        assert_range("fbm",     &Fbm::new(SimplexNoise::new(3), 6), -1.0, 1.0);
+        assert_range("ridged",  &RidgedMultifractal::new(PerlinNoise::new(3), 6), -1.0, 1.0);
+    }
+
+    #[test]
+    fn gradient_noise_vanishes_on_the_lattice() {
+        let noise = PerlinNoise::new(11);
+        for i in -5..5 {
+            for j in -5..5 {
+                assert_eq!(noise.get(i as f32, j as f32), 0.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,613 @@
+use ::{RenderableObj, Heightmap, Addressing};
+use ::lighting::{BLINN_PHONG, WATER, model_space_lighting};
+use ::random::Rng;
+use ::nalgebra::{Vec3, Mat4, Iso3, Norm, to_homogeneous, Transformation};
+use ::glium::{Program, Display, DrawParameters, DepthTest, Frame, PolygonMode, Rect};
+use ::glium::texture::{Texture2d, UncompressedFloatFormat};
+use ::glium::uniforms::SamplerWrapFunction;
+use ::std::f32::consts::PI;
+
+const GRAVITY : f32 = 9.81;
+
+/// Directional wave spectra the ocean can be generated from. Both are
+/// spread around the wind direction and give the variance of the height
+/// per unit of wavenumber squared.
+#[derive(Copy, Clone, Debug)]
+pub enum OceanSpectrum {
+    /// Tessendorf's fully developed sea, amplitude is the Phillips constant.
+    Phillips { amplitude: f32 },
+    /// A sea still developing over fetch metres of open water, gamma
+    /// sharpens the peak, 3.3 is typical.
+    Jonswap { fetch: f32, gamma: f32 }
+}
+
+/// Ocean surface synthesized from a wave spectrum with inverse FFTs on the
+/// CPU, after Tessendorf's "Simulating Ocean Water". The surface tiles with
+/// period length and is sampled on a size x size grid, size a power of two.
+/// The same seed and parameters always give the same waves.
+pub struct Ocean {
+    pub seed           : u32,
+    pub size           : usize,
+    /// World size of one tile of the surface.
+    pub length         : f32,
+    pub spectrum       : OceanSpectrum,
+    /// Wind speed ten metres above the water.
+    pub wind_speed     : f32,
+    /// Unit direction the wind blows towards on the xz plane.
+    pub wind_direction : (f32, f32),
+    /// Scale of the horizontal displacement which sharpens the crests.
+    pub choppiness     : f32,
+    initial            : Vec<Complex>,
+    heights            : Vec<f32>,
+    displacements      : Vec<(f32, f32, f32)>,
+    normals            : Vec<(f32, f32, f32)>
+}
+
+impl Ocean {
+    pub fn new(seed: u32, size: usize, length: f32) -> Ocean {
+        assert!(size.is_power_of_two(), "ocean size must be a power of two");
+
+        let mut ocean = Ocean {
+            seed           : seed,
+            size           : size,
+            length         : length,
+            spectrum       : OceanSpectrum::Phillips { amplitude : 0.0081 },
+            wind_speed     : 20.0,
+            wind_direction : (1.0, 0.0),
+            choppiness     : 1.0,
+            initial        : vec![],
+            heights        : vec![0.0; size * size],
+            displacements  : vec![(0.0, 0.0, 0.0); size * size],
+            normals        : vec![(0.0, 1.0, 0.0); size * size]
+        };
+        ocean.regenerate();
+        ocean.update(0.0);
+        ocean
+    }
+
+    /// Heights of the samples, row by row along z.
+    pub fn heights(&self) -> &[f32] {
+        &self.heights
+    }
+
+    /// Offset of each sample from its resting position on the xz plane,
+    /// with the horizontal part scaled by choppiness.
+    pub fn displacements(&self) -> &[(f32, f32, f32)] {
+        &self.displacements
+    }
+
+    pub fn normals(&self) -> &[(f32, f32, f32)] {
+        &self.normals
+    }
+
+    /// The heights as a wrapping heightmap with one sample per grid point.
+    pub fn height_map(&self) -> Heightmap {
+        Heightmap::from_fn(self.size, self.size, |x, z| self.heights[z * self.size + x])
+            .with_addressing(Addressing::Wrap)
+    }
+
+    /// Variance density of the spectrum for wave vector (kx, kz).
+    pub fn spectrum_at(&self, kx: f32, kz: f32) -> f32 {
+        let k = (kx*kx + kz*kz).sqrt();
+        if k < 1.0e-6 {
+            return 0.0;
+        }
+
+        let (wx, wz) = self.wind_direction;
+        let cos      = (kx * wx + kz * wz) / k;
+
+        match self.spectrum {
+            OceanSpectrum::Phillips { amplitude } => {
+                let largest = self.wind_speed * self.wind_speed / GRAVITY;
+                let damping = largest / 1000.0;
+
+                // waves against the wind are mostly suppressed
+                let along = if cos < 0.0 { 0.07 * cos * cos } else { cos * cos };
+                amplitude * (-1.0 / (k * largest).powi(2)).exp() / k.powi(4) *
+                    along * (-(k * damping).powi(2)).exp()
+            },
+            OceanSpectrum::Jonswap { fetch, gamma } => {
+                let omega = (GRAVITY * k).sqrt();
+                let x     = GRAVITY * fetch / (self.wind_speed * self.wind_speed);
+                let alpha = 0.076 * x.powf(-0.22);
+                let peak  = 22.0 * (GRAVITY * GRAVITY / (self.wind_speed * fetch)).powf(1.0 / 3.0);
+                let sigma = if omega <= peak { 0.07 } else { 0.09 };
+                let r     = (-(omega - peak).powi(2) / (2.0 * sigma * sigma * peak * peak)).exp();
+
+                let s_omega = alpha * GRAVITY * GRAVITY / omega.powi(5) *
+                    (-1.25 * (peak / omega).powi(4)).exp() * gamma.powf(r);
+
+                // from frequency to wavenumber, then spread over direction
+                let d_omega = GRAVITY / (2.0 * omega);
+                let spread  = if cos > 0.0 { 2.0 / PI * cos * cos } else { 0.0 };
+                s_omega * d_omega * spread / k
+            }
+        }
+    }
+
+    /// Rebuilds the initial wave amplitudes, needed after changing the seed,
+    /// spectrum or wind.
+    pub fn regenerate(&mut self) {
+        let n  = self.size;
+        let dk = 2.0 * PI / self.length;
+        let mut rng = Rng::new(self.seed);
+
+        let mut initial = Vec::with_capacity(n * n);
+        for z in 0..n {
+            for x in 0..n {
+                // draw the numbers for every sample so the sea does not
+                // change with which frequencies are skipped
+                let (r, i) = (rng.gaussian(), rng.gaussian());
+                if x == n / 2 || z == n / 2 {
+                    initial.push(Complex::zero());
+                    continue;
+                }
+                let (kx, kz) = self.wave_vector(x, z);
+                let scale    = (self.spectrum_at(kx, kz) * dk * dk / 2.0).sqrt();
+                initial.push(Complex::new(r * scale, i * scale));
+            }
+        }
+        self.initial = initial;
+    }
+
+    /// Wave vector of the frequency at (x, z), the upper half of each axis
+    /// holding the negative frequencies.
+    fn wave_vector(&self, x: usize, z: usize) -> (f32, f32) {
+        let n     = self.size as i64;
+        let index = |i: usize| if (i as i64) < n / 2 { i as i64 } else { i as i64 - n };
+        let dk    = 2.0 * PI / self.length;
+        (index(x) as f32 * dk, index(z) as f32 * dk)
+    }
+
+    /// Advances the surface to time.
+    pub fn update(&mut self, time: f32) {
+        let n = self.size;
+
+        // the outputs are real, so pairs of them share one transform as its
+        // real and imaginary parts
+        let mut height_dx = vec![Complex::zero(); n * n];
+        let mut dz_sx     = vec![Complex::zero(); n * n];
+        let mut slope_z   = vec![Complex::zero(); n * n];
+
+        for z in 0..n {
+            for x in 0..n {
+                let (kx, kz) = self.wave_vector(x, z);
+                let k        = (kx*kx + kz*kz).sqrt();
+                let omega    = (GRAVITY * k).sqrt() * time;
+
+                let mirror = ((n - z) % n) * n + (n - x) % n;
+                let h = self.initial[z * n + x].mul(Complex::exp_i(omega))
+                    .add(self.initial[mirror].conj().mul(Complex::exp_i(-omega)));
+
+                // points move towards the crests, i k h turns the wave
+                // A cos(k x) into -A sin(k x)
+                let (ux, uz) = if k > 1.0e-6 { (kx / k, kz / k) } else { (0.0, 0.0) };
+                let dx    = h.mul(Complex::new(0.0, ux));
+                let dz    = h.mul(Complex::new(0.0, uz));
+                let slope = |kc: f32| h.mul(Complex::new(0.0, kc));
+
+                height_dx[z * n + x] = h.add(dx.mul(Complex::new(0.0, 1.0)));
+                dz_sx[z * n + x]     = dz.add(slope(kx).mul(Complex::new(0.0, 1.0)));
+                slope_z[z * n + x]   = slope(kz);
+            }
+        }
+
+        inverse_fft_2d(&mut height_dx, n);
+        inverse_fft_2d(&mut dz_sx, n);
+        inverse_fft_2d(&mut slope_z, n);
+
+        for i in 0..n * n {
+            let height = height_dx[i].re;
+            let (dx, dz) = (height_dx[i].im, dz_sx[i].re);
+            let (sx, sz) = (dz_sx[i].im, slope_z[i].re);
+
+            self.heights[i]       = height;
+            self.displacements[i] = (self.choppiness * dx, height, self.choppiness * dz);
+
+            let normal = Vec3::new(-sx, 1.0, -sz).normalize();
+            self.normals[i] = (normal.x, normal.y, normal.z);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Complex {
+    re : f32,
+    im : f32
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Complex {
+        Complex { re : re, im : im }
+    }
+
+    fn zero() -> Complex {
+        Complex::new(0.0, 0.0)
+    }
+
+    /// e to the power of i * angle.
+    fn exp_i(angle: f32) -> Complex {
+        Complex::new(angle.cos(), angle.sin())
+    }
+
+    fn add(self, o: Complex) -> Complex {
+        Complex::new(self.re + o.re, self.im + o.im)
+    }
+
+    fn sub(self, o: Complex) -> Complex {
+        Complex::new(self.re - o.re, self.im - o.im)
+    }
+
+    fn mul(self, o: Complex) -> Complex {
+        Complex::new(self.re * o.re - self.im * o.im, self.re * o.im + self.im * o.re)
+    }
+
+    fn conj(self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+}
+
+/// In-place radix-2 inverse FFT, without the 1/n scale, of n values a
+/// power of two.
+fn inverse_fft(values: &mut [Complex]) {
+    let n = values.len();
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            values.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= n {
+        let step = Complex::exp_i(2.0 * PI / length as f32);
+        for start in (0..n / length).map(|b| b * length) {
+            let mut w = Complex::new(1.0, 0.0);
+            for i in 0..length / 2 {
+                let even = values[start + i];
+                let odd  = values[start + i + length / 2].mul(w);
+                values[start + i]              = even.add(odd);
+                values[start + i + length / 2] = even.sub(odd);
+                w = w.mul(step);
+            }
+        }
+        length <<= 1;
+    }
+}
+
+/// Inverse FFT of an n x n grid stored row by row.
+fn inverse_fft_2d(values: &mut [Complex], n: usize) {
+    for row in values.chunks_mut(n) {
+        inverse_fft(row);
+    }
+
+    let mut column = vec![Complex::zero(); n];
+    for x in 0..n {
+        for z in 0..n {
+            column[z] = values[z * n + x];
+        }
+        inverse_fft(&mut column);
+        for z in 0..n {
+            values[z * n + x] = column[z];
+        }
+    }
+}
+
+/// Splits a size x size map into rows of texels.
+fn texel_rows(values: &[(f32, f32, f32)], size: usize) -> Vec<Vec<(f32, f32, f32, f32)>> {
+    values.chunks(size)
+        .map(|row| row.iter().map(|&(x, y, z)| (x, y, z, 0.0)).collect())
+        .collect()
+}
+
+/// Uploads a size x size map to an unclamped float texture with a single
+/// level, so that upload can replace all of it.
+fn map_texture(display: &Display, values: &[(f32, f32, f32)], size: usize) -> Texture2d {
+    Texture2d::with_format(
+        display, texel_rows(values, size), UncompressedFloatFormat::F32F32F32F32, false
+    ).unwrap()
+}
+
+/// Draws a flat mesh on the xz plane as an Ocean, displacing it by the
+/// ocean's displacement map and shading it with its normal map. The maps
+/// repeat every length world units.
+pub struct OceanRenderer {
+    pub program         : Program,
+    /// Direction towards the light, in world space.
+    pub light_direction : Vec3<f32>,
+    pub water_color     : Vec3<f32>,
+    pub sky_color       : Vec3<f32>,
+    pub specular_color  : Vec3<f32>,
+    pub shininess       : f32,
+    pub wire            : bool,
+    /// Horizontal displacement and height in rgb.
+    pub displacement    : Texture2d,
+    pub normals         : Texture2d,
+    length              : f32
+}
+
+impl OceanRenderer {
+    pub fn new(display: &Display, ocean: &Ocean) -> OceanRenderer {
+
+        OceanRenderer {
+            program         : OceanRenderer::create_shader_program(&display),
+            light_direction : Vec3::new(0.3, 0.5, -0.8).normalize(),
+            water_color     : Vec3::new(0.0, 0.12, 0.18),
+            sky_color       : Vec3::new(0.55, 0.7, 0.85),
+            specular_color  : Vec3::new(1.0, 0.95, 0.85),
+            shininess       : 256.0,
+            wire            : false,
+            displacement    : map_texture(display, ocean.displacements(), ocean.size),
+            normals         : map_texture(display, ocean.normals(), ocean.size),
+            length          : ocean.length
+        }
+    }
+
+    /// Uploads the ocean's current maps, which must be the size the
+    /// renderer was created with.
+    pub fn upload(&mut self, ocean: &Ocean) {
+        let rect = Rect {
+            left   : 0,
+            bottom : 0,
+            width  : ocean.size as u32,
+            height : ocean.size as u32
+        };
+
+        self.displacement.write(rect, texel_rows(ocean.displacements(), ocean.size));
+        self.normals.write(rect, texel_rows(ocean.normals(), ocean.size));
+        self.length = ocean.length;
+    }
+
+    pub fn draw(
+        &self, frame: &mut Frame,
+        obj: &RenderableObj, proj: &Mat4<f32>, view: &Iso3<f32>, model: &Iso3<f32>
+    ) {
+        let mv  = view.prepend_transformation(model);
+        let mvp = *proj * to_homogeneous(&mv);
+
+        let (eye, light) = model_space_lighting(&mv, model, &self.light_direction);
+
+        let params = DrawParameters {
+            depth_test   : DepthTest::IfLess,
+            depth_write  : true,
+            polygon_mode : if self.wire == true { PolygonMode::Line } else { PolygonMode::Fill },
+            .. ::std::default::Default::default()
+        };
+
+        let uniforms = uniform!(
+            MVP             : mvp,
+            patch_length    : self.length,
+            displacement    : self.displacement.sampled().wrap_function(SamplerWrapFunction::Repeat),
+            normals         : self.normals.sampled().wrap_function(SamplerWrapFunction::Repeat),
+            eye_position    : eye,
+            light_direction : light,
+            water_color     : self.water_color,
+            sky_color       : self.sky_color,
+            specular_color  : self.specular_color,
+            shininess       : self.shininess
+        );
+
+        obj.draw(frame, &self.program, &uniforms, &params);
+    }
+
+    fn create_shader_program(display: &Display) -> Program {
+        let vertex_shader_src = r#"
+            #version 330
+            in vec3 position;
+            smooth out vec3 surface_position;
+            smooth out vec2 surface_uv;
+
+            uniform mat4 MVP;
+            uniform float patch_length;
+            uniform sampler2D displacement;
+
+            void main() {
+                surface_uv = position.xz / patch_length;
+
+                vec3 p = vec3(position.x, 0.0, position.z) + texture(displacement, surface_uv).xyz;
+                surface_position = p;
+                gl_Position = MVP * vec4(p, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = [r#"
+            #version 330
+        "#, BLINN_PHONG, WATER, r#"
+            smooth in vec3 surface_position;
+            smooth in vec2 surface_uv;
+            out vec4 vFragColor;
+
+            uniform sampler2D normals;
+            uniform vec3 eye_position;
+            uniform vec3 light_direction;
+            uniform vec3 water_color;
+            uniform vec3 sky_color;
+            uniform vec3 specular_color;
+            uniform float shininess;
+
+            void main() {
+                vec3 N = normalize(texture(normals, surface_uv).xyz);
+                vec3 V = normalize(eye_position - surface_position);
+
+                vec3 color = water(
+                    N, light_direction, V, water_color, sky_color, specular_color, shininess
+                );
+                vFragColor = vec4(color, 1.0);
+            }
+        "#].concat();
+
+        Program::from_source(
+            display, vertex_shader_src, &fragment_shader_src, None
+        ).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The inverse DFT, without the 1/n scale, summed term by term.
+    fn naive_inverse_dft(values: &[Complex]) -> Vec<Complex> {
+        let n = values.len();
+        (0..n).map(|j| {
+            values.iter().enumerate().fold(Complex::zero(), |sum, (k, &v)| {
+                sum.add(v.mul(Complex::exp_i(2.0 * PI * (j * k % n) as f32 / n as f32)))
+            })
+        }).collect()
+    }
+
+    fn random_values(rng: &mut Rng, n: usize) -> Vec<Complex> {
+        (0..n).map(|_| Complex::new(rng.range(-1.0, 1.0), rng.range(-1.0, 1.0))).collect()
+    }
+
+    fn assert_close(a: Complex, b: Complex, tolerance: f32) {
+        assert!((a.re - b.re).abs() < tolerance && (a.im - b.im).abs() < tolerance,
+                "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn fft_matches_the_naive_dft() {
+        let mut rng = Rng::new(3);
+        for &n in &[1, 2, 4, 8, 64] {
+            let values   = random_values(&mut rng, n);
+            let expected = naive_inverse_dft(&values);
+
+            let mut fast = values.clone();
+            inverse_fft(&mut fast);
+            for (&a, &b) in fast.iter().zip(expected.iter()) {
+                assert_close(a, b, 1.0e-4 * n as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn fft_round_trip() {
+        // conjugating before and after turns the inverse transform into
+        // the forward one
+        let n      = 32;
+        let values = random_values(&mut Rng::new(9), n * n);
+
+        let mut round = values.clone();
+        inverse_fft_2d(&mut round, n);
+        let mut round: Vec<Complex> = round.iter().map(|v| v.conj()).collect();
+        inverse_fft_2d(&mut round, n);
+
+        let scale = 1.0 / (n * n) as f32;
+        for (&a, &b) in values.iter().zip(round.iter()) {
+            assert_close(a, Complex::new(b.re * scale, -b.im * scale), 1.0e-4);
+        }
+    }
+
+    #[test]
+    fn points_move_towards_the_crests() {
+        // a single wave along x with its crest on the first column
+        let n = 16;
+        let mut ocean = Ocean::new(4, n, 64.0);
+        for h in ocean.initial.iter_mut() {
+            *h = Complex::zero();
+        }
+        ocean.initial[1] = Complex::new(0.5, 0.0);
+        ocean.update(0.0);
+
+        let at = |x: usize| ocean.displacements()[3 * n + x];
+        assert!((at(0).1 - 1.0).abs() < 1.0e-4, "the crest is {} high", at(0).1);
+        for x in 1..n / 2 {
+            assert!(at(x).0 < 0.0, "sample {} moves away from the crest by {}", x, at(x).0);
+            assert!(at(n - x).0 > 0.0, "sample {} moves away from the crest by {}", n - x, at(n - x).0);
+        }
+        for x in 0..n {
+            assert!(at(x).2.abs() < 1.0e-5);
+        }
+    }
+
+    #[test]
+    fn heights_match_the_naive_sum_of_waves() {
+        let (n, time) = (8, 1.7);
+        let mut ocean = Ocean::new(4, n, 50.0);
+        ocean.update(time);
+
+        for z in 0..n {
+            for x in 0..n {
+                let mut height = 0.0;
+                for kz in 0..n {
+                    for kx in 0..n {
+                        let (wx, wz) = ocean.wave_vector(kx, kz);
+                        let omega    = (GRAVITY * (wx*wx + wz*wz).sqrt()).sqrt() * time;
+                        let mirror   = ((n - kz) % n) * n + (n - kx) % n;
+                        let h = ocean.initial[kz * n + kx].mul(Complex::exp_i(omega))
+                            .add(ocean.initial[mirror].conj().mul(Complex::exp_i(-omega)));
+
+                        let phase = 2.0 * PI * ((kx * x + kz * z) % n) as f32 / n as f32;
+                        height += h.mul(Complex::exp_i(phase)).re;
+                    }
+                }
+                let got = ocean.heights()[z * n + x];
+                assert!((got - height).abs() < 1.0e-4, "height {} {} was {} not {}", x, z, got, height);
+            }
+        }
+    }
+
+    #[test]
+    fn the_spectrum_depends_only_on_the_seed() {
+        let (first, second) = (Ocean::new(11, 32, 100.0), Ocean::new(11, 32, 100.0));
+        assert_eq!(first.heights(), second.heights());
+        assert_eq!(first.normals(), second.normals());
+        assert!(first.heights() != Ocean::new(12, 32, 100.0).heights());
+
+        // the mean height is the zero frequency, which has no energy
+        let mean = first.heights().iter().fold(0.0, |a, &h| a + h) / (32 * 32) as f32;
+        assert!(mean.abs() < 1.0e-4, "mean height is {}", mean);
+        assert!(first.heights().iter().any(|&h| h.abs() > 0.01));
+    }
+
+    #[test]
+    fn initial_amplitudes_follow_the_spectrum() {
+        // each amplitude is a complex gaussian with variance S(k) dk^2, so
+        // relative to it the squared magnitudes average to one
+        let ocean = Ocean::new(21, 64, 200.0);
+        let dk    = 2.0 * PI / ocean.length;
+        let (mut sum, mut count) = (0.0, 0);
+
+        for z in 0..64 {
+            for x in 0..64 {
+                let (kx, kz) = ocean.wave_vector(x, z);
+                let variance = ocean.spectrum_at(kx, kz) * dk * dk;
+                let h        = ocean.initial[z * 64 + x];
+                if x == 32 || z == 32 || variance <= 0.0 {
+                    assert_eq!((h.re, h.im), (0.0, 0.0));
+                    continue;
+                }
+                sum   += (h.re * h.re + h.im * h.im) / variance;
+                count += 1;
+            }
+        }
+        let mean = sum / count as f32;
+        assert!((mean - 1.0).abs() < 0.1, "amplitudes are {} times the spectrum", mean);
+    }
+
+    #[test]
+    fn waves_follow_the_wind() {
+        for &spectrum in &[
+            OceanSpectrum::Phillips { amplitude : 0.0081 },
+            OceanSpectrum::Jonswap { fetch : 100000.0, gamma : 3.3 }
+        ] {
+            let mut ocean = Ocean::new(1, 16, 100.0);
+            ocean.spectrum = spectrum;
+
+            let k = 0.2;
+            let along   = ocean.spectrum_at(k, 0.0);
+            let against = ocean.spectrum_at(-k, 0.0);
+            let across  = ocean.spectrum_at(0.0, k);
+            assert!(along > 0.0, "{:?} has no waves along the wind", spectrum);
+            assert!(against < 0.1 * along && across < 1.0e-3 * along, "{:?}", spectrum);
+            assert_eq!(ocean.spectrum_at(0.0, 0.0), 0.0);
+        }
+    }
+}
@@ -0,0 +1,409 @@
+//! Parametric primitive shapes. Every shape is centered on the origin and
+//! its triangles wind counter-clockwise when seen from the outside.
+
+use ::{Vertex, BuildRenderable, RenderableIndices, Mesh};
+use ::glium::{VertexBuffer, Display};
+use ::std::f32::consts::PI;
+
+macro_rules! impl_mesh_renderable {
+    ($shape: ident) => {
+        impl $shape {
+            /// Returns the generated triangle mesh.
+            pub fn mesh(&self) -> &Mesh {
+                &self.mesh
+            }
+        }
+
+        impl BuildRenderable for $shape {
+            fn get_vertex_array(&self, display: &Display) -> VertexBuffer<Vertex> {
+                self.mesh.get_vertex_array(display)
+            }
+
+            fn get_indices(&self, display: &Display) -> RenderableIndices {
+                self.mesh.get_indices(display)
+            }
+        }
+    }
+}
+
+fn coincident(a: [f32; 3], b: [f32; 3]) -> bool {
+    (0..3).all(|k| (a[k] - b[k]).abs() < 1.0e-5)
+}
+
+/// Connects a lattice of (rows+1) x (cols+1) vertices starting at base.
+/// Positions must be laid out so that cross(next column - p, next row - p)
+/// points outward. Triangles collapsed to a point, such as at the poles of
+/// a sphere, are skipped.
+fn push_lattice(mesh: &mut Mesh, base: u32, cols: u32, rows: u32) {
+    let stride = cols + 1;
+
+    for row in 0..rows {
+        for col in 0..cols {
+            let a = base + row * stride + col;
+            let b = a + 1;
+            let d = a + stride;
+            let c = d + 1;
+
+            let (first, second) = {
+                let pos = |i: u32| mesh.vertices[i as usize].position;
+                (!coincident(pos(a), pos(b)), !coincident(pos(c), pos(d)))
+            };
+
+            if first {
+                mesh.push_triangle(a, b, d);
+            }
+            if second {
+                mesh.push_triangle(b, c, d);
+            }
+        }
+    }
+}
+
+/// Adds a flat cap of the given radius at height y facing up or down.
+fn push_cap(mesh: &mut Mesh, radius: f32, y: f32, slices: u32, up: bool) {
+    let ny = if up { 1.0 } else { -1.0 };
+    let center = mesh.push_vertex(
+        Vertex::new([0.0, y, 0.0], [0.0, ny, 0.0], [0.5, 0.5])
+    );
+
+    for i in 0..slices+1 {
+        let (sin, cos) = (2.0 * PI * (i as f32) / (slices as f32)).sin_cos();
+        mesh.push_vertex(Vertex::new(
+            [radius * cos, y, radius * sin], [0.0, ny, 0.0],
+            [0.5 + 0.5 * cos, 0.5 + 0.5 * sin]
+        ));
+    }
+
+    for i in 0..slices {
+        let (p, q) = (center + 1 + i, center + 2 + i);
+        if up {
+            mesh.push_triangle(center, q, p);
+        }
+        else {
+            mesh.push_triangle(center, p, q);
+        }
+    }
+}
+
+/// An axis aligned box with separate vertices, and normals, for every face.
+pub struct Cuboid {
+    mesh : Mesh
+}
+
+impl Cuboid {
+    pub fn new(width: f32, height: f32, depth: f32) -> Cuboid {
+        let (hx, hy, hz) = (width / 2.0, height / 2.0, depth / 2.0);
+        let mut mesh = Mesh::new();
+
+        // normal, u axis, v axis with cross(u, v) == normal
+        let faces = [
+            ([ 1.0,  0.0,  0.0], [ 0.0, 0.0, -1.0], [0.0, 1.0,  0.0]),
+            ([-1.0,  0.0,  0.0], [ 0.0, 0.0,  1.0], [0.0, 1.0,  0.0]),
+            ([ 0.0,  1.0,  0.0], [ 1.0, 0.0,  0.0], [0.0, 0.0, -1.0]),
+            ([ 0.0, -1.0,  0.0], [ 1.0, 0.0,  0.0], [0.0, 0.0,  1.0]),
+            ([ 0.0,  0.0,  1.0], [ 1.0, 0.0,  0.0], [0.0, 1.0,  0.0]),
+            ([ 0.0,  0.0, -1.0], [-1.0, 0.0,  0.0], [0.0, 1.0,  0.0])
+        ];
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+
+        for &(n, u, v) in faces.iter() {
+            let base = mesh.vertices.len() as u32;
+
+            for &(su, sv) in corners.iter() {
+                let mut pos = [0.0; 3];
+                for k in 0..3 {
+                    pos[k] = n[k] + su * u[k] + sv * v[k];
+                }
+                mesh.push_vertex(Vertex::new(
+                    [pos[0] * hx, pos[1] * hy, pos[2] * hz], n,
+                    [(su + 1.0) / 2.0, (sv + 1.0) / 2.0]
+                ));
+            }
+
+            mesh.push_triangle(base, base + 1, base + 2);
+            mesh.push_triangle(base, base + 2, base + 3);
+        }
+
+        Cuboid { mesh : mesh }
+    }
+
+    pub fn cube(size: f32) -> Cuboid {
+        Cuboid::new(size, size, size)
+    }
+}
+
+impl_mesh_renderable!(Cuboid);
+
+/// A latitude/longitude sphere.
+pub struct UvSphere {
+    mesh : Mesh
+}
+
+impl UvSphere {
+    pub fn new(radius: f32, slices: u32, stacks: u32) -> UvSphere {
+        let mut mesh = Mesh::new();
+
+        for row in 0..stacks+1 {
+            let v = (row as f32) / (stacks as f32);
+            let (sin_phi, cos_phi) = (v * PI).sin_cos();
+
+            for col in 0..slices+1 {
+                let u = (col as f32) / (slices as f32);
+                let (sin_theta, cos_theta) = (u * 2.0 * PI).sin_cos();
+
+                let n = [sin_phi * cos_theta, cos_phi, sin_phi * sin_theta];
+                mesh.push_vertex(Vertex::new(
+                    [n[0] * radius, n[1] * radius, n[2] * radius], n, [u, v]
+                ));
+            }
+        }
+        push_lattice(&mut mesh, 0, slices, stacks);
+
+        UvSphere { mesh : mesh }
+    }
+}
+
+impl_mesh_renderable!(UvSphere);
+
+/// A capped cylinder, or a cone when the top radius is zero.
+pub struct Cylinder {
+    mesh : Mesh
+}
+
+impl Cylinder {
+    pub fn new(
+        bottom_radius: f32, top_radius: f32, height: f32, slices: u32
+    ) -> Cylinder {
+        let mut mesh = Mesh::new();
+        let half  = height / 2.0;
+        let slope = (bottom_radius - top_radius) / height;
+
+        for row in 0..2 {
+            let (y, radius) = if row == 0 {
+                (half, top_radius)
+            } else {
+                (-half, bottom_radius)
+            };
+
+            for col in 0..slices+1 {
+                let u = (col as f32) / (slices as f32);
+                let (sin, cos) = (u * 2.0 * PI).sin_cos();
+                let len = (1.0 + slope * slope).sqrt();
+
+                mesh.push_vertex(Vertex::new(
+                    [radius * cos, y, radius * sin],
+                    [cos / len, slope / len, sin / len],
+                    [u, row as f32]
+                ));
+            }
+        }
+        push_lattice(&mut mesh, 0, slices, 1);
+
+        if top_radius > 0.0 {
+            push_cap(&mut mesh, top_radius, half, slices, true);
+        }
+        if bottom_radius > 0.0 {
+            push_cap(&mut mesh, bottom_radius, -half, slices, false);
+        }
+
+        Cylinder { mesh : mesh }
+    }
+
+    pub fn cone(radius: f32, height: f32, slices: u32) -> Cylinder {
+        Cylinder::new(radius, 0.0, height, slices)
+    }
+}
+
+impl_mesh_renderable!(Cylinder);
+
+/// A torus around the y axis.
+pub struct Torus {
+    mesh : Mesh
+}
+
+impl Torus {
+    pub fn new(
+        major_radius: f32, minor_radius: f32, rings: u32, sides: u32
+    ) -> Torus {
+        let mut mesh = Mesh::new();
+
+        for row in 0..sides+1 {
+            let v = (row as f32) / (sides as f32);
+            let (sin_psi, cos_psi) = (v * 2.0 * PI).sin_cos();
+
+            for col in 0..rings+1 {
+                let u = (col as f32) / (rings as f32);
+                let (sin_theta, cos_theta) = (u * 2.0 * PI).sin_cos();
+                let r = major_radius + minor_radius * cos_psi;
+
+                mesh.push_vertex(Vertex::new(
+                    [r * cos_theta, -minor_radius * sin_psi, r * sin_theta],
+                    [cos_psi * cos_theta, -sin_psi, cos_psi * sin_theta],
+                    [u, v]
+                ));
+            }
+        }
+        push_lattice(&mut mesh, 0, rings, sides);
+
+        Torus { mesh : mesh }
+    }
+}
+
+impl_mesh_renderable!(Torus);
+
+/// A cylinder of the given height with hemispherical ends.
+pub struct Capsule {
+    mesh : Mesh
+}
+
+impl Capsule {
+    /// stacks is the number of rows in each hemisphere.
+    pub fn new(radius: f32, height: f32, slices: u32, stacks: u32) -> Capsule {
+        let mut mesh = Mesh::new();
+        let half  = height / 2.0;
+        let total = height + PI * radius;
+
+        for row in 0..2*stacks+2 {
+            let (phi, offset) = if row <= stacks {
+                ((row as f32) / (stacks as f32) * PI / 2.0, half)
+            } else {
+                ((row - 1) as f32 / (stacks as f32) * PI / 2.0, -half)
+            };
+            let (sin_phi, cos_phi) = phi.sin_cos();
+            let y = radius * cos_phi + offset;
+
+            for col in 0..slices+1 {
+                let u = (col as f32) / (slices as f32);
+                let (sin_theta, cos_theta) = (u * 2.0 * PI).sin_cos();
+
+                let n = [sin_phi * cos_theta, cos_phi, sin_phi * sin_theta];
+                mesh.push_vertex(Vertex::new(
+                    [n[0] * radius, y, n[2] * radius], n,
+                    [u, (half + radius - y) / total]
+                ));
+            }
+        }
+        push_lattice(&mut mesh, 0, slices, 2*stacks + 1);
+
+        Capsule { mesh : mesh }
+    }
+}
+
+impl_mesh_renderable!(Capsule);
+
+/// A flat disk in the xz plane facing +y.
+pub struct Disk {
+    mesh : Mesh
+}
+
+impl Disk {
+    pub fn new(radius: f32, slices: u32) -> Disk {
+        let mut mesh = Mesh::new();
+        push_cap(&mut mesh, radius, 0.0, slices, true);
+        Disk { mesh : mesh }
+    }
+}
+
+impl_mesh_renderable!(Disk);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::Mesh;
+    use ::nalgebra::{Vec3, dot, cross};
+    use ::std::collections::HashMap;
+
+    /// Counts how often each directed edge is used once seams are welded.
+    fn directed_edges(mesh: &Mesh) -> HashMap<(u32, u32), u32> {
+        let mut edges = HashMap::new();
+        for tri in mesh.indices.chunks(3) {
+            for k in 0..3 {
+                *edges.entry((tri[k], tri[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        edges
+    }
+
+    /// Every edge is shared by exactly two faces which traverse it in
+    /// opposite directions.
+    fn assert_closed(mesh: &Mesh) {
+        let welded = mesh.weld(1.0e-4);
+        let edges  = directed_edges(&welded);
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge {} {} is used {} times", a, b, count);
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge {} {} has no twin", a, b);
+        }
+    }
+
+    fn signed_volume(mesh: &Mesh) -> f32 {
+        (0..mesh.triangle_count()).map(|f| {
+            let a = mesh.position(mesh.indices[f*3]);
+            let b = mesh.position(mesh.indices[f*3 + 1]);
+            let c = mesh.position(mesh.indices[f*3 + 2]);
+            dot(&a, &cross(&b, &c)) / 6.0
+        }).fold(0.0, |sum, v| sum + v)
+    }
+
+    fn assert_volume(mesh: &Mesh, expected: f32, tolerance: f32) {
+        let volume = signed_volume(mesh);
+        assert!(volume > 0.0, "volume {} is not positive", volume);
+        assert!(
+            (volume - expected).abs() < expected * tolerance,
+            "volume {} is not close to {}", volume, expected
+        );
+    }
+
+    #[test]
+    fn cuboid_is_closed_and_outward() {
+        let cuboid = Cuboid::new(1.0, 2.0, 3.0);
+        assert_closed(cuboid.mesh());
+        assert_volume(cuboid.mesh(), 6.0, 1.0e-5);
+    }
+
+    #[test]
+    fn uv_sphere_is_closed_and_outward() {
+        let sphere = UvSphere::new(1.0, 32, 16);
+        assert_closed(sphere.mesh());
+        assert_volume(sphere.mesh(), 4.0 / 3.0 * PI, 0.05);
+    }
+
+    #[test]
+    fn cylinder_and_cone_are_closed_and_outward() {
+        let cylinder = Cylinder::new(1.0, 1.0, 2.0, 32);
+        assert_closed(cylinder.mesh());
+        assert_volume(cylinder.mesh(), 2.0 * PI, 0.02);
+
+        let cone = Cylinder::cone(1.0, 2.0, 32);
+        assert_closed(cone.mesh());
+        assert_volume(cone.mesh(), 2.0 * PI / 3.0, 0.02);
+    }
+
+    #[test]
+    fn torus_is_closed_and_outward() {
+        let torus = Torus::new(2.0, 0.5, 32, 16);
+        assert_closed(torus.mesh());
+        assert_volume(torus.mesh(), 2.0 * PI * PI * 2.0 * 0.25, 0.05);
+    }
+
+    #[test]
+    fn capsule_is_closed_and_outward() {
+        let capsule = Capsule::new(0.5, 1.0, 16, 8);
+        assert_closed(capsule.mesh());
+        assert_volume(capsule.mesh(), PI * 0.25 + 4.0 / 3.0 * PI * 0.125, 0.03);
+    }
+
+    #[test]
+    fn disk_faces_up_with_one_boundary_loop() {
+        let disk   = Disk::new(1.0, 24);
+        let welded = disk.mesh().weld(1.0e-4);
+
+        for f in 0..welded.triangle_count() {
+            assert!(dot(&welded.face_normal(f), &Vec3::new(0.0, 1.0, 0.0)) > 0.0);
+        }
+
+        let edges = directed_edges(&welded);
+        let boundary = edges.keys().filter(|&&(a, b)| !edges.contains_key(&(b, a))).count();
+        assert_eq!(boundary, 24);
+        assert!(edges.values().all(|&count| count == 1));
+    }
+}
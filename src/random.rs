@@ -0,0 +1,37 @@
+/// Small seedable xorshift* generator, so that procedural content is
+/// reproducible from a seed on every platform.
+#[derive(Clone)]
+pub struct Rng {
+    state : u64
+}
+
+impl Rng {
+    pub fn new(seed: u32) -> Rng {
+        // the state must never be zero
+        Rng { state : (seed as u64).wrapping_mul(0x9E3779B97F4A7C15) | 1 }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 32) as u32
+    }
+
+    /// Returns a value in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / 16777216.0
+    }
+
+    /// Returns a value in [low, high).
+    pub fn range(&mut self, low: f32, high: f32) -> f32 {
+        low + (high - low) * self.next_f32()
+    }
+
+    /// Returns a normally distributed value with mean 0 and deviation 1.
+    pub fn gaussian(&mut self) -> f32 {
+        let u = 1.0 - self.next_f32();
+        let v = self.next_f32();
+        (-2.0 * u.ln()).sqrt() * (2.0 * ::std::f32::consts::PI * v).cos()
+    }
+}
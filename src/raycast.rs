@@ -0,0 +1,457 @@
+//! Ray intersection against the bilinear surface of a heightmap, for
+//! picking points on the terrain and line of sight queries.
+
+use ::{Heightmap, Addressing};
+use ::nalgebra::{Pnt3, Vec3, Norm};
+
+#[derive(Copy, Clone, Debug)]
+pub struct RayHit {
+    pub position : Pnt3<f32>,
+    pub normal   : Vec3<f32>,
+    /// Distance along the ray to the hit.
+    pub distance : f32
+}
+
+/// Finds the first point within max_distance of origin where the ray hits
+/// the heightmap, walking every cell the ray passes over. Suits heightmaps
+/// which change, such as streamed terrain; use MinMaxTree for static ones.
+pub fn raycast(
+    heightmap: &Heightmap, origin: Pnt3<f32>, direction: Vec3<f32>, max_distance: f32
+) -> Option<RayHit> {
+    let dir = direction.normalize();
+    let (t0, t1) = match clip_to_map(heightmap, origin, dir, max_distance) {
+        Some(range) => range,
+        None        => return None
+    };
+
+    cell_walk(origin, dir, t0, t1, 1.0, |i, j, a, b| {
+        intersect_cell(heightmap, i, j, origin, dir, a, b)
+    }).map(|t| hit_at(heightmap, origin, dir, t))
+}
+
+/// Returns true if nothing on the heightmap blocks the line from one point
+/// to the other.
+pub fn line_of_sight(heightmap: &Heightmap, from: Pnt3<f32>, to: Pnt3<f32>) -> bool {
+    let distance = (to - from).norm();
+    distance == 0.0 || raycast(heightmap, from, to - from, distance).is_none()
+}
+
+/// A pyramid of the lowest and highest heights over ever larger squares of
+/// a heightmap, letting rays skip over areas they pass above. It must be
+/// used with the heightmap it was built from.
+pub struct MinMaxTree {
+    /// Size in cells of each level, finest first.
+    sizes  : Vec<(usize, usize)>,
+    bounds : Vec<Vec<(f32, f32)>>,
+    wrap   : bool
+}
+
+impl MinMaxTree {
+    pub fn new(heightmap: &Heightmap) -> MinMaxTree {
+        let wrap = heightmap.addressing() == Addressing::Wrap;
+
+        // a wrapping map has a cell between the last sample and the first
+        let (width, depth) = if wrap {
+            (heightmap.width(), heightmap.depth())
+        } else {
+            (heightmap.width().saturating_sub(1), heightmap.depth().saturating_sub(1))
+        };
+
+        // halve until a single node is left, wrapping levels have to tile
+        // exactly to be addressed modulo their size
+        let mut sizes = vec![(width, depth)];
+        loop {
+            let (w, d) = *sizes.last().unwrap();
+            if (w <= 1 && d <= 1) || (wrap && (w % 2 != 0 || d % 2 != 0)) {
+                break;
+            }
+            sizes.push(((w + 1) / 2, (d + 1) / 2));
+        }
+
+        let mut tree = MinMaxTree {
+            bounds : sizes.iter().map(|&(w, d)| vec![(0.0, 0.0); w * d]).collect(),
+            sizes  : sizes,
+            wrap   : wrap
+        };
+        tree.update(heightmap, 0, 0, heightmap.width(), heightmap.depth());
+        tree
+    }
+
+    /// Recomputes the bounds over samples x..x + width by z..z + depth after
+    /// they have been changed.
+    pub fn update(
+        &mut self, heightmap: &Heightmap, x: usize, z: usize, width: usize, depth: usize
+    ) {
+        let (cells_x, cells_z) = self.sizes[0];
+        if cells_x == 0 || cells_z == 0 {
+            return;
+        }
+
+        // cells also use the samples on their far sides, so the cells before
+        // the changed samples are updated too
+        for j in z as i64 - 1..(z + depth) as i64 {
+            for i in x as i64 - 1..(x + width) as i64 {
+                let cell = match self.cell_index(i, j) {
+                    Some(cell) => cell,
+                    None       => continue
+                };
+                let corners = [
+                    heightmap.texel(i, j),     heightmap.texel(i + 1, j),
+                    heightmap.texel(i, j + 1), heightmap.texel(i + 1, j + 1)
+                ];
+                let lo = corners.iter().fold(::std::f32::INFINITY, |a, &b| a.min(b));
+                let hi = corners.iter().fold(::std::f32::NEG_INFINITY, |a, &b| a.max(b));
+                self.bounds[0][cell] = (lo, hi);
+            }
+        }
+
+        // refresh the parents of the changed cells, level by level. When
+        // wrapping, the cell before the first sample is the last one, so the
+        // whole axis is refreshed
+        let wrap = self.wrap;
+        let span = |start: usize, length: usize, cells: usize| {
+            if wrap && start == 0 {
+                (0, cells)
+            } else {
+                (start.saturating_sub(1), (start + length).min(cells))
+            }
+        };
+        let (mut x0, mut x1) = span(x, width, cells_x);
+        let (mut z0, mut z1) = span(z, depth, cells_z);
+
+        for level in 1..self.sizes.len() {
+            let (w, d)   = self.sizes[level - 1];
+            let (pw, _)  = self.sizes[level];
+            let (px0, pz0, px1, pz1) = (x0 / 2, z0 / 2, (x1 + 1) / 2, (z1 + 1) / 2);
+
+            for pj in pz0..pz1 {
+                for pi in px0..px1 {
+                    let mut bounds = (::std::f32::INFINITY, ::std::f32::NEG_INFINITY);
+                    for j in pj * 2..(pj * 2 + 2).min(d) {
+                        for i in pi * 2..(pi * 2 + 2).min(w) {
+                            let (lo, hi) = self.bounds[level - 1][j * w + i];
+                            bounds = (bounds.0.min(lo), bounds.1.max(hi));
+                        }
+                    }
+                    self.bounds[level][pj * pw + pi] = bounds;
+                }
+            }
+            x0 = px0; z0 = pz0; x1 = px1; z1 = pz1;
+        }
+    }
+
+    /// Index of cell (i, j) in the finest level, or None outside of a
+    /// clamped map.
+    fn cell_index(&self, i: i64, j: i64) -> Option<usize> {
+        let (w, d) = (self.sizes[0].0 as i64, self.sizes[0].1 as i64);
+        if self.wrap {
+            Some(((((j % d) + d) % d) * w + ((i % w) + w) % w) as usize)
+        } else if i < 0 || j < 0 || i >= w || j >= d {
+            None
+        } else {
+            Some((j * w + i) as usize)
+        }
+    }
+
+    /// Bounds of node (x, z) of a level, or None outside of a clamped map.
+    fn node(&self, level: usize, x: i64, z: i64) -> Option<(f32, f32)> {
+        let (w, d) = (self.sizes[level].0 as i64, self.sizes[level].1 as i64);
+        let (x, z) = if self.wrap {
+            (((x % w) + w) % w, ((z % d) + d) % d)
+        } else if x < 0 || z < 0 || x >= w || z >= d {
+            return None;
+        } else {
+            (x, z)
+        };
+        Some(self.bounds[level][(z * w + x) as usize])
+    }
+
+    /// Finds the first point within max_distance of origin where the ray
+    /// hits the heightmap.
+    pub fn raycast(
+        &self, heightmap: &Heightmap, origin: Pnt3<f32>, direction: Vec3<f32>, max_distance: f32
+    ) -> Option<RayHit> {
+        let dir = direction.normalize();
+        let (t0, t1) = match clip_to_map(heightmap, origin, dir, max_distance) {
+            Some(range) => range,
+            None        => return None
+        };
+
+        let top  = self.sizes.len() - 1;
+        let size = (1u64 << top) as f32;
+        cell_walk(origin, dir, t0, t1, size, |i, j, a, b| {
+            self.visit(heightmap, top, i, j, origin, dir, a, b)
+        }).map(|t| hit_at(heightmap, origin, dir, t))
+    }
+
+    /// Returns true if nothing on the heightmap blocks the line from one
+    /// point to the other.
+    pub fn line_of_sight(&self, heightmap: &Heightmap, from: Pnt3<f32>, to: Pnt3<f32>) -> bool {
+        let distance = (to - from).norm();
+        distance == 0.0 || self.raycast(heightmap, from, to - from, distance).is_none()
+    }
+
+    fn visit(
+        &self, heightmap: &Heightmap, level: usize, x: i64, z: i64,
+        origin: Pnt3<f32>, dir: Vec3<f32>, t0: f32, t1: f32
+    ) -> Option<f32> {
+        let (_, hi) = match self.node(level, x, z) {
+            Some(bounds) => bounds,
+            None         => return None
+        };
+
+        // the ray passes over everything in the node
+        if (origin.y + dir.y * t0).min(origin.y + dir.y * t1) > hi {
+            return None;
+        }
+        if level == 0 {
+            return intersect_cell(heightmap, x, z, origin, dir, t0, t1);
+        }
+
+        let size = (1u64 << (level - 1)) as f32;
+        cell_walk(origin, dir, t0, t1, size, |i, j, a, b| {
+            self.visit(heightmap, level - 1, i, j, origin, dir, a, b)
+        })
+    }
+}
+
+/// Limits the ray to the area a clamped heightmap covers.
+fn clip_to_map(
+    heightmap: &Heightmap, origin: Pnt3<f32>, dir: Vec3<f32>, max_distance: f32
+) -> Option<(f32, f32)> {
+    let (mut t0, mut t1) = (0.0, max_distance);
+    if heightmap.addressing() == Addressing::Wrap {
+        return Some((t0, t1));
+    }
+
+    let slabs = [
+        (origin.x, dir.x, (heightmap.width() as f32 - 1.0).max(0.0)),
+        (origin.z, dir.z, (heightmap.depth() as f32 - 1.0).max(0.0))
+    ];
+    for &(o, d, end) in slabs.iter() {
+        if d == 0.0 {
+            if o < 0.0 || o > end {
+                return None;
+            }
+            continue;
+        }
+        let (a, b) = ((0.0 - o) / d, (end - o) / d);
+        t0 = t0.max(a.min(b));
+        t1 = t1.min(a.max(b));
+    }
+
+    if t0 <= t1 { Some((t0, t1)) } else { None }
+}
+
+/// Visits the squares of a grid with the given spacing which the ray
+/// crosses between t0 and t1, in order, with the part of the ray over each,
+/// until visit returns a result.
+fn cell_walk<F>(
+    origin: Pnt3<f32>, dir: Vec3<f32>, t0: f32, t1: f32, size: f32, mut visit: F
+) -> Option<f32>
+    where F: FnMut(i64, i64, f32, f32) -> Option<f32>
+{
+    let start = origin + dir * t0;
+    let mut i = (start.x / size).floor() as i64;
+    let mut j = (start.z / size).floor() as i64;
+
+    // distance along the ray to the next boundary on each axis, and
+    // between boundaries
+    let axis = |p: f32, d: f32, cell: i64| {
+        if d > 0.0 {
+            (1, t0 + ((cell + 1) as f32 * size - p) / d, size / d)
+        } else if d < 0.0 {
+            (-1, t0 + (cell as f32 * size - p) / d, -size / d)
+        } else {
+            (0, ::std::f32::INFINITY, ::std::f32::INFINITY)
+        }
+    };
+    let (step_i, mut next_i, delta_i) = axis(start.x, dir.x, i);
+    let (step_j, mut next_j, delta_j) = axis(start.z, dir.z, j);
+
+    let mut t = t0;
+    loop {
+        let end = next_i.min(next_j).min(t1);
+        if let Some(hit) = visit(i, j, t, end) {
+            return Some(hit);
+        }
+        if end >= t1 {
+            return None;
+        }
+
+        if next_i < next_j {
+            i += step_i;
+            next_i += delta_i;
+        } else {
+            j += step_j;
+            next_j += delta_j;
+        }
+        t = end;
+    }
+}
+
+/// Distance along the ray at which it first meets the bilinear patch over
+/// cell (i, j) between t0 and t1.
+fn intersect_cell(
+    heightmap: &Heightmap, i: i64, j: i64, origin: Pnt3<f32>, dir: Vec3<f32>, t0: f32, t1: f32
+) -> Option<f32> {
+    let h00 = heightmap.texel(i,     j);
+    let h10 = heightmap.texel(i + 1, j);
+    let h01 = heightmap.texel(i,     j + 1);
+    let h11 = heightmap.texel(i + 1, j + 1);
+
+    // measure from the start of the interval so the cell coordinates stay
+    // small, u and v being the position inside the cell
+    let start = origin + dir * t0;
+    let (u, du) = (start.x - i as f32, dir.x);
+    let (v, dv) = (start.z - j as f32, dir.z);
+    let (a, b, k) = (h10 - h00, h01 - h00, h00 - h10 - h01 + h11);
+
+    // the height above the surface along the ray, c + b s + a s^2
+    let qc = start.y - (h00 + a*u + b*v + k*u*v);
+    let qb = dir.y - (a*du + b*dv + k*(u*dv + v*du));
+    let qa = -k * du * dv;
+
+    if qc <= 0.0 {
+        return Some(t0);
+    }
+
+    let span = t1 - t0;
+    let root = if qa.abs() < 1.0e-8 {
+        if qb < 0.0 { Some(-qc / qb) } else { None }
+    } else {
+        let disc = qb*qb - 4.0*qa*qc;
+        if disc < 0.0 {
+            None
+        } else {
+            let sq = disc.sqrt();
+            let (r1, r2) = ((-qb - sq) / (2.0 * qa), (-qb + sq) / (2.0 * qa));
+            let (r1, r2) = if r1 < r2 { (r1, r2) } else { (r2, r1) };
+            if r1 >= 0.0 { Some(r1) } else if r2 >= 0.0 { Some(r2) } else { None }
+        }
+    };
+
+    match root {
+        Some(s) if s <= span => Some(t0 + s),
+        _ => None
+    }
+}
+
+fn hit_at(heightmap: &Heightmap, origin: Pnt3<f32>, dir: Vec3<f32>, t: f32) -> RayHit {
+    let position = origin + dir * t;
+    RayHit {
+        position : position,
+        normal   : heightmap.normal(position.x, position.z),
+        distance : t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Heightmap, Addressing};
+    use ::random::Rng;
+    use ::nalgebra::{Pnt3, Vec3};
+
+    fn hills(width: usize, depth: usize) -> Heightmap {
+        Heightmap::from_fn(width, depth, |x, z| {
+            4.0 * (x as f32 * 0.37).sin() * (z as f32 * 0.21).cos() + 2.0 * (z as f32 * 0.9).sin()
+        })
+    }
+
+    /// A flat map with a ridge 10 high along x = 16.
+    fn ridge() -> Heightmap {
+        Heightmap::from_fn(33, 33, |x, _| if x == 16 { 10.0 } else { 0.0 })
+    }
+
+    /// Casts random rays at the map with both methods and checks they
+    /// agree, returning how many hit.
+    fn compare(map: &Heightmap, seed: u32) -> usize {
+        let tree    = MinMaxTree::new(map);
+        let mut rng = Rng::new(seed);
+        let mut hits = 0;
+
+        for _ in 0..500 {
+            let origin = Pnt3::new(
+                rng.range(-8.0, map.width() as f32 + 8.0),
+                rng.range(-2.0, 12.0),
+                rng.range(-8.0, map.depth() as f32 + 8.0)
+            );
+            let dir = Vec3::new(rng.range(-1.0, 1.0), rng.range(-1.0, 0.2), rng.range(-1.0, 1.0));
+
+            let brute = raycast(map, origin, dir, 80.0);
+            let fast  = tree.raycast(map, origin, dir, 80.0);
+            match (brute, fast) {
+                (None, None) => (),
+                (Some(a), Some(b)) => {
+                    hits += 1;
+                    assert!((a.distance - b.distance).abs() < 1.0e-3,
+                            "ray from {:?} hit at {} and {}", origin, a.distance, b.distance);
+                    // rays starting under the surface hit straight away and
+                    // ones entering a clamped map under its edge hit the side
+                    let p       = a.position;
+                    let surface = map.sample(p.x, p.z);
+                    let side    = map.addressing() == Addressing::Clamp && (
+                        p.x <= 1.0e-3 || p.z <= 1.0e-3 ||
+                        p.x >= map.width() as f32 - 1.001 || p.z >= map.depth() as f32 - 1.001
+                    );
+                    let under = a.distance == 0.0 || side;
+                    assert!((p.y - surface).abs() < 1.0e-2 || (under && p.y < surface),
+                            "hit at {:?} is off the surface at {}", p, surface);
+                },
+                (a, b) => panic!("ray from {:?} {:?}: brute force {:?}, tree {:?}",
+                                 origin, dir, a.map(|h| h.distance), b.map(|h| h.distance))
+            }
+        }
+        hits
+    }
+
+    #[test]
+    fn tree_matches_brute_force_on_clamped_maps() {
+        let hits = compare(&hills(37, 29), 1);
+        assert!(hits > 50, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn tree_matches_brute_force_on_wrapped_maps() {
+        let hits = compare(&hills(32, 32).with_addressing(Addressing::Wrap), 2);
+        assert!(hits > 50, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn ridge_blocks_the_line_of_sight() {
+        let map  = ridge();
+        let tree = MinMaxTree::new(&map);
+        let cases = [
+            // across the ridge below its top
+            (Pnt3::new(4.0, 2.0, 16.0),  Pnt3::new(28.0, 2.0, 16.0),  false),
+            (Pnt3::new(10.0, 1.0, 3.0),  Pnt3::new(20.0, 9.0, 30.0),  false),
+            // over the top of it
+            (Pnt3::new(4.0, 11.0, 16.0), Pnt3::new(28.0, 11.0, 16.0), true),
+            // on the same side of it
+            (Pnt3::new(4.0, 2.0, 5.0),   Pnt3::new(12.0, 2.0, 25.0),  true),
+            (Pnt3::new(20.0, 1.0, 2.0),  Pnt3::new(20.0, 1.0, 30.0),  true)
+        ];
+
+        for &(from, to, visible) in cases.iter() {
+            assert_eq!(line_of_sight(&map, from, to), visible, "from {:?} to {:?}", from, to);
+            assert_eq!(line_of_sight(&map, to, from), visible, "from {:?} to {:?}", to, from);
+            assert_eq!(tree.line_of_sight(&map, from, to), visible, "from {:?} to {:?}", from, to);
+        }
+    }
+
+    #[test]
+    fn updated_trees_see_through_lowered_ridges() {
+        let mut map  = ridge();
+        let mut tree = MinMaxTree::new(&map);
+        let (from, to) = (Pnt3::new(4.0, 2.0, 16.0), Pnt3::new(28.0, 2.0, 16.0));
+        assert!(!tree.line_of_sight(&map, from, to));
+
+        for z in 12..21 {
+            map.set(16, z, 0.0);
+        }
+        tree.update(&map, 16, 12, 1, 9);
+        assert!(tree.line_of_sight(&map, from, to));
+        assert!(line_of_sight(&map, from, to));
+    }
+}
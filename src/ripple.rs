@@ -0,0 +1,154 @@
+use ::RenderableObj;
+use ::nalgebra::{Mat4, Iso3, to_homogeneous, Transformation};
+use ::glium::{Program, Display, DrawParameters, DepthTest, Frame, PolygonMode};
+use ::glium::texture::{Texture2d, UncompressedFloatFormat};
+
+/// Most emitters a RippleRenderer keeps, spawning more replaces the oldest.
+pub const MAX_EMITTERS : usize = 16;
+
+/// A point on the surface which sends out a ring of waves from start_time.
+#[derive(Copy, Clone, Debug)]
+pub struct RippleEmitter {
+    pub x          : f32,
+    pub z          : f32,
+    pub start_time : f32
+}
+
+/// Displaces the y of a flat mesh by circular waves spreading from a set of
+/// emitters.
+pub struct RippleRenderer {
+    pub program   : Program,
+    pub amplitude : f32,
+    /// Wave crests per unit of distance.
+    pub frequency : f32,
+    /// Distance the waves travel per unit of time.
+    pub speed     : f32,
+    /// Rate at which the waves die down with age.
+    pub damping   : f32,
+    pub wire      : bool,
+    emitters      : Vec<RippleEmitter>,
+    emitter_data  : Texture2d
+}
+
+impl RippleRenderer {
+    pub fn new(display: &Display) -> RippleRenderer {
+        RippleRenderer {
+            program      : RippleRenderer::create_shader_program(&display),
+            amplitude    : 2.0,
+            frequency    : 0.25,
+            speed        : 2.0,
+            damping      : 0.1,
+            wire         : true,
+            emitters     : vec![],
+            emitter_data : RippleRenderer::create_emitter_data(&display, &[])
+        }
+    }
+
+    pub fn emitters(&self) -> &[RippleEmitter] {
+        &self.emitters
+    }
+
+    /// Starts a ripple at (x, z) on the mesh at time.
+    pub fn spawn(&mut self, display: &Display, x: f32, z: f32, time: f32) {
+        if self.emitters.len() == MAX_EMITTERS {
+            self.emitters.remove(0);
+        }
+        self.emitters.push(RippleEmitter { x : x, z : z, start_time : time });
+        self.emitter_data = RippleRenderer::create_emitter_data(display, &self.emitters);
+    }
+
+    pub fn clear(&mut self, display: &Display) {
+        self.emitters.clear();
+        self.emitter_data = RippleRenderer::create_emitter_data(display, &[]);
+    }
+
+    pub fn draw(
+        &self, frame: &mut Frame,
+        obj: &RenderableObj, proj: &Mat4<f32>, view: &Iso3<f32>, model: &Iso3<f32>, time: f32
+    ) {
+        let mv  = view.prepend_transformation(model);
+        let mvp = *proj * to_homogeneous(&mv);
+
+        let params = DrawParameters {
+            depth_test   : DepthTest::IfLess,
+            depth_write  : true,
+            polygon_mode : if self.wire == true { PolygonMode::Line } else { PolygonMode::Fill },
+            .. ::std::default::Default::default()
+        };
+
+        let uniforms = uniform!(
+            MVP           : mvp,
+            time          : time,
+            amplitude     : self.amplitude,
+            frequency     : self.frequency,
+            speed         : self.speed,
+            damping       : self.damping,
+            emitter_count : self.emitters.len() as i32,
+            emitters      : &self.emitter_data
+        );
+
+        obj.draw(frame, &self.program, &uniforms, &params);
+    }
+
+    /// Packs the emitters into a row of unclamped float texels, as
+    /// (x, z, start_time, 0).
+    fn create_emitter_data(display: &Display, emitters: &[RippleEmitter]) -> Texture2d {
+        let mut row: Vec<(f32, f32, f32, f32)> = emitters.iter()
+            .map(|e| (e.x, e.z, e.start_time, 0.0))
+            .collect();
+        while row.len() < MAX_EMITTERS {
+            row.push((0.0, 0.0, 0.0, 0.0));
+        }
+
+        Texture2d::with_format(
+            display, vec![row], UncompressedFloatFormat::F32F32F32F32, false
+        ).unwrap()
+    }
+
+    fn create_shader_program(display: &Display) -> Program {
+        let vertex_shader_src = r#"
+            #version 330
+            in vec3 position;
+
+            uniform mat4 MVP;
+            uniform float time;
+            uniform float amplitude;
+            uniform float frequency;
+            uniform float speed;
+            uniform float damping;
+            uniform int emitter_count;
+            uniform sampler2D emitters;
+
+            const float PI = 3.14159;
+
+            void main() {
+                float y = 0.0;
+                for (int i = 0; i < emitter_count; i++) {
+                    vec4 emitter = texelFetch(emitters, ivec2(i, 0), 0);
+                    float age      = time - emitter.z;
+                    float distance = length(position.xz - emitter.xy);
+
+                    // nothing moves until the leading wave arrives
+                    float behind = speed * age - distance;
+                    if (age < 0.0 || behind < 0.0) {
+                        continue;
+                    }
+                    y += amplitude * exp(-damping * age) * sin(2.0 * PI * frequency * behind);
+                }
+                gl_Position = MVP * vec4(position.x, y, position.z, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = r#"
+            #version 330
+            out vec4 vFragColor;
+            void main() {
+                vFragColor = vec4(1.0);
+            }
+        "#;
+
+        Program::from_source(
+            display, vertex_shader_src, fragment_shader_src, None
+        ).unwrap()
+    }
+}
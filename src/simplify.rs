@@ -0,0 +1,554 @@
+//! Mesh simplification by quadric error metric edge collapses, following
+//! Garland and Heckbert. Open boundaries are kept in place by adding
+//! heavily weighted planes perpendicular to the boundary edges.
+
+use ::Mesh;
+use ::std::collections::{BinaryHeap, HashMap, HashSet};
+use ::std::cmp::Ordering;
+
+/// When to stop collapsing edges.
+#[derive(Copy, Clone)]
+pub enum SimplifyTarget {
+    /// Stop once the mesh has at most this many triangles.
+    TriangleCount(usize),
+    /// Stop before any collapse which would exceed this squared distance
+    /// error.
+    MaxError(f32)
+}
+
+/// Symmetric 4x4 matrix measuring the squared distance to a set of planes.
+#[derive(Copy, Clone)]
+struct Quadric {
+    m : [f64; 10]
+}
+
+impl Quadric {
+    fn zero() -> Quadric {
+        Quadric { m : [0.0; 10] }
+    }
+
+    /// Quadric of the plane ax + by + cz + d = 0 scaled by weight.
+    fn from_plane(a: f64, b: f64, c: f64, d: f64, weight: f64) -> Quadric {
+        Quadric { m : [
+            a*a*weight, a*b*weight, a*c*weight, a*d*weight,
+                        b*b*weight, b*c*weight, b*d*weight,
+                                    c*c*weight, c*d*weight,
+                                                d*d*weight
+        ] }
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut m = self.m;
+        for i in 0..10 {
+            m[i] += other.m[i];
+        }
+        Quadric { m : m }
+    }
+
+    fn error(&self, p: &[f64; 3]) -> f64 {
+        let m = &self.m;
+        let (x, y, z) = (p[0], p[1], p[2]);
+
+        m[0]*x*x + 2.0*m[1]*x*y + 2.0*m[2]*x*z + 2.0*m[3]*x +
+                       m[4]*y*y + 2.0*m[5]*y*z + 2.0*m[6]*y +
+                                      m[7]*z*z + 2.0*m[8]*z +
+                                                     m[9]
+    }
+
+    /// Position minimising the error, None when the quadric is singular.
+    fn optimal(&self) -> Option<[f64; 3]> {
+        let m = &self.m;
+        let a = [[m[0], m[1], m[2]], [m[1], m[4], m[5]], [m[2], m[5], m[7]]];
+        let b = [-m[3], -m[6], -m[8]];
+
+        let det = a[0][0] * (a[1][1]*a[2][2] - a[1][2]*a[2][1]) -
+                  a[0][1] * (a[1][0]*a[2][2] - a[1][2]*a[2][0]) +
+                  a[0][2] * (a[1][0]*a[2][1] - a[1][1]*a[2][0]);
+        if det.abs() < 1.0e-12 {
+            return None;
+        }
+
+        // Cramer's rule
+        let mut result = [0.0; 3];
+        for col in 0..3 {
+            let mut t = a;
+            for row in 0..3 {
+                t[row][col] = b[row];
+            }
+            result[col] = (
+                t[0][0] * (t[1][1]*t[2][2] - t[1][2]*t[2][1]) -
+                t[0][1] * (t[1][0]*t[2][2] - t[1][2]*t[2][0]) +
+                t[0][2] * (t[1][0]*t[2][1] - t[1][1]*t[2][0])
+            ) / det;
+        }
+        Some(result)
+    }
+}
+
+struct Collapse {
+    cost     : f64,
+    a        : u32,
+    b        : u32,
+    versions : (u32, u32),
+    position : [f64; 3]
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // reversed so the BinaryHeap pops the cheapest collapse first
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+fn sub(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[1]*b[2] - a[2]*b[1], a[2]*b[0] - a[0]*b[2], a[0]*b[1] - a[1]*b[0]]
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0]*b[0] + a[1]*b[1] + a[2]*b[2]
+}
+
+struct Simplifier {
+    positions    : Vec<[f64; 3]>,
+    quadrics     : Vec<Quadric>,
+    versions     : Vec<u32>,
+    removed      : Vec<bool>,
+    faces        : Vec<[u32; 3]>,
+    alive        : Vec<bool>,
+    vertex_faces : Vec<Vec<usize>>,
+    heap         : BinaryHeap<Collapse>
+}
+
+impl Simplifier {
+    fn new(mesh: &Mesh) -> Simplifier {
+        let positions: Vec<[f64; 3]> = mesh.vertices.iter().map(|v| {
+            [v.position[0] as f64, v.position[1] as f64, v.position[2] as f64]
+        }).collect();
+        let faces: Vec<[u32; 3]> = mesh.indices.chunks(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect();
+
+        let mut quadrics     = vec![Quadric::zero(); positions.len()];
+        let mut vertex_faces = vec![vec![]; positions.len()];
+        let mut edge_count   = HashMap::new();
+
+        for (f, tri) in faces.iter().enumerate() {
+            let (p0, p1, p2) = (
+                positions[tri[0] as usize], positions[tri[1] as usize], positions[tri[2] as usize]
+            );
+            let n   = cross(&sub(&p1, &p0), &sub(&p2, &p0));
+            let len = dot(&n, &n).sqrt();
+
+            if len > 0.0 {
+                let n = [n[0] / len, n[1] / len, n[2] / len];
+                let q = Quadric::from_plane(n[0], n[1], n[2], -dot(&n, &p0), 1.0);
+                for &v in tri.iter() {
+                    quadrics[v as usize] = quadrics[v as usize].add(&q);
+                }
+            }
+
+            for k in 0..3 {
+                vertex_faces[tri[k] as usize].push(f);
+                let (a, b) = (tri[k], tri[(k + 1) % 3]);
+                let key = if a < b { (a, b) } else { (b, a) };
+                *edge_count.entry(key).or_insert(0) += 1;
+            }
+        }
+
+        // constrain boundary edges with planes perpendicular to their face
+        for tri in &faces {
+            for k in 0..3 {
+                let (a, b) = (tri[k], tri[(k + 1) % 3]);
+                let key = if a < b { (a, b) } else { (b, a) };
+                if edge_count[&key] != 1 {
+                    continue;
+                }
+
+                let (pa, pb, pc) = (
+                    positions[a as usize], positions[b as usize], positions[tri[(k + 2) % 3] as usize]
+                );
+                let face_n = cross(&sub(&pb, &pa), &sub(&pc, &pa));
+                let n      = cross(&sub(&pb, &pa), &face_n);
+                let len    = dot(&n, &n).sqrt();
+                if len > 0.0 {
+                    let n = [n[0] / len, n[1] / len, n[2] / len];
+                    let q = Quadric::from_plane(n[0], n[1], n[2], -dot(&n, &pa), 1000.0);
+                    quadrics[a as usize] = quadrics[a as usize].add(&q);
+                    quadrics[b as usize] = quadrics[b as usize].add(&q);
+                }
+            }
+        }
+
+        let mut simplifier = Simplifier {
+            versions     : vec![0; positions.len()],
+            removed      : vec![false; positions.len()],
+            alive        : vec![true; faces.len()],
+            positions    : positions,
+            quadrics     : quadrics,
+            faces        : faces,
+            vertex_faces : vertex_faces,
+            heap         : BinaryHeap::new()
+        };
+
+        let mut edges: Vec<(u32, u32)> = edge_count.keys().cloned().collect();
+        edges.sort();
+        for (a, b) in edges {
+            simplifier.push_collapse(a, b);
+        }
+        simplifier
+    }
+
+    fn push_collapse(&mut self, a: u32, b: u32) {
+        let q = self.quadrics[a as usize].add(&self.quadrics[b as usize]);
+        let (pa, pb) = (self.positions[a as usize], self.positions[b as usize]);
+        let mid = [(pa[0] + pb[0]) / 2.0, (pa[1] + pb[1]) / 2.0, (pa[2] + pb[2]) / 2.0];
+
+        let mut best = q.optimal().unwrap_or(mid);
+        for candidate in [pa, pb, mid].iter() {
+            if q.error(candidate) < q.error(&best) {
+                best = *candidate;
+            }
+        }
+
+        self.heap.push(Collapse {
+            cost     : q.error(&best).max(0.0),
+            a        : a,
+            b        : b,
+            versions : (self.versions[a as usize], self.versions[b as usize]),
+            position : best
+        });
+    }
+
+    /// Returns true if moving a and b to position flips any remaining face.
+    fn flips(&self, a: u32, b: u32, position: &[f64; 3]) -> bool {
+        for &v in [a, b].iter() {
+            for &f in &self.vertex_faces[v as usize] {
+                let tri = self.faces[f];
+                if !self.alive[f] || (tri.contains(&a) && tri.contains(&b)) {
+                    continue;
+                }
+
+                let old: Vec<[f64; 3]> = tri.iter().map(|&i| self.positions[i as usize]).collect();
+                let new: Vec<[f64; 3]> = tri.iter().map(|&i| {
+                    if i == a || i == b { *position } else { self.positions[i as usize] }
+                }).collect();
+
+                let n_old = cross(&sub(&old[1], &old[0]), &sub(&old[2], &old[0]));
+                let n_new = cross(&sub(&new[1], &new[0]), &sub(&new[2], &new[0]));
+                if dot(&n_old, &n_new) <= 0.0 {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Returns the vertices sharing a remaining face with v.
+    fn one_ring(&self, v: u32) -> HashSet<u32> {
+        let mut ring = HashSet::new();
+        for &f in &self.vertex_faces[v as usize] {
+            if self.alive[f] {
+                ring.extend(self.faces[f].iter().cloned().filter(|&i| i != v));
+            }
+        }
+        ring
+    }
+
+    /// Returns true if v lies on an edge used by only one remaining face.
+    fn on_boundary(&self, v: u32) -> bool {
+        let mut edge_count = HashMap::new();
+        for &f in &self.vertex_faces[v as usize] {
+            if self.alive[f] {
+                for &i in self.faces[f].iter().filter(|&&i| i != v) {
+                    *edge_count.entry(i).or_insert(0) += 1;
+                }
+            }
+        }
+        edge_count.values().any(|&count| count == 1)
+    }
+
+    /// Link condition: collapsing a and b keeps the surface manifold only if
+    /// the vertices both are connected to are exactly the ones opposite the
+    /// edge, and an interior edge does not join two boundaries.
+    fn keeps_manifold(&self, a: u32, b: u32) -> bool {
+        let opposite: HashSet<u32> = self.vertex_faces[a as usize].iter()
+            .filter(|&&f| self.alive[f] && self.faces[f].contains(&b))
+            .flat_map(|&f| self.faces[f].iter().cloned())
+            .filter(|&i| i != a && i != b)
+            .collect();
+        if opposite.is_empty() {
+            return false;
+        }
+
+        let (ring_a, ring_b) = (self.one_ring(a), self.one_ring(b));
+        if ring_a.intersection(&ring_b).count() != opposite.len() {
+            return false;
+        }
+
+        let boundary_edge = opposite.len() == 1;
+        boundary_edge || !(self.on_boundary(a) && self.on_boundary(b))
+    }
+
+    /// Collapses b into a, returning the number of faces removed.
+    fn collapse(&mut self, a: u32, b: u32, position: [f64; 3]) -> usize {
+        let mut removed_faces = 0;
+
+        self.positions[a as usize] = position;
+        self.quadrics[a as usize]  = self.quadrics[a as usize].add(&self.quadrics[b as usize]);
+        self.removed[b as usize]   = true;
+        self.versions[a as usize] += 1;
+        self.versions[b as usize] += 1;
+
+        let b_faces = self.vertex_faces[b as usize].clone();
+        for f in b_faces {
+            if !self.alive[f] {
+                continue;
+            }
+            if self.faces[f].contains(&a) {
+                self.alive[f] = false;
+                removed_faces += 1;
+                continue;
+            }
+            for k in 0..3 {
+                if self.faces[f][k] == b {
+                    self.faces[f][k] = a;
+                }
+            }
+            self.vertex_faces[a as usize].push(f);
+        }
+
+        let alive = &self.alive;
+        self.vertex_faces[a as usize].retain(|&f| alive[f]);
+
+        let mut neighbours = HashSet::new();
+        for &f in &self.vertex_faces[a as usize] {
+            for &v in self.faces[f].iter() {
+                if v != a {
+                    neighbours.insert(v);
+                }
+            }
+        }
+        let mut neighbours: Vec<u32> = neighbours.into_iter().collect();
+        neighbours.sort();
+        for v in neighbours {
+            self.push_collapse(a, v);
+        }
+
+        removed_faces
+    }
+
+    fn run(&mut self, target: SimplifyTarget) {
+        let mut face_count = self.faces.len();
+
+        while let Some(c) = self.heap.pop() {
+            match target {
+                SimplifyTarget::TriangleCount(count) if face_count <= count => break,
+                SimplifyTarget::MaxError(max) if c.cost > max as f64 => break,
+                _ => ()
+            }
+
+            let (a, b) = (c.a as usize, c.b as usize);
+            if self.removed[a] || self.removed[b] ||
+               c.versions != (self.versions[a], self.versions[b]) {
+                continue;
+            }
+            if !self.keeps_manifold(c.a, c.b) || self.flips(c.a, c.b, &c.position) {
+                continue;
+            }
+
+            face_count -= self.collapse(c.a, c.b, c.position);
+        }
+    }
+
+    fn to_mesh(&self, original: &Mesh) -> Mesh {
+        let mut remap    = vec![0; self.positions.len()];
+        let mut vertices = vec![];
+
+        for (i, p) in self.positions.iter().enumerate() {
+            if self.removed[i] {
+                continue;
+            }
+            let mut vertex = original.vertices[i];
+            vertex.position = [p[0] as f32, p[1] as f32, p[2] as f32];
+            remap[i] = vertices.len() as u32;
+            vertices.push(vertex);
+        }
+
+        let mut indices = vec![];
+        for (f, tri) in self.faces.iter().enumerate() {
+            if self.alive[f] {
+                indices.extend(tri.iter().map(|&v| remap[v as usize]));
+            }
+        }
+
+        let mut mesh = Mesh::from_parts(vertices, indices);
+        mesh.compute_normals();
+        mesh
+    }
+}
+
+/// Simplifies a triangle mesh by repeatedly collapsing the edge whose
+/// removal changes the surface the least. Vertices sharing a position are
+/// merged first so seams do not split the surface.
+pub fn simplify(mesh: &Mesh, target: SimplifyTarget) -> Mesh {
+    let welded = mesh.weld(1.0e-5);
+    let mut simplifier = Simplifier::new(&welded);
+
+    simplifier.run(target);
+    simplifier.to_mesh(&welded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Grid, Vertex};
+    use ::primitives::{UvSphere, Torus};
+    use ::validation::validate;
+
+    fn bumpy_grid() -> Mesh {
+        Grid::new(1.0, 1.0, 17, 17)
+            .with_height(|x, z| 0.1 * (3.0 * x).sin() * (2.0 * z).cos())
+            .to_mesh()
+    }
+
+    #[test]
+    fn stops_at_the_triangle_count() {
+        let sphere = UvSphere::new(1.0, 32, 16).mesh().weld(1.0e-5);
+        let target = sphere.triangle_count() / 4;
+        let result = simplify(&sphere, SimplifyTarget::TriangleCount(target));
+
+        // every collapse on a closed surface removes two triangles
+        let count = result.triangle_count();
+        assert!(count <= target && count + 2 >= target, "{} triangles for {}", count, target);
+    }
+
+    #[test]
+    fn stops_before_exceeding_the_error() {
+        let sphere = UvSphere::new(1.0, 32, 16).mesh().weld(1.0e-5);
+        let strict = simplify(&sphere, SimplifyTarget::MaxError(1.0e-6));
+        let loose  = simplify(&sphere, SimplifyTarget::MaxError(1.0e-2));
+        assert!(loose.triangle_count() < strict.triangle_count());
+
+        for vertex in &strict.vertices {
+            let p = vertex.position;
+            let r = (p[0]*p[0] + p[1]*p[1] + p[2]*p[2]).sqrt();
+            assert!((r - 1.0).abs() < 1.0e-2, "vertex {:?} left the sphere", p);
+        }
+
+        // collapses within a plane cost nothing
+        let flat = Grid::new(1.0, 1.0, 9, 9).to_mesh();
+        let result = simplify(&flat, SimplifyTarget::MaxError(1.0e-6));
+        assert!(result.triangle_count() < flat.triangle_count() / 4);
+        assert!(result.vertices.iter().all(|v| v.position[1].abs() < 1.0e-5));
+    }
+
+    #[test]
+    fn boundary_vertices_stay_on_the_boundary() {
+        let grid   = bumpy_grid();
+        let result = simplify(&grid, SimplifyTarget::TriangleCount(grid.triangle_count() / 4));
+
+        let mut edges = HashMap::new();
+        for tri in result.indices.chunks(3) {
+            for k in 0..3 {
+                let (a, b) = (tri[k], tri[(k + 1) % 3]);
+                *edges.entry(if a < b { (a, b) } else { (b, a) }).or_insert(0) += 1;
+            }
+        }
+
+        // the boundary planes lean with the bumpy faces, so points along
+        // the edges may slide slightly off the outline
+        let on_outline = |p: [f32; 3]| {
+            (p[0].abs() - 1.0).abs() < 1.0e-3 || (p[2].abs() - 1.0).abs() < 1.0e-3
+        };
+        for (&(a, b), &count) in &edges {
+            if count == 1 {
+                for &v in [a, b].iter() {
+                    let p = result.vertices[v as usize].position;
+                    assert!(on_outline(p), "boundary vertex {:?} moved inside", p);
+                }
+            }
+        }
+
+        for &(x, z) in &[(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+            assert!(result.vertices.iter().any(|v| {
+                (v.position[0] - x).abs() < 1.0e-3 && (v.position[2] - z).abs() < 1.0e-3
+            }), "corner {} {} was removed", x, z);
+        }
+    }
+
+    fn mesh_from(positions: &[[f32; 3]], triangles: &[[u32; 3]]) -> Mesh {
+        let mut mesh = Mesh::new();
+        for p in positions {
+            mesh.push_vertex(Vertex::from_position(p[0], p[1], p[2]));
+        }
+        for t in triangles {
+            mesh.push_triangle(t[0], t[1], t[2]);
+        }
+        mesh
+    }
+
+    #[test]
+    fn link_condition_rejects_pinching_collapses() {
+        // the diagonal of a quad joins two boundary vertices
+        let quad = Simplifier::new(&mesh_from(
+            &[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0], [0.0, 0.0, 1.0]],
+            &[[0, 2, 1], [0, 3, 2]]
+        ));
+        assert!(!quad.keeps_manifold(0, 2));
+        assert!(quad.keeps_manifold(0, 1));
+
+        // an open three sided tube, the rim vertices share a neighbour across
+        // the hole as well as the one opposite the edge
+        let mut positions = vec![];
+        for &y in &[1.0, 0.0] {
+            for i in 0..3 {
+                let angle = i as f32 * 2.0 * ::std::f32::consts::PI / 3.0;
+                positions.push([angle.cos(), y, angle.sin()]);
+            }
+        }
+        let mut triangles = vec![];
+        for a in 0..3 {
+            let b = (a + 1) % 3;
+            triangles.push([a + 3, b + 3, b]);
+            triangles.push([a + 3, b, a]);
+        }
+        let tube = Simplifier::new(&mesh_from(&positions, &triangles));
+        assert!(!tube.keeps_manifold(0, 1));
+
+        // an interior vertex may collapse towards the boundary
+        let grid = Simplifier::new(&Grid::new(1.0, 1.0, 3, 3).to_mesh());
+        assert!(grid.keeps_manifold(4, 1));
+    }
+
+    #[test]
+    fn simplified_meshes_are_valid() {
+        let grid = bumpy_grid();
+        let sphere = UvSphere::new(1.0, 32, 16).mesh().weld(1.0e-5);
+        let torus = Torus::new(2.0, 0.5, 32, 16).mesh().weld(1.0e-5);
+
+        for &(name, ref mesh) in &[("grid", grid), ("sphere", sphere), ("torus", torus)] {
+            // aggressive enough for the link condition to matter
+            let result = simplify(mesh, SimplifyTarget::TriangleCount(mesh.triangle_count() / 10));
+            let errors = validate(&result);
+            assert!(errors.is_empty(), "simplified {} is invalid: {:?}", name, errors);
+        }
+    }
+}
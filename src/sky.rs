@@ -0,0 +1,231 @@
+use ::nalgebra::{Vec3, Norm};
+use ::std::f32::consts::PI;
+
+/// Daylight sky after Preetham, Shirley and Smits, "A Practical Analytic
+/// Model for Daylight". The sun crosses the sky from +x at 6 to -x at 18,
+/// highest at noon. Shown by an Environment made with Environment::sky,
+/// and lights a scene through sun_direction and sun_color.
+#[derive(Copy, Clone, Debug)]
+pub struct Sky {
+    /// Hour of the day, from 0 to 24.
+    pub time_of_day : f32,
+    /// Haziness of the air, from 2 for a clear day to 10 for a hazy one.
+    pub turbidity   : f32,
+    /// Angle, in radians, the path of the sun leans from overhead towards -z.
+    pub tilt        : f32,
+    /// Scale of the sky luminance before it is mapped into [0, 1).
+    pub exposure    : f32
+}
+
+/// Perez distribution coefficients A to E, each for luminance Y and
+/// chromaticities x and y.
+pub type PerezCoefficients = [Vec3<f32>; 5];
+
+impl Sky {
+    pub fn new(time_of_day: f32) -> Sky {
+        Sky {
+            time_of_day : time_of_day,
+            turbidity   : 3.0,
+            tilt        : 0.5,
+            exposure    : 0.1
+        }
+    }
+
+    /// Unit direction towards the sun, below the horizon at night.
+    pub fn sun_direction(&self) -> Vec3<f32> {
+        let angle = (self.time_of_day - 6.0) / 12.0 * PI;
+        Vec3::new(
+            angle.cos(), angle.sin() * self.tilt.cos(), -angle.sin() * self.tilt.sin()
+        ).normalize()
+    }
+
+    /// Height of the sun above the horizon, in radians.
+    pub fn sun_elevation(&self) -> f32 {
+        self.sun_direction().y.max(-1.0).min(1.0).asin()
+    }
+
+    /// How much daylight is left, from 1 with the sun up to 0 once it is
+    /// well below the horizon.
+    pub fn daylight(&self) -> f32 {
+        smoothstep(-0.2, 0.05, self.sun_elevation())
+    }
+
+    /// Color of direct sunlight, reddened by the air it passes through.
+    pub fn sun_color(&self) -> Vec3<f32> {
+        let elevation = self.sun_elevation();
+        let zenith    = (90.0 - elevation.max(0.0).to_degrees()).min(90.0);
+
+        // Kasten and Young's relative air mass
+        let air_mass = 1.0 / (zenith.to_radians().cos() +
+            0.50572 * (96.07995 - zenith).powf(-1.6364));
+
+        // Rayleigh optical depths of the whole atmosphere, plus haze
+        let haze  = 0.01 * self.turbidity;
+        let depth = Vec3::new(0.046 + haze, 0.108 + haze, 0.265 + haze);
+        let fade  = smoothstep(-0.05, 0.05, elevation);
+
+        Vec3::new(
+            (-air_mass * depth.x).exp(), (-air_mass * depth.y).exp(), (-air_mass * depth.z).exp()
+        ) * fade
+    }
+
+    /// Light from the whole sky, for the ambient term of the lighting, with
+    /// a little left at night.
+    pub fn ambient_color(&self) -> Vec3<f32> {
+        self.radiance(Vec3::new(0.0, 1.0, 0.0)) * 0.4 + Vec3::new(0.02, 0.02, 0.04)
+    }
+
+    /// Perez coefficients for the turbidity.
+    pub fn coefficients(&self) -> PerezCoefficients {
+        let t = self.turbidity;
+        [
+            Vec3::new( 0.1787 * t - 1.4630, -0.0193 * t - 0.2592, -0.0167 * t - 0.2608),
+            Vec3::new(-0.3554 * t + 0.4275, -0.0665 * t + 0.0008, -0.0950 * t + 0.0092),
+            Vec3::new(-0.0227 * t + 5.3251, -0.0004 * t + 0.2125, -0.0079 * t + 0.2102),
+            Vec3::new( 0.1206 * t - 2.5771, -0.0641 * t - 0.8989, -0.0441 * t - 1.6537),
+            Vec3::new(-0.0670 * t + 0.3703, -0.0033 * t + 0.0452, -0.0109 * t + 0.0529)
+        ]
+    }
+
+    /// Zenith luminance and chromaticity divided by the Perez distribution
+    /// at the zenith, so that multiplying by the distribution in any
+    /// direction gives the sky there. The luminance is dimmed at night.
+    pub fn zenith(&self) -> Vec3<f32> {
+        let t  = self.turbidity;
+        // the model only holds for the sun above the horizon
+        let ts = (PI / 2.0 - self.sun_elevation()).min(PI / 2.0 - 0.01);
+        let (t2, t3) = (ts * ts, ts * ts * ts);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * ts);
+        let y_z = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192) * self.daylight();
+
+        let x_z = t * t * (0.00166 * t3 - 0.00375 * t2 + 0.00209 * ts) +
+            t * (-0.02903 * t3 + 0.06377 * t2 - 0.03202 * ts + 0.00394) +
+            (0.11693 * t3 - 0.21196 * t2 + 0.06052 * ts + 0.25886);
+        let yc_z = t * t * (0.00275 * t3 - 0.00610 * t2 + 0.00317 * ts) +
+            t * (-0.04214 * t3 + 0.08970 * t2 - 0.04153 * ts + 0.00516) +
+            (0.15346 * t3 - 0.26756 * t2 + 0.06670 * ts + 0.26688);
+
+        let at_zenith = perez(&self.coefficients(), 1.0, ts.cos(), ts);
+        Vec3::new(y_z / at_zenith.x, x_z / at_zenith.y, yc_z / at_zenith.z)
+    }
+
+    /// Tone mapped color of the sky seen in direction, computed as the
+    /// Environment shader does.
+    pub fn radiance(&self, direction: Vec3<f32>) -> Vec3<f32> {
+        let dir       = direction.normalize();
+        let sun       = self.sun_direction();
+        let cos_gamma = (dir.x * sun.x + dir.y * sun.y + dir.z * sun.z).max(-1.0).min(1.0);
+
+        let f   = perez(&self.coefficients(), dir.y.max(0.01), cos_gamma, cos_gamma.acos());
+        let z   = self.zenith();
+        let yxy = Vec3::new(z.x * f.x, z.y * f.y, z.z * f.z);
+        xyy_to_rgb(1.0 - (-self.exposure * yxy.x).exp(), yxy.y, yxy.z)
+    }
+}
+
+/// The Perez distribution of luminance and chromaticity for a view
+/// direction cos_theta from the zenith and gamma from the sun.
+fn perez(c: &PerezCoefficients, cos_theta: f32, cos_gamma: f32, gamma: f32) -> Vec3<f32> {
+    let channel = |a: f32, b: f32, cc: f32, d: f32, e: f32| {
+        (1.0 + a * (b / cos_theta).exp()) *
+            (1.0 + cc * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    };
+    Vec3::new(
+        channel(c[0].x, c[1].x, c[2].x, c[3].x, c[4].x),
+        channel(c[0].y, c[1].y, c[2].y, c[3].y, c[4].y),
+        channel(c[0].z, c[1].z, c[2].z, c[3].z, c[4].z)
+    )
+}
+
+/// Converts luminance and chromaticity to linear sRGB.
+fn xyy_to_rgb(luminance: f32, x: f32, y: f32) -> Vec3<f32> {
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Vec3::new(
+        ( 3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        ( 0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0)
+    )
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).max(0.0).min(1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3<f32>, b: Vec3<f32>) {
+        assert!((a - b).norm() < 1.0e-4, "{:?} is not {:?}", a, b);
+    }
+
+    fn is_finite(v: Vec3<f32>) -> bool {
+        v.x.is_finite() && v.y.is_finite() && v.z.is_finite()
+    }
+
+    #[test]
+    fn sun_crosses_the_sky() {
+        let sky = Sky::new(6.0);
+        assert_close(sky.sun_direction(), Vec3::new(1.0, 0.0, 0.0));
+        assert_close(Sky::new(18.0).sun_direction(), Vec3::new(-1.0, 0.0, 0.0));
+
+        let noon = Sky::new(12.0);
+        assert_close(noon.sun_direction(), Vec3::new(0.0, noon.tilt.cos(), -noon.tilt.sin()));
+        assert!((noon.sun_elevation() - (PI / 2.0 - noon.tilt)).abs() < 1.0e-4);
+        assert!(Sky::new(0.0).sun_elevation() < 0.0);
+    }
+
+    #[test]
+    fn sunlight_reddens_towards_the_horizon() {
+        let colors: Vec<Vec3<f32>> = [12.0, 9.0, 7.0, 6.2].iter()
+            .map(|&time| Sky::new(time).sun_color())
+            .collect();
+
+        for pair in colors.windows(2) {
+            let (high, low) = (pair[0], pair[1]);
+            assert!(low.x / low.z > high.x / high.z, "{:?} is not redder than {:?}", low, high);
+            assert!(low.z < high.z);
+        }
+        assert_close(Sky::new(0.0).sun_color(), Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn night_sky_is_finite() {
+        for &time in &[0.0, 3.0, 5.9, 18.5, 22.0] {
+            let sky = Sky::new(time);
+            assert!(is_finite(sky.zenith()), "zenith at {} is {:?}", time, sky.zenith());
+            for &dir in &[Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.1, 0.0), Vec3::new(0.0, -1.0, 0.0)] {
+                let radiance = sky.radiance(dir);
+                assert!(is_finite(radiance), "radiance at {} is {:?}", time, radiance);
+            }
+        }
+    }
+
+    #[test]
+    fn sky_is_brighter_towards_the_sun() {
+        let sky     = Sky::new(9.0);
+        let sun     = sky.sun_direction();
+        let towards = sky.radiance(sun);
+        let away    = sky.radiance(Vec3::new(-sun.x, sun.y, -sun.z));
+
+        assert!(towards.x + towards.y + towards.z > away.x + away.y + away.z,
+                "{:?} towards the sun, {:?} away from it", towards, away);
+    }
+
+    #[test]
+    fn coefficients_match_the_published_table() {
+        // Preetham et al. appendix, evaluated for a turbidity of 2
+        let mut sky = Sky::new(12.0);
+        sky.turbidity = 2.0;
+        let c = sky.coefficients();
+
+        assert_close(c[0], Vec3::new(-1.1056, -0.2978, -0.2942));
+        assert_close(c[1], Vec3::new(-0.2833, -0.1322, -0.1808));
+        assert_close(c[2], Vec3::new( 5.2797,  0.2117,  0.1944));
+        assert_close(c[3], Vec3::new(-2.3359, -1.0271, -1.7419));
+        assert_close(c[4], Vec3::new( 0.2363,  0.0386,  0.0311));
+    }
+}
@@ -0,0 +1,419 @@
+use ::Vertex;
+use ::sky::Sky;
+use ::heightmap_io::{self, HeightmapError};
+use ::nalgebra::{Vec3, Mat4, Iso3, Norm, RotationMatrix};
+use ::glium::{Program, Display, DrawParameters, Frame, Surface, VertexBuffer, Rect};
+use ::glium::index::{NoIndices, PrimitiveType};
+use ::glium::texture::{Texture2d, UncompressedFloatFormat};
+use ::std::fs::File;
+use ::std::path::Path;
+use ::std::io;
+use ::std::fmt;
+
+/// GLSL looking up the color of the environment seen in a world space
+/// direction. Shaders including it must set the uniforms it declares from
+/// an Environment.
+pub const ENVIRONMENT : &'static str = r#"
+    uniform sampler2D environment_map;
+    uniform int environment_kind;
+    uniform vec3 zenith_color;
+    uniform vec3 horizon_color;
+    uniform vec3 ground_color;
+    uniform vec3 sun_direction;
+    uniform vec3 sun_color;
+
+    const float ENVIRONMENT_PI = 3.14159265;
+
+    // the six faces sit side by side in the order +x, -x, +y, -y, +z, -z
+    // with the usual cubemap orientation
+    vec3 environment_cube(vec3 dir) {
+        vec3 a = abs(dir);
+        float face;
+        vec2 st;
+        if (a.x >= a.y && a.x >= a.z) {
+            face = dir.x > 0.0 ? 0.0 : 1.0;
+            st   = vec2(dir.x > 0.0 ? -dir.z : dir.z, -dir.y) / a.x;
+        } else if (a.y >= a.z) {
+            face = dir.y > 0.0 ? 2.0 : 3.0;
+            st   = vec2(dir.x, dir.y > 0.0 ? dir.z : -dir.z) / a.y;
+        } else {
+            face = dir.z > 0.0 ? 4.0 : 5.0;
+            st   = vec2(dir.z > 0.0 ? dir.x : -dir.x, -dir.y) / a.z;
+        }
+
+        // keep away from the face edges so filtering does not bleed over
+        vec2 face_size = vec2(textureSize(environment_map, 0)) / vec2(6.0, 1.0);
+        vec2 uv = clamp(st * 0.5 + 0.5, 0.5 / face_size, 1.0 - 0.5 / face_size);
+        return texture(environment_map, vec2((face + uv.x) / 6.0, uv.y)).rgb;
+    }
+
+    vec3 environment_sun(vec3 dir, float haze) {
+        float facing = max(0.0, dot(dir, sun_direction));
+        float above  = smoothstep(-0.02, 0.02, dir.y);
+        return sun_color * above * (haze * pow(facing, 8.0) + smoothstep(0.9995, 0.9997, facing));
+    }
+
+    vec3 environment_gradient(vec3 dir) {
+        vec3 color = dir.y >= 0.0 ?
+            mix(horizon_color, zenith_color, pow(dir.y, 0.5)) :
+            mix(horizon_color, ground_color, pow(-dir.y, 0.5));
+        return color + environment_sun(dir, 0.25);
+    }
+
+    // the Perez coefficients A to E are in the first five texels and the
+    // scaled zenith values and the exposure in the sixth, see Sky
+    vec3 environment_sky(vec3 dir) {
+        vec3 A      = texelFetch(environment_map, ivec2(0, 0), 0).rgb;
+        vec3 B      = texelFetch(environment_map, ivec2(1, 0), 0).rgb;
+        vec3 C      = texelFetch(environment_map, ivec2(2, 0), 0).rgb;
+        vec3 D      = texelFetch(environment_map, ivec2(3, 0), 0).rgb;
+        vec3 E      = texelFetch(environment_map, ivec2(4, 0), 0).rgb;
+        vec4 zenith = texelFetch(environment_map, ivec2(5, 0), 0);
+
+        float cos_theta = max(dir.y, 0.01);
+        float cos_gamma = clamp(dot(dir, sun_direction), -1.0, 1.0);
+        float gamma     = acos(cos_gamma);
+
+        vec3 F   = (1.0 + A * exp(B / cos_theta)) *
+                   (1.0 + C * exp(D * gamma) + E * cos_gamma * cos_gamma);
+        vec3 Yxy = zenith.xyz * F;
+
+        float Y = 1.0 - exp(-zenith.w * Yxy.x);
+        vec3 XYZ = vec3(Yxy.y / Yxy.z * Y, Y, (1.0 - Yxy.y - Yxy.z) / Yxy.z * Y);
+        vec3 rgb = max(vec3(0.0), mat3(
+             3.2406, -0.9689,  0.0557,
+            -1.5372,  1.8758, -0.2040,
+            -0.4986,  0.0415,  1.0570
+        ) * XYZ);
+
+        vec3 ground = ground_color * max(0.05, sun_color.g);
+        return mix(ground, rgb, smoothstep(-0.05, 0.0, dir.y)) + environment_sun(dir, 0.0);
+    }
+
+    vec3 environment(vec3 dir) {
+        dir = normalize(dir);
+        if (environment_kind == 1) {
+            return environment_cube(dir);
+        }
+        if (environment_kind == 2) {
+            vec2 uv = vec2(
+                atan(dir.z, dir.x) / (2.0 * ENVIRONMENT_PI) + 0.5,
+                acos(clamp(dir.y, -1.0, 1.0)) / ENVIRONMENT_PI
+            );
+            return texture(environment_map, uv).rgb;
+        }
+        if (environment_kind == 3) {
+            return environment_sky(dir);
+        }
+        return environment_gradient(dir);
+    }
+"#;
+
+/// Where the color of an Environment comes from.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EnvironmentKind {
+    /// Procedural sky fading from the horizon to the zenith and the ground,
+    /// with a sun.
+    Gradient,
+    /// Six images of the faces of a cube around the viewer.
+    Cube,
+    /// One image spanning every longitude across and latitude down.
+    Equirectangular,
+    /// Physically based daylight from a Sky.
+    Sky
+}
+
+/// Problems loading the images of an Environment.
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    /// The image could not be decoded, or the images do not fit together.
+    Format(String)
+}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> ImageError {
+        ImageError::Io(err)
+    }
+}
+
+impl From<HeightmapError> for ImageError {
+    fn from(err: HeightmapError) -> ImageError {
+        match err {
+            HeightmapError::Io(err)     => ImageError::Io(err),
+            HeightmapError::Format(msg) => ImageError::Format(msg)
+        }
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImageError::Io(ref err)     => write!(f, "image io error: {}", err),
+            ImageError::Format(ref msg) => write!(f, "invalid image: {}", msg)
+        }
+    }
+}
+
+fn format_error<T>(msg: &str) -> Result<T, ImageError> {
+    Err(ImageError::Format(msg.to_string()))
+}
+
+/// The surroundings of a scene, seen in the sky and reflected by surfaces.
+/// glium 0.4 has no cubemap textures, so cube faces are packed side by side
+/// in one texture and picked in the shader.
+pub struct Environment {
+    pub map           : Texture2d,
+    pub kind          : EnvironmentKind,
+    pub zenith_color  : Vec3<f32>,
+    pub horizon_color : Vec3<f32>,
+    pub ground_color  : Vec3<f32>,
+    /// Unit direction towards the sun, for the gradient and daylight skies.
+    pub sun_direction : Vec3<f32>,
+    pub sun_color     : Vec3<f32>
+}
+
+impl Environment {
+    fn with_map(map: Texture2d, kind: EnvironmentKind) -> Environment {
+        Environment {
+            map           : map,
+            kind          : kind,
+            zenith_color  : Vec3::new(0.15, 0.35, 0.75),
+            horizon_color : Vec3::new(0.7, 0.8, 0.9),
+            ground_color  : Vec3::new(0.25, 0.22, 0.2),
+            sun_direction : Vec3::new(0.3, 0.5, -0.8).normalize(),
+            sun_color     : Vec3::new(1.0, 0.95, 0.85)
+        }
+    }
+
+    /// A procedural gradient sky.
+    pub fn gradient(display: &Display) -> Environment {
+        let map = Texture2d::new(display, vec![vec![(0.0f32, 0.0f32, 0.0f32)]]);
+        Environment::with_map(map, EnvironmentKind::Gradient)
+    }
+
+    /// A cube from the rows of its six equally sized faces, top row first,
+    /// in the order +x, -x, +y, -y, +z, -z.
+    pub fn from_face_rows(display: &Display, faces: Vec<Vec<Vec<(f32, f32, f32)>>>) -> Environment {
+        // a single level, so that lookups clamped inside a face never
+        // filter in texels of its neighbours
+        let map = Texture2d::with_mipmaps(display, pack_faces(faces), false);
+        Environment::with_map(map, EnvironmentKind::Cube)
+    }
+
+    /// A cube from six PNG images, in the order +x, -x, +y, -y, +z, -z.
+    pub fn from_faces<P: AsRef<Path>>(
+        display: &Display, paths: &[P]
+    ) -> Result<Environment, ImageError> {
+        if paths.len() != 6 {
+            return format_error("a cube needs six faces");
+        }
+
+        let mut faces = vec![];
+        for path in paths {
+            faces.push(try!(load_rows(path)));
+        }
+        let size = (faces[0].len(), faces[0][0].len());
+        if faces.iter().any(|face| (face.len(), face[0].len()) != size) {
+            return format_error("cube faces differ in size");
+        }
+        Ok(Environment::from_face_rows(display, faces))
+    }
+
+    /// An equirectangular PNG image, such as a panorama.
+    pub fn from_equirectangular<P: AsRef<Path>>(
+        display: &Display, path: P
+    ) -> Result<Environment, ImageError> {
+        let rows = try!(load_rows(path));
+        Ok(Environment::with_map(Texture2d::new(display, rows), EnvironmentKind::Equirectangular))
+    }
+
+    /// A daylight sky, lit by the sky's sun.
+    pub fn sky(display: &Display, sky: &Sky) -> Environment {
+        let mut environment = Environment::gradient(display);
+        environment.set_sky(display, sky);
+        environment
+    }
+
+    /// Shows sky, call again whenever it changes such as with the time of
+    /// day. The sky's texture is only created the first time.
+    pub fn set_sky(&mut self, display: &Display, sky: &Sky) {
+        let mut row: Vec<(f32, f32, f32, f32)> = sky.coefficients().iter()
+            .map(|c| (c.x, c.y, c.z, 0.0))
+            .collect();
+        let zenith = sky.zenith();
+        row.push((zenith.x, zenith.y, zenith.z, sky.exposure));
+
+        let width = row.len() as u32;
+        if self.kind != EnvironmentKind::Sky || self.map.get_width() != width {
+            self.map = Texture2d::empty_with_format(
+                display, UncompressedFloatFormat::F32F32F32F32, false, width, 1
+            ).unwrap();
+        }
+        self.map.write(Rect { left : 0, bottom : 0, width : width, height : 1 }, vec![row]);
+
+        self.kind          = EnvironmentKind::Sky;
+        self.sun_direction = sky.sun_direction();
+        self.sun_color     = sky.sun_color();
+    }
+
+    /// Number passed to the environment_kind uniform.
+    pub fn kind_index(&self) -> i32 {
+        match self.kind {
+            EnvironmentKind::Gradient        => 0,
+            EnvironmentKind::Cube            => 1,
+            EnvironmentKind::Equirectangular => 2,
+            EnvironmentKind::Sky             => 3
+        }
+    }
+}
+
+/// Places the rows of six equally sized faces side by side.
+fn pack_faces(faces: Vec<Vec<Vec<(f32, f32, f32)>>>) -> Vec<Vec<(f32, f32, f32)>> {
+    assert!(faces.len() == 6, "a cube needs six faces");
+    let (height, width) = (faces[0].len(), faces[0].get(0).map_or(0, |row| row.len()));
+    assert!(width > 0 && height > 0, "cube faces are empty");
+    assert!(faces.iter().all(|face| {
+        face.len() == height && face.iter().all(|row| row.len() == width)
+    }), "cube faces differ in size");
+
+    let mut rows = faces[0].clone();
+    for face in faces.iter().skip(1) {
+        for (row, face_row) in rows.iter_mut().zip(face.iter()) {
+            row.extend(face_row.iter().cloned());
+        }
+    }
+    rows
+}
+
+/// Rows of RGB pixels of a PNG image, top row first.
+fn load_rows<P: AsRef<Path>>(path: P) -> Result<Vec<Vec<(f32, f32, f32)>>, ImageError> {
+    let image = try!(heightmap_io::decode_png(try!(File::open(path))));
+    if image.width == 0 || image.height == 0 {
+        return format_error("empty image");
+    }
+
+    Ok((0..image.height).map(|y| {
+        (0..image.width).map(|x| {
+            let at = (y * image.width + x) * image.channels;
+            let s  = &image.samples[at..at + image.channels];
+            if image.channels >= 3 { (s[0], s[1], s[2]) } else { (s[0], s[0], s[0]) }
+        }).collect()
+    }).collect())
+}
+
+/// Draws an Environment behind everything else, call it first each frame.
+pub struct SkyboxRenderer {
+    pub program : Program,
+    fs_quad     : VertexBuffer<Vertex>
+}
+
+impl SkyboxRenderer {
+    pub fn new(display: &Display) -> SkyboxRenderer {
+        SkyboxRenderer {
+            program : SkyboxRenderer::create_shader_program(&display),
+            fs_quad : VertexBuffer::new(display, vec![
+                Vertex::from_position(-1.0, -1.0, 0.0),
+                Vertex::from_position( 1.0, -1.0, 0.0),
+                Vertex::from_position(-1.0,  1.0, 0.0),
+                Vertex::from_position( 1.0,  1.0, 0.0)
+            ])
+        }
+    }
+
+    pub fn draw(
+        &self, frame: &mut Frame, environment: &Environment, proj: &Mat4<f32>, view: &Iso3<f32>
+    ) {
+        let uniforms = uniform!(
+            P                : *proj,
+            view_rotation    : *view.to_rot_mat().submat(),
+            environment_map  : &environment.map,
+            environment_kind : environment.kind_index(),
+            zenith_color     : environment.zenith_color,
+            horizon_color    : environment.horizon_color,
+            ground_color     : environment.ground_color,
+            sun_direction    : environment.sun_direction,
+            sun_color        : environment.sun_color
+        );
+
+        // only the direction of the view matters, nothing is written to
+        // the depth buffer so the scene draws over the sky
+        let params = DrawParameters {
+            depth_write : false,
+            .. ::std::default::Default::default()
+        };
+
+        frame.draw(
+            &self.fs_quad, &NoIndices(PrimitiveType::TriangleStrip),
+            &self.program, &uniforms, &params
+        ).unwrap();
+    }
+
+    fn create_shader_program(display: &Display) -> Program {
+        let vertex_shader_src = r#"
+            #version 330
+            in vec3 position;
+            smooth out vec3 view_direction;
+
+            uniform mat4 P;
+            uniform mat3 view_rotation;
+
+            void main() {
+                vec4 eye = inverse(P) * vec4(position.xy, 1.0, 1.0);
+                view_direction = transpose(view_rotation) * (eye.xyz / eye.w);
+                gl_Position = vec4(position.xy, 1.0, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = [r#"
+            #version 330
+        "#, ENVIRONMENT, r#"
+            smooth in vec3 view_direction;
+            out vec4 vFragColor;
+
+            void main() {
+                vFragColor = vec4(environment(view_direction), 1.0);
+            }
+        "#].concat();
+
+        Program::from_source(
+            display, vertex_shader_src, &fragment_shader_src, None
+        ).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn face(value: f32, width: usize, height: usize) -> Vec<Vec<(f32, f32, f32)>> {
+        vec![vec![(value, value, value); width]; height]
+    }
+
+    #[test]
+    fn faces_are_packed_side_by_side() {
+        let rows = pack_faces((0..6).map(|i| face(i as f32, 2, 3)).collect());
+
+        assert_eq!(rows.len(), 3);
+        for row in &rows {
+            let reds: Vec<f32> = row.iter().map(|p| p.0).collect();
+            assert_eq!(reds, vec![0.0, 0.0, 1.0, 1.0, 2.0, 2.0, 3.0, 3.0, 4.0, 4.0, 5.0, 5.0]);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "cube faces differ in size")]
+    fn faces_of_different_sizes_are_rejected() {
+        let mut faces: Vec<_> = (0..6).map(|i| face(i as f32, 2, 2)).collect();
+        faces[3] = face(3.0, 3, 2);
+        pack_faces(faces);
+    }
+
+    #[test]
+    fn image_errors_keep_the_decoder_message() {
+        match ImageError::from(HeightmapError::Format("not a PNG image".to_string())) {
+            ImageError::Format(msg) => assert_eq!(msg, "not a PNG image"),
+            error => panic!("unexpected {:?}", error)
+        }
+    }
+}
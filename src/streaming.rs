@@ -0,0 +1,175 @@
+use ::{Heightmap, Addressing, FreeCamera};
+use ::noise::Noise2;
+use ::glium::Rect;
+use ::glium::texture::Texture2d;
+
+/// Produces the heights of square tiles of an unbounded world, generated or
+/// loaded. Tile (x, z) covers world positions [x * size, (x + 1) * size)
+/// along each axis.
+pub trait TileSource {
+    fn tile(&self, x: i32, z: i32, size: usize) -> Heightmap;
+}
+
+impl<F> TileSource for F where F: Fn(i32, i32, usize) -> Heightmap {
+    fn tile(&self, x: i32, z: i32, size: usize) -> Heightmap {
+        self(x, z, size)
+    }
+}
+
+/// Generates tiles from a noise function sampled at world positions, so
+/// neighbouring tiles join up without seams.
+pub struct NoiseTiles<N> {
+    pub noise     : N,
+    pub frequency : f32,
+    pub amplitude : f32
+}
+
+impl<N: Noise2> NoiseTiles<N> {
+    pub fn new(noise: N, frequency: f32, amplitude: f32) -> NoiseTiles<N> {
+        NoiseTiles { noise : noise, frequency : frequency, amplitude : amplitude }
+    }
+}
+
+impl<N: Noise2> TileSource for NoiseTiles<N> {
+    fn tile(&self, x: i32, z: i32, size: usize) -> Heightmap {
+        let (ox, oz) = (x as f32 * size as f32, z as f32 * size as f32);
+        Heightmap::from_fn(size, size, |sx, sz| {
+            self.noise.get((ox + sx as f32) * self.frequency, (oz + sz as f32) * self.frequency) *
+                self.amplitude
+        })
+    }
+}
+
+/// Keeps the tiles around the camera resident in a toroidal heightmap,
+/// tiles x tiles tiles across. World position p is stored at p modulo the
+/// heightmap size, so a tile entering the window replaces the distant tile
+/// it wraps onto and the rest stay where they are.
+pub struct TerrainStream {
+    pub source    : Box<TileSource>,
+    pub tile_size : usize,
+    pub tiles     : i32,
+    /// Most tiles loaded by one update, nearest first, to spread the cost
+    /// of crossing a tile boundary over several frames.
+    pub budget    : usize,
+    /// The tile held by each slot of the heightmap.
+    slots         : Vec<Option<(i32, i32)>>
+}
+
+impl TerrainStream {
+    /// Creates a stream of tile_size square tiles, tiles must be odd so the
+    /// window reaches equally far on every side of the camera's tile.
+    pub fn new(source: Box<TileSource>, tile_size: usize, tiles: i32) -> TerrainStream {
+        assert!(tiles > 0 && tiles % 2 == 1, "a stream needs an odd number of tiles, not {}", tiles);
+        TerrainStream {
+            source    : source,
+            tile_size : tile_size,
+            tiles     : tiles,
+            budget    : 4,
+            slots     : vec![None; (tiles * tiles) as usize]
+        }
+    }
+
+    /// Side length, in samples, of the resident heightmap.
+    pub fn size(&self) -> usize {
+        self.tile_size * self.tiles as usize
+    }
+
+    /// Returns true once every tile around the camera is resident.
+    pub fn is_complete(&self, camera: &FreeCamera) -> bool {
+        self.missing(camera).is_empty()
+    }
+
+    /// Tiles around the camera which are not resident, nearest first.
+    fn missing(&self, camera: &FreeCamera) -> Vec<(i32, i32)> {
+        let size = self.tile_size as f32;
+        let (cx, cz) = ((camera.pos.x / size).floor() as i32, (camera.pos.z / size).floor() as i32);
+        let half = self.tiles / 2;
+
+        let mut missing = vec![];
+        for z in cz - half..cz + half + 1 {
+            for x in cx - half..cx + half + 1 {
+                if self.slots[self.slot(x, z)] != Some((x, z)) {
+                    missing.push((x, z));
+                }
+            }
+        }
+        missing.sort_by(|a, b| {
+            let d = |t: &(i32, i32)| (t.0 - cx).abs().max((t.1 - cz).abs());
+            d(a).cmp(&d(b))
+        });
+        missing
+    }
+
+    fn slot(&self, x: i32, z: i32) -> usize {
+        let wrap = |v: i32| ((v % self.tiles) + self.tiles) % self.tiles;
+        (wrap(z) * self.tiles + wrap(x)) as usize
+    }
+
+    /// Loads up to budget of the missing tiles around the camera into the
+    /// heightmap texture and its CPU copy, evicting the tiles they replace.
+    pub fn update(&mut self, camera: &FreeCamera, heightmap: &Texture2d, heights: &mut Heightmap) {
+        let size = self.tile_size;
+
+        for (x, z) in self.missing(camera).into_iter().take(self.budget) {
+            let slot = self.slot(x, z);
+            let tile = self.source.tile(x, z, size);
+
+            let (left, bottom) = ((slot % self.tiles as usize) * size, (slot / self.tiles as usize) * size);
+            for sz in 0..size {
+                for sx in 0..size {
+                    heights.set(left + sx, bottom + sz, tile.get(sx, sz));
+                }
+            }
+
+            heightmap.write(Rect {
+                left   : left as u32,
+                bottom : bottom as u32,
+                width  : size as u32,
+                height : size as u32
+            }, tile.to_rows());
+
+            self.slots[slot] = Some((x, z));
+        }
+    }
+
+    /// An empty heightmap of the stream's size addressed like the texture.
+    pub fn empty_heights(&self) -> Heightmap {
+        Heightmap::new(self.size(), self.size()).with_addressing(Addressing::Wrap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::nalgebra::Pnt3;
+    use ::std::collections::HashSet;
+
+    fn flat_stream(tiles: i32) -> TerrainStream {
+        TerrainStream::new(Box::new(|_, _, size| Heightmap::new(size, size)), 8, tiles)
+    }
+
+    #[test]
+    fn window_surrounds_the_camera_evenly() {
+        let stream = flat_stream(5);
+        let mut camera = FreeCamera::new(1.0, 60.0, 0.1, 100.0);
+        camera.pos = Pnt3::new(-4.0, 0.0, 20.0);
+
+        // the camera is in tile (-1, 2)
+        let missing = stream.missing(&camera);
+        assert_eq!(missing.len(), 25);
+        assert_eq!(missing[0], (-1, 2));
+        for &(x, z) in &missing {
+            assert!(x >= -3 && x <= 1 && z >= 0 && z <= 4, "tile {} {} is outside", x, z);
+        }
+
+        // every tile of the window has a slot of its own
+        let slots: HashSet<usize> = missing.iter().map(|&(x, z)| stream.slot(x, z)).collect();
+        assert_eq!(slots.len(), 25);
+    }
+
+    #[test]
+    #[should_panic(expected = "odd number of tiles")]
+    fn even_tile_counts_are_rejected() {
+        flat_stream(4);
+    }
+}
@@ -0,0 +1,370 @@
+//! Smoothing subdivision surfaces. Loop subdivision works on triangle
+//! meshes and Catmull-Clark on quad meshes, both keep open boundaries as
+//! curves which only depend on other boundary vertices.
+//!
+//! Vertices sharing a position, such as at texture seams, are merged before
+//! subdividing so that the surface stays connected. Normals are recomputed
+//! from the result and texture coordinates are not kept.
+
+use ::{Vertex, Mesh};
+use ::nalgebra::Vec3;
+use ::std::collections::HashMap;
+
+/// A mesh made of quads, the input and output of Catmull-Clark subdivision.
+#[derive(Clone)]
+pub struct QuadMesh {
+    pub positions : Vec<Vec3<f32>>,
+    pub quads     : Vec<[u32; 4]>
+}
+
+impl QuadMesh {
+    pub fn new(positions: Vec<Vec3<f32>>, quads: Vec<[u32; 4]>) -> QuadMesh {
+        QuadMesh { positions : positions, quads : quads }
+    }
+
+    /// Splits every quad into two triangles and computes smooth normals.
+    pub fn to_mesh(&self) -> Mesh {
+        let vertices = self.positions.iter()
+            .map(|p| Vertex::from_position(p.x, p.y, p.z))
+            .collect();
+
+        let mut mesh = Mesh::from_parts(vertices, vec![]);
+        for q in &self.quads {
+            mesh.push_triangle(q[0], q[1], q[2]);
+            mesh.push_triangle(q[0], q[2], q[3]);
+        }
+        mesh.compute_normals();
+        mesh
+    }
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+/// The keys of an edge map in ascending order, so that new vertices are
+/// numbered the same way every run.
+fn sorted_edges<T>(edges: &HashMap<(u32, u32), T>) -> Vec<(u32, u32)> {
+    let mut keys: Vec<(u32, u32)> = edges.keys().cloned().collect();
+    keys.sort();
+    keys
+}
+
+/// Applies levels steps of Loop subdivision to a triangle mesh.
+pub fn loop_subdivide(mesh: &Mesh, levels: u32) -> Mesh {
+    let welded        = mesh.weld(1.0e-5);
+    let mut positions = welded.vertices.iter()
+        .map(|v| Vec3::new(v.position[0], v.position[1], v.position[2]))
+        .collect();
+    let mut indices   = welded.indices;
+
+    for _ in 0..levels {
+        let (p, i) = loop_step(&positions, &indices);
+        positions = p;
+        indices   = i;
+    }
+
+    let vertices = positions.iter()
+        .map(|p| Vertex::from_position(p.x, p.y, p.z))
+        .collect();
+    let mut result = Mesh::from_parts(vertices, indices);
+    result.compute_normals();
+    result
+}
+
+fn loop_step(positions: &Vec<Vec3<f32>>, indices: &Vec<u32>) -> (Vec<Vec3<f32>>, Vec<u32>) {
+    // vertices opposite to each edge, one for boundary edges and two for
+    // interior edges
+    let mut opposite: HashMap<(u32, u32), Vec<u32>> = HashMap::new();
+    for tri in indices.chunks(3) {
+        for k in 0..3 {
+            let (a, b, c) = (tri[k], tri[(k + 1) % 3], tri[(k + 2) % 3]);
+            opposite.entry(edge_key(a, b)).or_insert(vec![]).push(c);
+        }
+    }
+
+    let edge_list = sorted_edges(&opposite);
+
+    let mut neighbours          = vec![vec![]; positions.len()];
+    let mut boundary_neighbours = vec![vec![]; positions.len()];
+    for &(a, b) in &edge_list {
+        let opp = &opposite[&(a, b)];
+        neighbours[a as usize].push(b);
+        neighbours[b as usize].push(a);
+        if opp.len() != 2 {
+            boundary_neighbours[a as usize].push(b);
+            boundary_neighbours[b as usize].push(a);
+        }
+    }
+
+    // even vertices, repositioned originals
+    let mut new_positions = vec![];
+    for (v, p) in positions.iter().enumerate() {
+        let ring     = &neighbours[v];
+        let boundary = &boundary_neighbours[v];
+
+        let moved = if boundary.len() == 2 {
+            *p * 0.75 + (positions[boundary[0] as usize] + positions[boundary[1] as usize]) * 0.125
+        }
+        else if boundary.len() > 0 || ring.len() == 0 {
+            *p
+        }
+        else {
+            let n    = ring.len() as f32;
+            let beta = if ring.len() == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * n) };
+            let mut sum = Vec3::new(0.0, 0.0, 0.0);
+            for &r in ring {
+                sum = sum + positions[r as usize];
+            }
+            *p * (1.0 - n * beta) + sum * beta
+        };
+        new_positions.push(moved);
+    }
+
+    // odd vertices, one per edge
+    let mut edge_points = HashMap::new();
+    for &(a, b) in &edge_list {
+        let opp      = &opposite[&(a, b)];
+        let (pa, pb) = (positions[a as usize], positions[b as usize]);
+
+        let point = if opp.len() == 2 {
+            (pa + pb) * 0.375 + (positions[opp[0] as usize] + positions[opp[1] as usize]) * 0.125
+        }
+        else {
+            (pa + pb) * 0.5
+        };
+        edge_points.insert((a, b), new_positions.len() as u32);
+        new_positions.push(point);
+    }
+
+    let mut new_indices = vec![];
+    for tri in indices.chunks(3) {
+        let (a, b, c) = (tri[0], tri[1], tri[2]);
+        let ab = edge_points[&edge_key(a, b)];
+        let bc = edge_points[&edge_key(b, c)];
+        let ca = edge_points[&edge_key(c, a)];
+
+        new_indices.extend([
+            a, ab, ca,
+            ab, b, bc,
+            ca, bc, c,
+            ab, bc, ca
+        ].iter().cloned());
+    }
+
+    (new_positions, new_indices)
+}
+
+/// Applies levels steps of Catmull-Clark subdivision to a quad mesh.
+pub fn catmull_clark(mesh: &QuadMesh, levels: u32) -> QuadMesh {
+    let mut result = mesh.clone();
+    for _ in 0..levels {
+        result = catmull_clark_step(&result);
+    }
+    result
+}
+
+fn catmull_clark_step(mesh: &QuadMesh) -> QuadMesh {
+    let positions = &mesh.positions;
+
+    let face_points: Vec<Vec3<f32>> = mesh.quads.iter().map(|q| {
+        (positions[q[0] as usize] + positions[q[1] as usize] +
+         positions[q[2] as usize] + positions[q[3] as usize]) * 0.25
+    }).collect();
+
+    // faces adjacent to each edge
+    let mut edge_faces: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+    for (f, q) in mesh.quads.iter().enumerate() {
+        for k in 0..4 {
+            edge_faces.entry(edge_key(q[k], q[(k + 1) % 4])).or_insert(vec![]).push(f);
+        }
+    }
+
+    let mut vertex_faces = vec![vec![]; positions.len()];
+    for (f, q) in mesh.quads.iter().enumerate() {
+        for &v in q.iter() {
+            vertex_faces[v as usize].push(f);
+        }
+    }
+
+    let edge_list = sorted_edges(&edge_faces);
+
+    let mut vertex_edges        = vec![vec![]; positions.len()];
+    let mut boundary_neighbours = vec![vec![]; positions.len()];
+    for &(a, b) in &edge_list {
+        let faces = &edge_faces[&(a, b)];
+        vertex_edges[a as usize].push(b);
+        vertex_edges[b as usize].push(a);
+        if faces.len() != 2 {
+            boundary_neighbours[a as usize].push(b);
+            boundary_neighbours[b as usize].push(a);
+        }
+    }
+
+    let mut new_positions = vec![];
+    for (v, p) in positions.iter().enumerate() {
+        let boundary = &boundary_neighbours[v];
+        let edges    = &vertex_edges[v];
+        let faces    = &vertex_faces[v];
+
+        let moved = if boundary.len() == 2 {
+            *p * 0.75 + (positions[boundary[0] as usize] + positions[boundary[1] as usize]) * 0.125
+        }
+        else if boundary.len() > 0 || edges.len() < 3 {
+            *p
+        }
+        else {
+            let n = edges.len() as f32;
+
+            let mut f = Vec3::new(0.0, 0.0, 0.0);
+            for &face in faces {
+                f = f + face_points[face];
+            }
+            f = f / (faces.len() as f32);
+
+            let mut r = Vec3::new(0.0, 0.0, 0.0);
+            for &e in edges {
+                r = r + (*p + positions[e as usize]) * 0.5;
+            }
+            r = r / n;
+
+            (f + r * 2.0 + *p * (n - 3.0)) / n
+        };
+        new_positions.push(moved);
+    }
+
+    let face_base = new_positions.len() as u32;
+    new_positions.extend(face_points.iter().cloned());
+
+    let mut edge_points = HashMap::new();
+    for &(a, b) in &edge_list {
+        let faces    = &edge_faces[&(a, b)];
+        let (pa, pb) = (positions[a as usize], positions[b as usize]);
+
+        let point = if faces.len() == 2 {
+            (pa + pb + face_points[faces[0]] + face_points[faces[1]]) * 0.25
+        }
+        else {
+            (pa + pb) * 0.5
+        };
+        edge_points.insert((a, b), new_positions.len() as u32);
+        new_positions.push(point);
+    }
+
+    let mut quads = vec![];
+    for (f, q) in mesh.quads.iter().enumerate() {
+        let center = face_base + f as u32;
+        for k in 0..4 {
+            let prev = q[(k + 3) % 4];
+            let next = q[(k + 1) % 4];
+            quads.push([
+                q[k],
+                edge_points[&edge_key(q[k], next)],
+                center,
+                edge_points[&edge_key(prev, q[k])]
+            ]);
+        }
+    }
+
+    QuadMesh::new(new_positions, quads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Grid, Mesh};
+    use ::primitives::Cuboid;
+    use ::nalgebra::Vec3;
+    use ::std::collections::HashMap;
+
+    /// Counts how often each directed edge of the polygons is used.
+    fn directed_edges(polygons: &[Vec<u32>]) -> HashMap<(u32, u32), u32> {
+        let mut edges = HashMap::new();
+        for poly in polygons {
+            for k in 0..poly.len() {
+                *edges.entry((poly[k], poly[(k + 1) % poly.len()])).or_insert(0) += 1;
+            }
+        }
+        edges
+    }
+
+    fn assert_closed(polygons: &[Vec<u32>]) {
+        let edges = directed_edges(polygons);
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge {} {} has no twin", a, b);
+        }
+    }
+
+    fn triangles(mesh: &Mesh) -> Vec<Vec<u32>> {
+        mesh.indices.chunks(3).map(|t| t.to_vec()).collect()
+    }
+
+    fn cube() -> QuadMesh {
+        let positions = [
+            (-1.0, -1.0, -1.0), ( 1.0, -1.0, -1.0), ( 1.0,  1.0, -1.0), (-1.0,  1.0, -1.0),
+            (-1.0, -1.0,  1.0), ( 1.0, -1.0,  1.0), ( 1.0,  1.0,  1.0), (-1.0,  1.0,  1.0)
+        ].iter().map(|&(x, y, z)| Vec3::new(x, y, z)).collect();
+
+        QuadMesh::new(positions, vec![
+            [0, 3, 2, 1], [4, 5, 6, 7], [0, 1, 5, 4],
+            [2, 3, 7, 6], [1, 2, 6, 5], [0, 4, 7, 3]
+        ])
+    }
+
+    #[test]
+    fn loop_step_counts_and_closedness() {
+        // the welded cube has 8 vertices, 18 edges and 12 triangles
+        let cube = loop_subdivide(Cuboid::cube(2.0).mesh(), 1);
+        assert_eq!(cube.vertices.len(), 8 + 18);
+        assert_eq!(cube.triangle_count(), 4 * 12);
+        assert_closed(&triangles(&cube));
+
+        let twice = loop_subdivide(Cuboid::cube(2.0).mesh(), 2);
+        assert_eq!(twice.vertices.len(), 26 + 72);
+        assert_eq!(twice.triangle_count(), 4 * 48);
+        assert_closed(&triangles(&twice));
+    }
+
+    #[test]
+    fn loop_keeps_boundaries_open() {
+        let grid   = Grid::new(2.0, 2.0, 3, 3).to_mesh();
+        let smooth = loop_subdivide(&grid, 1);
+
+        // 9 vertices, 16 edges of which 8 are on the boundary, 8 triangles
+        assert_eq!(smooth.vertices.len(), 9 + 16);
+        assert_eq!(smooth.triangle_count(), 4 * 8);
+
+        let edges = directed_edges(&triangles(&smooth));
+        let open  = edges.keys().filter(|&&(a, b)| !edges.contains_key(&(b, a))).count();
+        assert_eq!(open, 2 * 8);
+    }
+
+    #[test]
+    fn catmull_clark_step_counts_and_closedness() {
+        let once = catmull_clark(&cube(), 1);
+        assert_eq!(once.positions.len(), 8 + 6 + 12);
+        assert_eq!(once.quads.len(), 24);
+        assert_closed(&once.quads.iter().map(|q| q.to_vec()).collect::<Vec<_>>());
+
+        let twice = catmull_clark(&cube(), 2);
+        assert_eq!(twice.positions.len(), 26 + 24 + 48);
+        assert_eq!(twice.quads.len(), 96);
+        assert_closed(&twice.quads.iter().map(|q| q.to_vec()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn subdivision_is_deterministic() {
+        let first  = loop_subdivide(Cuboid::cube(2.0).mesh(), 2);
+        let second = loop_subdivide(Cuboid::cube(2.0).mesh(), 2);
+        assert_eq!(first.indices, second.indices);
+        for (a, b) in first.vertices.iter().zip(second.vertices.iter()) {
+            assert_eq!(a.position, b.position);
+        }
+
+        let first  = catmull_clark(&cube(), 2);
+        let second = catmull_clark(&cube(), 2);
+        assert_eq!(first.positions, second.positions);
+        assert_eq!(first.quads, second.quads);
+    }
+}
@@ -0,0 +1,658 @@
+use ::{
+    Vertex, Mesh, Heightmap, Addressing, RenderableIndices, RenderableObj, BuildRenderable,
+    FreeCamera
+};
+use ::glium::{
+    Display, VertexBuffer, Program, DrawParameters, DepthTest, Frame, Surface,
+    PolygonMode
+};
+use ::glium::index::{NoIndices, PrimitiveType};
+use ::glium::texture::{Texture2d, UncompressedFloatFormat};
+use ::nalgebra::{Vec3, Norm, to_homogeneous};
+use ::noise::{Noise2, Fbm, ValueNoise};
+use ::lighting::BLINN_PHONG;
+use ::streaming::TerrainStream;
+use ::sky::Sky;
+use ::fog::{Fog, FOG};
+use ::std::ops::Range;
+
+/// Size, in texels, of the square heightmap the terrain samples.
+pub const HEIGHTMAP_SIZE : u32 = 1024;
+
+/// Vertices along the side of one block of a clipmap level. Each level is
+/// four blocks plus two cells wide.
+pub const CLIPMAP_BLOCK : i32 = 32;
+
+/// Cells along the side of one block.
+const CELLS : i32 = CLIPMAP_BLOCK - 1;
+
+/// Terrain rendered as a geometry clipmap, nested square levels which
+/// double in spacing with every level, following the camera over a
+/// wrapping heightmap. With a stream the heightmap is a window onto an
+/// unbounded world instead of repeating.
+pub struct Terrain {
+    pub renderer     : TerrainRenderer,
+    pub heightmap    : Texture2d,
+    /// CPU copy of the heightmap, wrapping like the texture does.
+    pub heights      : Heightmap,
+    /// Fills the hole in the middle of the finest level.
+    pub center       : RenderableObj,
+    pub ring         : RenderableObj,
+    /// The four orientations of the L shaped strip filling the gap between
+    /// a level and the finer one inside it, indexed by TerrainTrim::index.
+    pub trims        : Vec<RenderableObj>,
+    /// Number of levels drawn.
+    pub level_count  : i32,
+    pub stream       : Option<TerrainStream>
+}
+
+impl Terrain {
+    /// Creates a terrain using the built-in generated heightmap.
+    pub fn new(display: &Display) -> Terrain {
+        let heightmap = Texture2d::empty_with_format(
+            display, UncompressedFloatFormat::F32, false,
+            HEIGHTMAP_SIZE, HEIGHTMAP_SIZE
+        ).unwrap();
+
+        let generator = HeightmapGenerator::new(display);
+        generator.generate(&heightmap);
+
+        Terrain::from_heightmap(display, heightmap)
+    }
+
+    /// Creates a terrain sampling the given F32 heightmap texture.
+    pub fn from_heightmap(display: &Display, heightmap: Texture2d) -> Terrain {
+        let heights = Heightmap::from_rows(&heightmap.read::<f32, Vec<Vec<f32>>>());
+        Terrain::build(display, heightmap, heights)
+    }
+
+    /// Creates a terrain from heights on the CPU, such as a loaded
+    /// heightmap, resampling them to HEIGHTMAP_SIZE if needed.
+    pub fn from_heights(display: &Display, heights: &Heightmap) -> Terrain {
+        let size    = HEIGHTMAP_SIZE as usize;
+        let heights = if heights.width() == size && heights.depth() == size {
+            heights.clone()
+        } else {
+            heights.resample(size, size)
+        };
+        Terrain::build(display, heights.to_texture(display), heights)
+    }
+
+    /// Creates a terrain whose heightmap is streamed in tiles around the
+    /// camera. The outermost level should fit inside the stream's window.
+    pub fn streamed(display: &Display, stream: TerrainStream) -> Terrain {
+        let size      = stream.size() as u32;
+        let heightmap = Texture2d::empty_with_format(
+            display, UncompressedFloatFormat::F32, false, size, size
+        ).unwrap();
+
+        let mut terrain = Terrain::build(display, heightmap, stream.empty_heights());
+        terrain.stream  = Some(stream);
+        terrain
+    }
+
+    fn build(display: &Display, heightmap: Texture2d, heights: Heightmap) -> Terrain {
+        let trims = (0..4)
+            .map(|i| RenderableObj::new(&TerrainTrim::from_index(i), display))
+            .collect();
+
+        Terrain {
+            renderer     : TerrainRenderer::new(display),
+            heightmap    : heightmap,
+            heights      : heights.with_addressing(Addressing::Wrap),
+            center       : RenderableObj::new(&TerrainMeshCenter, display),
+            ring         : RenderableObj::new(&TerrainRing, display),
+            trims        : trims,
+            level_count  : 5,
+            stream       : None
+        }
+    }
+
+    /// Streams in tiles around the camera, if the terrain has a stream.
+    pub fn update(&mut self, camera: &FreeCamera) {
+        if let Some(ref mut stream) = self.stream {
+            stream.update(camera, &self.heightmap, &mut self.heights);
+        }
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame, camera: &FreeCamera) {
+        self.update(camera);
+
+        for level in 0..self.level_count {
+            self.renderer.level = level;
+            self.renderer.draw(frame, &self.ring, camera, &self.heightmap, &self.heights);
+
+            let fill = if level == 0 {
+                &self.center
+            } else {
+                &self.trims[TerrainTrim::between(level, camera).index()]
+            };
+            self.renderer.draw(frame, fill, camera, &self.heightmap, &self.heights);
+        }
+    }
+}
+
+/// Distance in world units between the vertices of a level.
+fn level_spacing(level: i32) -> i32 {
+    1 << level
+}
+
+/// World position of the lowest corner of a level along one axis. Levels
+/// are snapped to twice their spacing so that every other vertex lies on
+/// the next coarser level's grid.
+fn level_origin(v: f32, level: i32) -> i32 {
+    let snap = 2 * level_spacing(level);
+    ((v / snap as f32).floor() as i32 - CELLS) * snap
+}
+
+pub struct HeightmapGenerator {
+    pub program : Program,
+    pub fs_quad : VertexBuffer<Vertex>
+}
+
+impl HeightmapGenerator {
+    pub fn new(display: &Display) -> HeightmapGenerator {
+        HeightmapGenerator {
+            program : HeightmapGenerator::create_shader_program(&display),
+            fs_quad : HeightmapGenerator::create_fullscreen_quad(&display)
+        }
+    }
+
+    pub fn generate(&self, heightmap: &Texture2d) {
+        let mut surface = heightmap.as_surface();
+
+        surface.clear_color(0.0, 0.0, 0.0, 0.0);
+        surface.draw(
+            &self.fs_quad, &NoIndices(PrimitiveType::TriangleStrip),
+            &self.program, &uniform!(), &::std::default::Default::default()
+        ).unwrap();
+    }
+
+    fn create_fullscreen_quad(display: &Display) -> VertexBuffer<Vertex> {
+        VertexBuffer::new(display, vec![
+            Vertex::from_position(-1.0, -1.0, 0.0),
+            Vertex::from_position( 1.0, -1.0, 0.0),
+            Vertex::from_position(-1.0,  1.0, 0.0),
+            Vertex::from_position( 1.0,  1.0, 0.0)
+        ])
+    }
+
+    fn create_shader_program(display: &Display) -> Program {
+        let vertex_shader_src = r#"
+            #version 330
+
+            in vec3 position;
+            out vec2 pos;
+            void main() {
+                pos = position.xy;
+                gl_Position = vec4(position, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = r#"
+            #version 330
+
+            in vec2 pos;
+            out vec4 frag_color;
+
+            const vec2 center = vec2(0, 0);
+            void main() {
+
+                frag_color = vec4(5*sin(pos.x*3.1415*10) + 5*cos(pos.y*3.1515*10));
+            }
+        "#;
+
+        Program::from_source(
+            display, vertex_shader_src, fragment_shader_src, None
+        ).unwrap()
+    }
+}
+
+/// Wraps a world coordinate into [0, wrap).
+pub fn wrap_to_size(v: f32, wrap: usize) -> usize {
+    let n = (v.abs() as usize) / wrap;
+    let diff = v.abs() as usize - wrap*n;
+
+    let mut res = if v >= 0.0 {
+        diff
+    } else {
+        wrap - diff
+    };
+
+    if diff == 0 {
+        res = 0;
+    }
+
+    res
+}
+
+/// Surface textures blended over the terrain, each repeating every scale
+/// world units.
+pub struct TerrainMaterials {
+    pub grass : Texture2d,
+    pub rock  : Texture2d,
+    pub snow  : Texture2d,
+    pub scale : f32
+}
+
+impl TerrainMaterials {
+    pub fn new(grass: Texture2d, rock: Texture2d, snow: Texture2d) -> TerrainMaterials {
+        TerrainMaterials { grass : grass, rock : rock, snow : snow, scale : 16.0 }
+    }
+
+    /// Creates procedural grass, rock and snow textures.
+    pub fn generate(display: &Display, seed: u32) -> TerrainMaterials {
+        let size = 256;
+
+        let grass_noise = Fbm::new(ValueNoise::new(seed), 5);
+        let rock_noise  = Fbm::new(ValueNoise::new(seed.wrapping_add(1)), 6);
+        let snow_noise  = Fbm::new(ValueNoise::new(seed.wrapping_add(2)), 3);
+
+        let grass = tileable_texture(display, size, &grass_noise, |n| {
+            (0.20 + 0.06 * n, 0.36 + 0.10 * n, 0.10 + 0.04 * n)
+        });
+        let rock = tileable_texture(display, size, &rock_noise, |n| {
+            let v = 0.42 + 0.15 * n;
+            (v, v * 0.95, v * 0.9)
+        });
+        let snow = tileable_texture(display, size, &snow_noise, |n| {
+            (0.90 + 0.04 * n, 0.92 + 0.04 * n, 0.96 + 0.03 * n)
+        });
+
+        TerrainMaterials::new(grass, rock, snow)
+    }
+}
+
+/// Fills a size x size texture with colors of noise, cross-faded with
+/// offset copies of itself so that opposite edges match.
+fn tileable_texture<N, F>(display: &Display, size: usize, noise: &N, color: F) -> Texture2d
+    where N: Noise2, F: Fn(f32) -> (f32, f32, f32)
+{
+    let frequency = 8.0 / size as f32;
+    let period    = size as f32;
+
+    let rows: Vec<Vec<(f32, f32, f32)>> = (0..size).map(|y| {
+        (0..size).map(|x| {
+            let (x, y)   = (x as f32, y as f32);
+            let (tx, ty) = (x / period, y / period);
+            let at = |ox: f32, oy: f32| noise.get((x - ox) * frequency, (y - oy) * frequency);
+
+            color(
+                at(0.0, 0.0)       * (1.0 - tx) * (1.0 - ty) +
+                at(period, 0.0)    * tx * (1.0 - ty) +
+                at(0.0, period)    * (1.0 - tx) * ty +
+                at(period, period) * tx * ty
+            )
+        }).collect()
+    }).collect();
+
+    Texture2d::new(display, rows)
+}
+
+pub struct TerrainRenderer {
+    pub program         : Program,
+    pub wire            : bool,
+    pub level           : i32,
+    pub materials       : TerrainMaterials,
+    /// Direction towards the light, which is far enough away for the
+    /// direction to be the same everywhere.
+    pub light_direction : Vec3<f32>,
+    pub ambient_color   : Vec3<f32>,
+    pub diffuse_color   : Vec3<f32>,
+    pub specular_color  : Vec3<f32>,
+    pub shininess       : f32,
+    /// Height above which snow covers the ground.
+    pub snow_height     : f32,
+    /// Slope, as 1 - normal.y, above which rock shows through.
+    pub rock_slope      : f32,
+    pub fog             : Fog
+}
+
+impl TerrainRenderer {
+    pub fn new(display: &Display) -> TerrainRenderer {
+        TerrainRenderer {
+            program         : TerrainRenderer::create_shader_program(&display),
+            wire            : false,
+            level           : 0,
+            materials       : TerrainMaterials::generate(display, 0),
+            light_direction : Vec3::new(0.4, 0.8, 0.3).normalize(),
+            ambient_color   : Vec3::new(0.25, 0.27, 0.3),
+            diffuse_color   : Vec3::new(1.0, 0.95, 0.85),
+            specular_color  : Vec3::new(0.1, 0.1, 0.1),
+            shininess       : 16.0,
+            snow_height     : 12.0,
+            rock_slope      : 0.25,
+            fog             : Fog::none()
+        }
+    }
+
+    /// Lights the terrain by the sun of sky, with the sky as ambient light.
+    pub fn set_sky(&mut self, sky: &Sky) {
+        self.light_direction = sky.sun_direction();
+        self.diffuse_color   = sky.sun_color();
+        self.ambient_color   = sky.ambient_color();
+    }
+
+    pub fn draw(
+        &self, frame: &mut Frame,
+        obj: &RenderableObj, camera: &FreeCamera, heightmap: &Texture2d, heights: &Heightmap
+    ) {
+        let proj    = camera.projection.to_mat();
+        let spacing = level_spacing(self.level);
+        let origin  = [
+            level_origin(camera.pos.x, self.level) as f32,
+            level_origin(camera.pos.z, self.level) as f32
+        ];
+
+        // the camera is at least 2 * CELLS from the outer edge of a level and
+        // at most CELLS + 2 from the inner edge, so the morph reaches one at
+        // the outer edge and is zero where the finer level meets this one
+        let morph_width = (CELLS / 3) as f32;
+        let morph_start = (2 * CELLS) as f32 - morph_width;
+
+        let params = DrawParameters {
+            depth_test   : DepthTest::IfLess,
+            depth_write  : true,
+            polygon_mode : if self.wire == true { PolygonMode::Line } else { PolygonMode::Fill },
+            .. ::std::default::Default::default()
+        };
+
+        let uniforms = uniform!(
+            projection      : proj,
+            view_rotation   : to_homogeneous(&camera.get_view_transform().rotation),
+            camera_position : camera.pos,
+            level_origin    : origin,
+            level_spacing   : spacing as f32,
+            morph_start     : morph_start,
+            morph_width     : morph_width,
+            heightmap       : heightmap,
+            heightmap_size  : heights.width() as f32,
+            grass           : &self.materials.grass,
+            rock            : &self.materials.rock,
+            snow            : &self.materials.snow,
+            material_scale  : self.materials.scale,
+            light_direction : self.light_direction.normalize(),
+            ambient_color   : self.ambient_color,
+            diffuse_color   : self.diffuse_color,
+            specular_color  : self.specular_color,
+            shininess       : self.shininess,
+            snow_height     : self.snow_height,
+            rock_slope      : self.rock_slope,
+            fog_mode        : self.fog.mode_index(),
+            fog_params      : self.fog.params(),
+            fog_color       : self.fog.color
+        );
+
+        obj.draw(frame, &self.program, &uniforms, &params);
+    }
+
+    fn create_shader_program(display: &Display) -> Program {
+        let vertex_shader_src = r#"
+            #version 330
+
+            in vec3 position;
+            out vec3 world_position;
+            out vec3 view_offset;
+
+            uniform mat4 projection;
+            uniform mat4 view_rotation;
+            uniform vec3 camera_position;
+            uniform vec2 level_origin;
+            uniform float level_spacing;
+            uniform float morph_start;
+            uniform float morph_width;
+            uniform sampler2D heightmap;
+            uniform float heightmap_size;
+
+            void main() {
+                vec2 world = level_origin + position.xz * level_spacing;
+
+                // slide odd vertices onto their even neighbours towards the
+                // outer edge of the level so it matches the coarser level
+                vec2 dist = abs(world - camera_position.xz) / level_spacing;
+                float morph = clamp(
+                    (max(dist.x, dist.y) - morph_start) / morph_width, 0.0, 1.0
+                );
+                world -= fract(world / (2.0 * level_spacing)) * 2.0 * level_spacing * morph;
+
+                // sample texel centers so heights match the CPU copy
+                float height = texture(heightmap, (world + 0.5) / heightmap_size).r;
+
+                world_position = vec3(world.x, height, world.y);
+                view_offset = vec3(
+                    world.x - camera_position.x,
+                    height - camera_position.y,
+                    world.y - camera_position.z
+                );
+                gl_Position = projection * view_rotation * vec4(view_offset, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = [r#"
+            #version 330
+        "#, BLINN_PHONG, FOG, r#"
+            in vec3 world_position;
+            in vec3 view_offset;
+            out vec4 frag_color;
+
+            uniform sampler2D heightmap;
+            uniform float heightmap_size;
+            uniform sampler2D grass;
+            uniform sampler2D rock;
+            uniform sampler2D snow;
+            uniform float material_scale;
+            uniform vec3 light_direction;
+            uniform vec3 ambient_color;
+            uniform vec3 diffuse_color;
+            uniform vec3 specular_color;
+            uniform float shininess;
+            uniform float snow_height;
+            uniform float rock_slope;
+            uniform vec3 camera_position;
+
+            float height_at(vec2 p) {
+                return texture(heightmap, (p + 0.5) / heightmap_size).r;
+            }
+
+            // projects the texture along all three axes, weighted towards
+            // the axis the surface faces, so steep faces are not stretched
+            vec3 triplanar(sampler2D material, vec3 p, vec3 n) {
+                vec3 weights = pow(abs(n), vec3(8.0));
+                weights /= weights.x + weights.y + weights.z;
+
+                p /= material_scale;
+                return texture(material, p.zy).rgb * weights.x +
+                       texture(material, p.xz).rgb * weights.y +
+                       texture(material, p.xy).rgb * weights.z;
+            }
+
+            void main() {
+                // per pixel normal from central differences of the heightmap
+                vec2 p = world_position.xz;
+                float dx = height_at(p + vec2(1, 0)) - height_at(p - vec2(1, 0));
+                float dz = height_at(p + vec2(0, 1)) - height_at(p - vec2(0, 1));
+                vec3 N = normalize(vec3(-0.5 * dx, 1.0, -0.5 * dz));
+
+                float slope = 1.0 - N.y;
+                float rock_weight = smoothstep(rock_slope - 0.05, rock_slope + 0.05, slope);
+                float snow_weight = smoothstep(snow_height - 1.0, snow_height + 1.0, world_position.y);
+                snow_weight *= 1.0 - rock_weight;
+                float grass_weight = (1.0 - rock_weight) * (1.0 - snow_weight);
+
+                vec3 albedo =
+                    grass_weight * triplanar(grass, world_position, N) +
+                    rock_weight  * triplanar(rock,  world_position, N) +
+                    snow_weight  * triplanar(snow,  world_position, N);
+
+                vec3 V = normalize(-view_offset);
+                vec2 light = blinn_phong(N, light_direction, V, shininess);
+
+                vec3 color = albedo * (ambient_color + light.x * diffuse_color) +
+                             light.y * specular_color;
+                frag_color = vec4(apply_fog(color, camera_position, view_offset), 1.0);
+            }
+        "#].concat();
+
+        Program::from_source(
+            display, vertex_shader_src, &fragment_shader_src, None
+        ).unwrap()
+    }
+}
+
+/// Appends a flat block of unit quads with vertices at xs by zs to mesh.
+fn push_block(mesh: &mut Mesh, xs: Range<i32>, zs: Range<i32>) {
+    let base   = mesh.vertices.len() as u32;
+    let stride = (zs.end - zs.start) as u32;
+    let rows   = (xs.end - xs.start) as u32;
+
+    for x in xs {
+        for z in zs.clone() {
+            mesh.push_vertex(Vertex::new(
+                [x as f32, 0.0, z as f32], [0.0, 1.0, 0.0], [0.0, 0.0]
+            ));
+        }
+    }
+
+    for row in 0..rows-1 {
+        for col in 0..stride-1 {
+            let tl = base + row * stride + col;
+            let tr = tl + stride;
+            let bl = tl + 1;
+            let br = bl + stride;
+
+            mesh.push_triangle(tl, bl, tr);
+            mesh.push_triangle(tr, bl, br);
+        }
+    }
+}
+
+/// Vertex ranges of the four blocks and the two cell wide fix-up gap along
+/// one side of a level, in level spacing units.
+fn block_ranges() -> [Range<i32>; 4] {
+    [
+        0..CELLS + 1,
+        CELLS..2*CELLS + 1,
+        2*CELLS + 2..3*CELLS + 3,
+        3*CELLS + 2..4*CELLS + 3
+    ]
+}
+
+fn fixup_range() -> Range<i32> {
+    2*CELLS..2*CELLS + 3
+}
+
+/// Fills the hole in the middle of the finest level, which has no finer
+/// level inside it.
+pub struct TerrainMeshCenter;
+
+impl TerrainMeshCenter {
+    pub fn mesh(&self) -> Mesh {
+        let mut mesh = Mesh::new();
+        push_block(&mut mesh, CELLS..3*CELLS + 3, CELLS..3*CELLS + 3);
+        mesh
+    }
+}
+
+impl BuildRenderable for TerrainMeshCenter {
+    fn get_vertex_array(&self, display: &Display) -> VertexBuffer<Vertex> {
+        self.mesh().get_vertex_array(display)
+    }
+
+    fn get_indices(&self, display: &Display) -> RenderableIndices {
+        self.mesh().get_indices(display)
+    }
+}
+
+/// The square ring of a clipmap level in level spacing units, twelve
+/// blocks around the outside plus the fix-up strips between them.
+pub struct TerrainRing;
+
+impl TerrainRing {
+    pub fn mesh(&self) -> Mesh {
+        let mut mesh = Mesh::new();
+        let blocks   = block_ranges();
+
+        for i in 0..4 {
+            for j in 0..4 {
+                if i == 0 || i == 3 || j == 0 || j == 3 {
+                    push_block(&mut mesh, blocks[i].clone(), blocks[j].clone());
+                }
+            }
+        }
+
+        for &outer in &[0, 3] {
+            push_block(&mut mesh, fixup_range(), blocks[outer].clone());
+            push_block(&mut mesh, blocks[outer].clone(), fixup_range());
+        }
+        mesh
+    }
+}
+
+impl BuildRenderable for TerrainRing {
+    fn get_vertex_array(&self, display: &Display) -> VertexBuffer<Vertex> {
+        self.mesh().get_vertex_array(display)
+    }
+
+    fn get_indices(&self, display: &Display) -> RenderableIndices {
+        self.mesh().get_indices(display)
+    }
+}
+
+/// The one cell wide L shaped strip inside a level's ring which the finer
+/// level, one cell narrower than the hole, leaves uncovered. Depending on
+/// how the two levels are snapped the strip lies along the low or high
+/// side of each axis.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TerrainTrim {
+    pub low_x : bool,
+    pub low_z : bool
+}
+
+impl TerrainTrim {
+    pub fn from_index(index: usize) -> TerrainTrim {
+        TerrainTrim { low_x : index & 2 != 0, low_z : index & 1 != 0 }
+    }
+
+    pub fn index(&self) -> usize {
+        (self.low_x as usize) * 2 + (self.low_z as usize)
+    }
+
+    /// The strip needed in level, around level - 1, for the camera.
+    pub fn between(level: i32, camera: &FreeCamera) -> TerrainTrim {
+        let spacing = level_spacing(level);
+        let low = |v: f32| {
+            let inner = level_origin(v, level - 1);
+            let hole  = level_origin(v, level) + CELLS * spacing;
+            inner != hole
+        };
+        TerrainTrim { low_x : low(camera.pos.x), low_z : low(camera.pos.z) }
+    }
+
+    pub fn mesh(&self) -> Mesh {
+        let mut mesh = Mesh::new();
+        let hole     = CELLS..3*CELLS + 3;
+        let low      = CELLS..CELLS + 2;
+        let high     = 3*CELLS + 1..3*CELLS + 3;
+
+        let (column, rest) = if self.low_x {
+            (low.clone(), CELLS + 1..3*CELLS + 3)
+        } else {
+            (high.clone(), CELLS..3*CELLS + 2)
+        };
+        let row = if self.low_z { low } else { high };
+
+        push_block(&mut mesh, column, hole);
+        push_block(&mut mesh, rest, row);
+        mesh
+    }
+}
+
+impl BuildRenderable for TerrainTrim {
+    fn get_vertex_array(&self, display: &Display) -> VertexBuffer<Vertex> {
+        self.mesh().get_vertex_array(display)
+    }
+
+    fn get_indices(&self, display: &Display) -> RenderableIndices {
+        self.mesh().get_indices(display)
+    }
+}
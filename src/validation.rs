@@ -0,0 +1,369 @@
+//! Checks for common mistakes in generated meshes, and passes to repair
+//! the ones which can be fixed automatically.
+
+use ::Mesh;
+use ::half_edge::{HalfEdgeMesh, TopologyError};
+use ::nalgebra::Norm;
+use ::std::collections::HashMap;
+
+/// Triangles with less area than this are treated as degenerate.
+const MIN_AREA : f32 = 1.0e-7;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshError {
+    /// A triangle refers to a vertex which does not exist.
+    IndexOutOfRange(usize),
+    /// A triangle repeats a vertex or has no area.
+    DegenerateFace(usize),
+    /// Two triangles traverse the shared edge in the same direction.
+    InconsistentWinding(u32, u32),
+    /// More than two triangles share the edge.
+    NonManifoldEdge(u32, u32),
+    /// The triangles around the vertex form more than one fan.
+    NonManifoldVertex(u32),
+    /// The vertex normal is zero length or not a number.
+    BadNormal(u32)
+}
+
+/// Returns every problem found in the mesh, an empty list means the mesh
+/// is a consistently wound manifold with valid normals.
+pub fn validate(mesh: &Mesh) -> Vec<MeshError> {
+    let mut errors = vec![];
+
+    if let Err(topology) = HalfEdgeMesh::from_mesh(mesh).validate() {
+        for error in topology {
+            errors.push(match error {
+                TopologyError::IndexOutOfRange(f)        => MeshError::IndexOutOfRange(f),
+                TopologyError::DegenerateFace(f)         => MeshError::DegenerateFace(f),
+                TopologyError::NonManifoldEdge(a, b)     => MeshError::NonManifoldEdge(a, b),
+                TopologyError::InconsistentWinding(a, b) => MeshError::InconsistentWinding(a, b),
+                TopologyError::NonManifoldVertex(v)      => MeshError::NonManifoldVertex(v)
+            });
+        }
+    }
+
+    let count = mesh.vertices.len() as u32;
+    for face in 0..mesh.triangle_count() {
+        let tri = &mesh.indices[face*3..face*3 + 3];
+        let distinct = tri[0] != tri[1] && tri[1] != tri[2] && tri[2] != tri[0];
+
+        if distinct && tri.iter().all(|&i| i < count) &&
+           face_area(mesh, face) < MIN_AREA {
+            errors.push(MeshError::DegenerateFace(face));
+        }
+    }
+
+    for (i, vertex) in mesh.vertices.iter().enumerate() {
+        let n  = vertex.normal;
+        let sq = n[0]*n[0] + n[1]*n[1] + n[2]*n[2];
+        if sq.is_nan() || sq < 1.0e-12 {
+            errors.push(MeshError::BadNormal(i as u32));
+        }
+    }
+
+    errors
+}
+
+fn face_area(mesh: &Mesh, face: usize) -> f32 {
+    0.5 * mesh.face_normal(face).norm()
+}
+
+/// Removes triangles which use out of range indices, repeat a vertex or
+/// have no area.
+pub fn remove_degenerates(mesh: &Mesh) -> Mesh {
+    let count   = mesh.vertices.len() as u32;
+    let mut out = Mesh::from_parts(mesh.vertices.clone(), vec![]);
+
+    for face in 0..mesh.triangle_count() {
+        let tri = &mesh.indices[face*3..face*3 + 3];
+        if tri.iter().any(|&i| i >= count) ||
+           tri[0] == tri[1] || tri[1] == tri[2] || tri[2] == tri[0] ||
+           face_area(mesh, face) < MIN_AREA {
+            continue;
+        }
+        out.push_triangle(tri[0], tri[1], tri[2]);
+    }
+    out
+}
+
+/// Flips triangles so that neighbours traverse their shared edges in
+/// opposite directions. Closed pieces are then turned so that they wind
+/// counter-clockwise seen from outside. Non-manifold edges are not used to
+/// propagate the winding.
+pub fn unify_winding(mesh: &Mesh) -> Mesh {
+    let faces = mesh.triangle_count();
+    let mut tris: Vec<[u32; 3]> = mesh.indices.chunks(3)
+        .map(|t| [t[0], t[1], t[2]])
+        .collect();
+
+    let mut edge_faces: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+    for (f, tri) in tris.iter().enumerate() {
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            let key = if a < b { (a, b) } else { (b, a) };
+            edge_faces.entry(key).or_insert(vec![]).push(f);
+        }
+    }
+
+    let has_edge = |tri: &[u32; 3], a: u32, b: u32| {
+        (0..3).any(|k| tri[k] == a && tri[(k + 1) % 3] == b)
+    };
+
+    let mut visited = vec![false; faces];
+    for seed in 0..faces {
+        if visited[seed] {
+            continue;
+        }
+
+        let mut component = vec![seed];
+        let mut stack     = vec![seed];
+        let mut closed    = true;
+        visited[seed] = true;
+
+        while let Some(f) = stack.pop() {
+            for k in 0..3 {
+                let (a, b) = (tris[f][k], tris[f][(k + 1) % 3]);
+                let key  = if a < b { (a, b) } else { (b, a) };
+                let uses = &edge_faces[&key];
+
+                if uses.len() == 1 {
+                    closed = false;
+                }
+                if uses.len() != 2 {
+                    continue;
+                }
+
+                let g = if uses[0] == f { uses[1] } else { uses[0] };
+                if visited[g] {
+                    continue;
+                }
+                if has_edge(&tris[g], a, b) {
+                    tris[g].swap(1, 2);
+                }
+                visited[g] = true;
+                component.push(g);
+                stack.push(g);
+            }
+        }
+
+        if closed {
+            // signed volume of the closed piece, negative when inside out
+            let mut volume = 0.0;
+            for &f in &component {
+                let p = |k: usize| mesh.position(tris[f][k]);
+                let (a, b, c) = (p(0), p(1), p(2));
+                volume += a.x * (b.y*c.z - b.z*c.y) -
+                          a.y * (b.x*c.z - b.z*c.x) +
+                          a.z * (b.x*c.y - b.y*c.x);
+            }
+            if volume < 0.0 {
+                for &f in &component {
+                    tris[f].swap(1, 2);
+                }
+            }
+        }
+    }
+
+    let indices = tris.iter().flat_map(|t| t.iter().cloned()).collect();
+    Mesh::from_parts(mesh.vertices.clone(), indices)
+}
+
+/// Welds vertices closer than tolerance, removes degenerate triangles and
+/// unifies the winding.
+pub fn repair(mesh: &Mesh, tolerance: f32) -> Mesh {
+    unify_winding(&remove_degenerates(&mesh.weld(tolerance)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Grid, IsoSphere, Vertex};
+    use ::grid::Triangulation;
+    use ::bezier::BezierSurface;
+    use ::teapot::Teapot;
+    use ::primitives::{Cuboid, UvSphere, Cylinder, Torus, Capsule, Disk};
+    use ::subdivision::{QuadMesh, loop_subdivide, catmull_clark};
+    use ::terrain::{TerrainMeshCenter, TerrainRing, TerrainTrim};
+    use ::nalgebra::Vec3;
+
+    fn assert_valid(name: &str, mesh: &Mesh) {
+        let errors = validate(mesh);
+        assert!(errors.is_empty(), "{} is invalid: {:?}", name, errors);
+    }
+
+    #[test]
+    fn grids_are_valid() {
+        let mut grid = Grid::new(2.0, 2.0, 9, 5).to_mesh();
+        grid.compute_normals();
+        assert_valid("grid", &grid);
+
+        let mut diamond = Grid::new(2.0, 2.0, 9, 5)
+            .with_triangulation(Triangulation::Diamond)
+            .to_mesh();
+        diamond.compute_normals();
+        assert_valid("diamond grid", &diamond);
+
+        let hills = Grid::new(2.0, 2.0, 9, 9)
+            .with_height(|x, z| (2.0 * x).sin() * z.cos())
+            .to_mesh();
+        assert_valid("grid with height", &hills);
+
+        // the skirt walls are vertical, so their normals come from the faces
+        let mut skirted = Grid::new(2.0, 2.0, 9, 5).with_skirt(0.5).to_mesh();
+        skirted.compute_normals();
+        assert_valid("grid with skirt", &skirted);
+    }
+
+    #[test]
+    fn primitives_are_valid() {
+        // seams and the cone apex are split for their attributes, so the
+        // shapes are only connected once welded
+        let welded = |mesh: &Mesh| mesh.weld(1.0e-4);
+        assert_valid("cuboid",     &welded(Cuboid::new(1.0, 2.0, 3.0).mesh()));
+        assert_valid("uv sphere",  &welded(UvSphere::new(1.0, 32, 16).mesh()));
+        assert_valid("cylinder",   &welded(Cylinder::new(1.0, 1.0, 2.0, 32).mesh()));
+        assert_valid("cone",       &welded(Cylinder::cone(1.0, 2.0, 32).mesh()));
+        assert_valid("torus",      &welded(Torus::new(2.0, 0.5, 32, 16).mesh()));
+        assert_valid("capsule",    &welded(Capsule::new(0.5, 1.0, 16, 8).mesh()));
+        assert_valid("disk",       &welded(Disk::new(1.0, 24).mesh()));
+        assert_valid("iso sphere", &IsoSphere::new(2).to_mesh());
+    }
+
+    #[test]
+    fn bezier_surfaces_are_valid() {
+        // the corner of the handle touches the body in Newell's data, the
+        // rest of the teapot is a manifold
+        let teapot = Teapot::new(8).mesh().weld(1.0e-4);
+        let errors = validate(&teapot);
+        assert_eq!(errors.len(), 1, "teapot is invalid: {:?}", errors);
+        match errors[0] {
+            MeshError::NonManifoldVertex(v) => {
+                assert_eq!(teapot.vertices[v as usize].position, [-2.0, 0.9, 0.0]);
+            },
+            ref error => panic!("teapot is invalid: {:?}", error)
+        }
+
+        let lid = BezierSurface::new(Teapot::patches()[20..24].to_vec(), 8);
+        assert_valid("teapot lid", &lid.mesh().weld(1.0e-4));
+    }
+
+    #[test]
+    fn subdivided_meshes_are_valid() {
+        let cube = Cuboid::cube(2.0).mesh().weld(1.0e-4);
+        assert_valid("loop", &loop_subdivide(&cube, 2));
+
+        let positions = (0..8).map(|i| Vec3::new(
+            (i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32
+        )).collect();
+        let quads = vec![
+            [0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4],
+            [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]
+        ];
+        let quads = catmull_clark(&QuadMesh::new(positions, quads), 2);
+        let mut mesh = quads.to_mesh();
+        mesh.compute_normals();
+        assert_valid("catmull clark", &mesh);
+    }
+
+    #[test]
+    fn terrain_blocks_are_valid() {
+        let with_normals = |mut mesh: Mesh| { mesh.compute_normals(); mesh };
+        assert_valid("terrain center", &with_normals(TerrainMeshCenter.mesh()));
+        assert_valid("terrain ring",   &with_normals(TerrainRing.mesh()));
+        for index in 0..4 {
+            let trim = TerrainTrim::from_index(index).mesh();
+            assert_valid(&format!("terrain trim {}", index), &with_normals(trim));
+        }
+    }
+
+    #[test]
+    fn tiny_triangles_are_degenerate_by_area() {
+        let mut mesh = Mesh::new();
+        for &p in &[[0.0, 0.0, 0.0], [1.0e-4, 0.0, 0.0], [0.0, 1.0e-4, 0.0]] {
+            mesh.push_vertex(Vertex::from_position(p[0], p[1], p[2]));
+        }
+        mesh.push_triangle(0, 1, 2);
+        mesh.compute_normals();
+
+        // an area of 5e-9, below the threshold even though the edges are not
+        assert_eq!(validate(&mesh), vec![MeshError::DegenerateFace(0)]);
+        assert_eq!(remove_degenerates(&mesh).triangle_count(), 0);
+    }
+
+    fn mesh_from(positions: &[[f32; 3]], triangles: &[[u32; 3]]) -> Mesh {
+        let mut mesh = Mesh::new();
+        for p in positions {
+            mesh.push_vertex(Vertex::from_position(p[0], p[1], p[2]));
+        }
+        for t in triangles {
+            mesh.push_triangle(t[0], t[1], t[2]);
+        }
+        mesh.compute_normals();
+        mesh
+    }
+
+    fn square() -> Vec<[f32; 3]> {
+        vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0], [0.0, 0.0, 1.0],
+             [0.5, 1.0, 0.5], [0.5, -1.0, 0.5]]
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let mut mesh = mesh_from(&square(), &[[0, 2, 1]]);
+        mesh.indices.extend([0, 2, 9].iter().cloned());
+        assert!(validate(&mesh).contains(&MeshError::IndexOutOfRange(1)));
+
+        let mesh = mesh_from(&square(), &[[0, 2, 1], [0, 0, 3]]);
+        assert!(validate(&mesh).contains(&MeshError::DegenerateFace(1)));
+
+        let mesh = mesh_from(&square(), &[[0, 2, 1], [0, 2, 3]]);
+        assert!(validate(&mesh).contains(&MeshError::InconsistentWinding(0, 2)));
+
+        let mesh = mesh_from(&square(), &[[0, 2, 1], [2, 0, 3], [0, 2, 4]]);
+        assert!(validate(&mesh).contains(&MeshError::NonManifoldEdge(0, 2)));
+
+        let mesh = mesh_from(&square(), &[[0, 2, 1], [0, 3, 4]]);
+        assert!(validate(&mesh).contains(&MeshError::NonManifoldVertex(0)));
+
+        // vertex 5 is not used by any triangle, so it gets no normal
+        let mesh = mesh_from(&square(), &[[0, 2, 1], [0, 3, 2]]);
+        assert_eq!(validate(&mesh), vec![MeshError::BadNormal(4), MeshError::BadNormal(5)]);
+    }
+
+    /// A welded cube with its first triangle wound the wrong way.
+    fn cube_with_flipped_face() -> Mesh {
+        let mut cube = Cuboid::cube(2.0).mesh().weld(1.0e-4);
+        cube.indices.swap(1, 2);
+        cube
+    }
+
+    #[test]
+    fn unify_winding_fixes_a_flipped_face() {
+        let cube = cube_with_flipped_face();
+        assert!(validate(&cube).iter().any(|e| match *e {
+            MeshError::InconsistentWinding(..) => true,
+            _ => false
+        }));
+        assert_valid("unified cube", &unify_winding(&cube));
+
+        // a closed mesh turned inside out is turned back
+        let mut inside_out = Cuboid::cube(2.0).mesh().weld(1.0e-4);
+        for tri in inside_out.indices.chunks_mut(3) {
+            tri.swap(1, 2);
+        }
+        let unified = unify_winding(&inside_out);
+        assert_eq!(unified.indices, Cuboid::cube(2.0).mesh().weld(1.0e-4).indices);
+    }
+
+    #[test]
+    fn repair_welds_and_fixes_a_flipped_face() {
+        // unwelded, so every face of the cube starts out disconnected
+        let mut cube = Cuboid::cube(2.0).mesh().clone();
+        cube.indices.swap(1, 2);
+        cube.push_triangle(0, 0, 1);
+
+        let repaired = repair(&cube, 1.0e-4);
+        assert_eq!(repaired.triangle_count(), 12);
+        assert_valid("repaired cube", &repaired);
+    }
+}
@@ -0,0 +1,574 @@
+use ::{RenderableObj, Vertex, Grid, Heightmap};
+use ::lighting::{BLINN_PHONG, model_space_lighting};
+use ::nalgebra::{Vec3, Mat4, Iso3, Norm, to_homogeneous, Transformation};
+use ::glium::{Program, Display, DrawParameters, DepthTest, Frame, Surface, PolygonMode, VertexBuffer};
+use ::glium::index::{NoIndices, PrimitiveType};
+use ::glium::texture::{Texture2d, UncompressedFloatFormat};
+
+/// Largest distance, in samples, a wave may travel in one step. Beyond
+/// 1/sqrt(2) the explicit solver blows up.
+const MAX_COURANT : f32 = 0.7;
+
+/// What happens to waves reaching the edge of a wave field.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Boundary {
+    /// The edge is a wall and waves bounce back.
+    Reflect,
+    /// The edge is open water and waves leave, with little reflection.
+    Absorb
+}
+
+/// Water height field solving the two dimensional wave equation on the CPU
+/// with an explicit finite difference scheme, one unit between samples.
+/// Objects stir it up through disturb.
+#[derive(Clone)]
+pub struct WaveField {
+    /// Distance, in samples, waves travel per unit of time.
+    pub speed    : f32,
+    /// Fraction of the motion lost per unit of time.
+    pub damping  : f32,
+    pub boundary : Boundary,
+    width        : usize,
+    depth        : usize,
+    current      : Vec<f32>,
+    previous     : Vec<f32>
+}
+
+impl WaveField {
+    pub fn new(width: usize, depth: usize) -> WaveField {
+        WaveField {
+            speed    : 20.0,
+            damping  : 0.5,
+            boundary : Boundary::Reflect,
+            width    : width,
+            depth    : depth,
+            current  : vec![0.0; width * depth],
+            previous : vec![0.0; width * depth]
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn get(&self, x: usize, z: usize) -> f32 {
+        self.current[z * self.width + x]
+    }
+
+    /// Heights of the samples, row by row along z.
+    pub fn heights(&self) -> &[f32] {
+        &self.current
+    }
+
+    pub fn to_heightmap(&self) -> Heightmap {
+        Heightmap::from_fn(self.width, self.depth, |x, z| self.get(x, z))
+    }
+
+    /// Flattens the water.
+    pub fn clear(&mut self) {
+        for i in 0..self.current.len() {
+            self.current[i]  = 0.0;
+            self.previous[i] = 0.0;
+        }
+    }
+
+    /// Raises the water within radius samples of sample position (x, z) by a
+    /// smooth bump, strength high at its center. Negative strengths push it
+    /// down.
+    pub fn disturb(&mut self, x: f32, z: f32, radius: f32, strength: f32) {
+        let reach = radius.ceil() as i64;
+        let (cx, cz) = (x.round() as i64, z.round() as i64);
+
+        for sz in cz - reach..cz + reach + 1 {
+            for sx in cx - reach..cx + reach + 1 {
+                if sx < 0 || sz < 0 || sx >= self.width as i64 || sz >= self.depth as i64 {
+                    continue;
+                }
+                let distance = ((sx as f32 - x).powi(2) + (sz as f32 - z).powi(2)).sqrt();
+                if distance < radius {
+                    let bump = 0.5 + 0.5 * (::std::f32::consts::PI * distance / radius).cos();
+                    self.current[sz as usize * self.width + sx as usize] += strength * bump;
+                }
+            }
+        }
+    }
+
+    /// Advances the water by dt.
+    pub fn step(&mut self, dt: f32) {
+        let courant = (self.speed * dt).min(MAX_COURANT);
+        let keep    = (1.0 - self.damping * dt).max(0.0);
+        // Mur's first order absorbing boundary
+        let mur     = (courant - 1.0) / (courant + 1.0);
+
+        let mut next = vec![0.0; self.current.len()];
+        for z in 0..self.depth {
+            for x in 0..self.width {
+                let mut height = self.interior(x, z, courant * courant, keep);
+
+                if self.boundary == Boundary::Absorb {
+                    if let Some((ix, iz)) = self.inward(x, z) {
+                        let inner = self.interior(ix, iz, courant * courant, keep);
+                        height = self.get(ix, iz) + mur * (inner - self.get(x, z));
+                    }
+                }
+                next[z * self.width + x] = height;
+            }
+        }
+
+        ::std::mem::swap(&mut self.previous, &mut self.current);
+        self.current = next;
+    }
+
+    /// Next height of sample (x, z) from its neighbours, clamping at the
+    /// edges so waves reflect off them.
+    fn interior(&self, x: usize, z: usize, courant2: f32, keep: f32) -> f32 {
+        let clamp = |v: i64, size: usize| v.max(0).min(size as i64 - 1) as usize;
+        let at    = |sx: i64, sz: i64| self.get(clamp(sx, self.width), clamp(sz, self.depth));
+        let (ix, iz) = (x as i64, z as i64);
+
+        let height    = self.get(x, z);
+        let laplacian = at(ix - 1, iz) + at(ix + 1, iz) + at(ix, iz - 1) + at(ix, iz + 1) -
+            4.0 * height;
+        height + (height - self.previous[z * self.width + x]) * keep + courant2 * laplacian
+    }
+
+    /// The neighbour one sample into the field from an edge sample, or None
+    /// away from the edges.
+    fn inward(&self, x: usize, z: usize) -> Option<(usize, usize)> {
+        if self.width < 3 || self.depth < 3 {
+            return None;
+        }
+        if x == 0 {
+            Some((1, z))
+        } else if x == self.width - 1 {
+            Some((x - 1, z))
+        } else if z == 0 {
+            Some((x, 1))
+        } else if z == self.depth - 1 {
+            Some((x, z - 1))
+        } else {
+            None
+        }
+    }
+
+    /// A grid width x depth in size, see Grid::new, with a vertex for every
+    /// sample lifted to its height, to be rebuilt after each step.
+    pub fn to_grid(&self, depth: f32, width: f32) -> Grid {
+        let heights = self.to_heightmap();
+        let (max_x, max_z) = ((self.width - 1) as f32, (self.depth - 1) as f32);
+
+        Grid::new(depth, width, self.width as u32, self.depth as u32).with_height(move |x, z| {
+            heights.sample((x / width + 1.0) * 0.5 * max_x, (z / depth + 1.0) * 0.5 * max_z)
+        })
+    }
+}
+
+/// The same solver as WaveField run on the GPU, ping-ponging between height
+/// textures. The field is addressed and disturbed in texels.
+pub struct GpuWaveField {
+    pub speed       : f32,
+    pub damping     : f32,
+    pub boundary    : Boundary,
+    step_program    : Program,
+    disturb_program : Program,
+    fs_quad         : VertexBuffer<Vertex>,
+    previous        : Texture2d,
+    current         : Texture2d,
+    next            : Texture2d
+}
+
+impl GpuWaveField {
+    pub fn new(display: &Display, width: u32, depth: u32) -> GpuWaveField {
+        let empty = || {
+            let texture = Texture2d::empty_with_format(
+                display, UncompressedFloatFormat::F32, false, width, depth
+            ).unwrap();
+            texture.as_surface().clear_color(0.0, 0.0, 0.0, 0.0);
+            texture
+        };
+
+        GpuWaveField {
+            speed           : 20.0,
+            damping         : 0.5,
+            boundary        : Boundary::Reflect,
+            step_program    : GpuWaveField::create_step_program(display),
+            disturb_program : GpuWaveField::create_disturb_program(display),
+            fs_quad         : VertexBuffer::new(display, vec![
+                Vertex::from_position(-1.0, -1.0, 0.0),
+                Vertex::from_position( 1.0, -1.0, 0.0),
+                Vertex::from_position(-1.0,  1.0, 0.0),
+                Vertex::from_position( 1.0,  1.0, 0.0)
+            ]),
+            previous        : empty(),
+            current         : empty(),
+            next            : empty()
+        }
+    }
+
+    /// The current heights, in the red channel.
+    pub fn heights(&self) -> &Texture2d {
+        &self.current
+    }
+
+    pub fn clear(&mut self) {
+        self.previous.as_surface().clear_color(0.0, 0.0, 0.0, 0.0);
+        self.current.as_surface().clear_color(0.0, 0.0, 0.0, 0.0);
+    }
+
+    /// Raises the water like WaveField::disturb.
+    pub fn disturb(&mut self, x: f32, z: f32, radius: f32, strength: f32) {
+        let uniforms = uniform!(
+            current  : &self.current,
+            center   : (x, z),
+            radius   : radius,
+            strength : strength
+        );
+        self.next.as_surface().draw(
+            &self.fs_quad, &NoIndices(PrimitiveType::TriangleStrip),
+            &self.disturb_program, &uniforms, &::std::default::Default::default()
+        ).unwrap();
+
+        ::std::mem::swap(&mut self.current, &mut self.next);
+    }
+
+    /// Advances the water by dt.
+    pub fn step(&mut self, dt: f32) {
+        let courant = (self.speed * dt).min(MAX_COURANT);
+        let uniforms = uniform!(
+            current  : &self.current,
+            previous : &self.previous,
+            courant2 : courant * courant,
+            keep     : (1.0 - self.damping * dt).max(0.0),
+            mur      : (courant - 1.0) / (courant + 1.0),
+            absorb   : (self.boundary == Boundary::Absorb) as i32
+        );
+        self.next.as_surface().draw(
+            &self.fs_quad, &NoIndices(PrimitiveType::TriangleStrip),
+            &self.step_program, &uniforms, &::std::default::Default::default()
+        ).unwrap();
+
+        // previous <- current <- next, reusing the old previous as next
+        ::std::mem::swap(&mut self.previous, &mut self.current);
+        ::std::mem::swap(&mut self.current, &mut self.next);
+    }
+
+    fn create_step_program(display: &Display) -> Program {
+        let vertex_shader_src = r#"
+            #version 330
+            in vec3 position;
+            void main() {
+                gl_Position = vec4(position, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = r#"
+            #version 330
+            out vec4 frag_color;
+
+            uniform sampler2D current;
+            uniform sampler2D previous;
+            uniform float courant2;
+            uniform float keep;
+            uniform float mur;
+            uniform int absorb;
+
+            float at(sampler2D field, ivec2 p) {
+                return texelFetch(field, clamp(p, ivec2(0), textureSize(field, 0) - 1), 0).r;
+            }
+
+            float interior(ivec2 p) {
+                float height    = at(current, p);
+                float laplacian = at(current, p + ivec2(-1, 0)) + at(current, p + ivec2(1, 0)) +
+                                  at(current, p + ivec2(0, -1)) + at(current, p + ivec2(0, 1)) -
+                                  4.0 * height;
+                return height + (height - at(previous, p)) * keep + courant2 * laplacian;
+            }
+
+            void main() {
+                ivec2 p    = ivec2(gl_FragCoord.xy);
+                ivec2 size = textureSize(current, 0);
+                float next = interior(p);
+
+                if (absorb == 1 && size.x >= 3 && size.y >= 3) {
+                    ivec2 inward = ivec2(0);
+                    if (p.x == 0)               { inward = ivec2(1, 0); }
+                    else if (p.x == size.x - 1) { inward = ivec2(-1, 0); }
+                    else if (p.y == 0)          { inward = ivec2(0, 1); }
+                    else if (p.y == size.y - 1) { inward = ivec2(0, -1); }
+
+                    if (inward != ivec2(0)) {
+                        ivec2 q = p + inward;
+                        next = at(current, q) + mur * (interior(q) - at(current, p));
+                    }
+                }
+                frag_color = vec4(next);
+            }
+        "#;
+
+        Program::from_source(
+            display, vertex_shader_src, fragment_shader_src, None
+        ).unwrap()
+    }
+
+    fn create_disturb_program(display: &Display) -> Program {
+        let vertex_shader_src = r#"
+            #version 330
+            in vec3 position;
+            void main() {
+                gl_Position = vec4(position, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = r#"
+            #version 330
+            out vec4 frag_color;
+
+            uniform sampler2D current;
+            uniform vec2 center;
+            uniform float radius;
+            uniform float strength;
+
+            const float PI = 3.14159;
+
+            void main() {
+                ivec2 p        = ivec2(gl_FragCoord.xy);
+                float height   = texelFetch(current, p, 0).r;
+                float distance = length(vec2(p) - center);
+                if (distance < radius) {
+                    height += strength * (0.5 + 0.5 * cos(PI * distance / radius));
+                }
+                frag_color = vec4(height);
+            }
+        "#;
+
+        Program::from_source(
+            display, vertex_shader_src, fragment_shader_src, None
+        ).unwrap()
+    }
+}
+
+/// Draws a Grid lifted by a height texture covering it, such as the
+/// heights of a GpuWaveField, lit with normals taken from the texture.
+pub struct WaveFieldRenderer {
+    pub program         : Program,
+    /// Direction towards the light, in world space.
+    pub light_direction : Vec3<f32>,
+    pub water_color     : Vec3<f32>,
+    pub specular_color  : Vec3<f32>,
+    pub shininess       : f32,
+    pub wire            : bool
+}
+
+impl WaveFieldRenderer {
+    pub fn new(display: &Display) -> WaveFieldRenderer {
+        WaveFieldRenderer {
+            program         : WaveFieldRenderer::create_shader_program(&display),
+            light_direction : Vec3::new(0.3, 0.8, 0.5).normalize(),
+            water_color     : Vec3::new(0.1, 0.35, 0.5),
+            specular_color  : Vec3::new(1.0, 1.0, 1.0),
+            shininess       : 64.0,
+            wire            : false
+        }
+    }
+
+    /// Draws obj, a grid of the given world width and depth, see Grid::new.
+    pub fn draw(
+        &self, frame: &mut Frame, obj: &RenderableObj, width: f32, depth: f32,
+        heights: &Texture2d, proj: &Mat4<f32>, view: &Iso3<f32>, model: &Iso3<f32>
+    ) {
+        let mv  = view.prepend_transformation(model);
+        let mvp = *proj * to_homogeneous(&mv);
+
+        let (eye, light) = model_space_lighting(&mv, model, &self.light_direction);
+
+        let params = DrawParameters {
+            depth_test   : DepthTest::IfLess,
+            depth_write  : true,
+            polygon_mode : if self.wire == true { PolygonMode::Line } else { PolygonMode::Fill },
+            .. ::std::default::Default::default()
+        };
+
+        let uniforms = uniform!(
+            MVP             : mvp,
+            heights         : heights,
+            grid_size       : (2.0 * width, 2.0 * depth),
+            eye_position    : eye,
+            light_direction : light,
+            water_color     : self.water_color,
+            specular_color  : self.specular_color,
+            shininess       : self.shininess
+        );
+
+        obj.draw(frame, &self.program, &uniforms, &params);
+    }
+
+    fn create_shader_program(display: &Display) -> Program {
+        let vertex_shader_src = r#"
+            #version 330
+            in vec3 position;
+            in vec2 tex_coord;
+            smooth out vec3 surface_position;
+            smooth out vec2 surface_uv;
+
+            uniform mat4 MVP;
+            uniform sampler2D heights;
+
+            void main() {
+                // the grid's corner vertices sit on the corner texel centers
+                vec2 size        = vec2(textureSize(heights, 0));
+                surface_uv       = (tex_coord * (size - 1.0) + 0.5) / size;
+                float height     = texture(heights, surface_uv).r;
+                surface_position = vec3(position.x, height, position.z);
+                gl_Position      = MVP * vec4(surface_position, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = [r#"
+            #version 330
+        "#, BLINN_PHONG, r#"
+            smooth in vec3 surface_position;
+            smooth in vec2 surface_uv;
+            out vec4 vFragColor;
+
+            uniform sampler2D heights;
+            uniform vec2 grid_size;
+            uniform vec3 eye_position;
+            uniform vec3 light_direction;
+            uniform vec3 water_color;
+            uniform vec3 specular_color;
+            uniform float shininess;
+
+            void main() {
+                // central differences one texel apart, scaled to world units
+                vec2 texel   = 1.0 / vec2(textureSize(heights, 0));
+                vec2 spacing = grid_size / (vec2(textureSize(heights, 0)) - 1.0);
+                float dx = texture(heights, surface_uv + vec2(texel.x, 0)).r -
+                           texture(heights, surface_uv - vec2(texel.x, 0)).r;
+                float dz = texture(heights, surface_uv + vec2(0, texel.y)).r -
+                           texture(heights, surface_uv - vec2(0, texel.y)).r;
+
+                vec3 N = normalize(vec3(-dx / (2.0 * spacing.x), 1.0, -dz / (2.0 * spacing.y)));
+                vec3 V = normalize(eye_position - surface_position);
+
+                vec2 light = blinn_phong(N, light_direction, V, shininess);
+                vec3 color = water_color * (0.3 + 0.7 * light.x) + light.y * specular_color;
+
+                vFragColor = vec4(color, 1.0);
+            }
+        "#].concat();
+
+        Program::from_source(
+            display, vertex_shader_src, &fragment_shader_src, None
+        ).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A still field of 64 x 64 samples with a bump in the middle.
+    fn splash(boundary: Boundary, damping: f32) -> WaveField {
+        let mut field = WaveField::new(64, 64);
+        field.boundary = boundary;
+        field.damping  = damping;
+        field.disturb(32.0, 32.0, 4.0, 1.0);
+        field.previous = field.current.clone();
+        field
+    }
+
+    /// Kinetic and potential energy of the field for steps of the given
+    /// courant number, up to a constant factor.
+    fn energy(field: &WaveField, courant: f32) -> f32 {
+        let mut total = 0.0;
+        for z in 0..field.depth {
+            for x in 0..field.width {
+                let i = z * field.width + x;
+                total += (field.current[i] - field.previous[i]).powi(2);
+                if x + 1 < field.width {
+                    total += courant * courant * (field.get(x + 1, z) - field.get(x, z)).powi(2);
+                }
+                if z + 1 < field.depth {
+                    total += courant * courant * (field.get(x, z + 1) - field.get(x, z)).powi(2);
+                }
+            }
+        }
+        total
+    }
+
+    fn highest(field: &WaveField) -> f32 {
+        field.heights().iter().fold(0.0f32, |m, &h| m.max(h.abs()))
+    }
+
+    #[test]
+    fn large_steps_stay_stable() {
+        // a step of a whole unit of time would move waves 20 samples, the
+        // courant number is limited instead
+        let mut field = splash(Boundary::Reflect, 0.0);
+        for _ in 0..2000 {
+            field.step(1.0);
+        }
+        assert!(highest(&field) <= 1.0, "heights grew to {}", highest(&field));
+        assert!(field.heights().iter().all(|h| h.is_finite()));
+    }
+
+    #[test]
+    fn undamped_waves_keep_their_energy() {
+        let mut field = splash(Boundary::Reflect, 0.0);
+        let dt = 0.02;
+        for _ in 0..5 {
+            field.step(dt);
+        }
+
+        let start = energy(&field, field.speed * dt);
+        for _ in 0..500 {
+            field.step(dt);
+        }
+        let end = energy(&field, field.speed * dt);
+        assert!(end > 0.8 * start && end < 1.2 * start, "energy went from {} to {}", start, end);
+    }
+
+    #[test]
+    fn damping_drains_energy() {
+        let dt = 0.02;
+        let mut field = splash(Boundary::Reflect, 2.0);
+        field.step(dt);
+
+        let mut previous = energy(&field, field.speed * dt);
+        let start = previous;
+        for _ in 0..20 {
+            for _ in 0..10 {
+                field.step(dt);
+            }
+            let now = energy(&field, field.speed * dt);
+            assert!(now < previous, "energy rose from {} to {}", previous, now);
+            previous = now;
+        }
+        // e^(-2 damping t) of the energy is left after t = 4
+        assert!(previous < 0.01 * start, "energy only fell from {} to {}", start, previous);
+    }
+
+    #[test]
+    fn absorbing_edges_let_waves_out() {
+        let dt = 0.02;
+        let run = |boundary: Boundary| {
+            let mut field = splash(boundary, 0.0);
+            field.step(dt);
+            let start = energy(&field, field.speed * dt);
+
+            // long enough for the ring to reach every edge and corner
+            for _ in 0..300 {
+                field.step(dt);
+            }
+            energy(&field, field.speed * dt) / start
+        };
+
+        let (absorbed, reflected) = (run(Boundary::Absorb), run(Boundary::Reflect));
+        assert!(reflected > 0.8, "the walls lost {} of the energy", 1.0 - reflected);
+        assert!(absorbed < 0.05, "{} of the energy was left in open water", absorbed);
+    }
+}